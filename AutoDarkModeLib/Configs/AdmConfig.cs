@@ -142,6 +142,11 @@ public class Updater
     public string DownloadBaseUrl { get; set; }
     public string ZipCustomUrl { get; set; }
     public string HashCustomUrl { get; set; }
+    public bool DeferWhenBusy { get; set; }
+    public int DeferIdleMinutes { get; set; } = 5;
+    public List<string> DeferBlocklist { get; set; } = new();
+    public string DeferMaintenanceWindow { get; set; }
+    public int DeferMaxMinutes { get; set; } = 240;
 }
 
 public class WindowsThemeMode
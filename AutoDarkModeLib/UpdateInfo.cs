@@ -26,6 +26,13 @@ public class UpdateInfo
     public string PathFileArm { get; set; }
     public string PathChecksum { get; set; }
     public string PathChecksumArm { get; set; }
+    /// <summary>
+    /// Full package of a release whose <see cref="PathFile"/> is a delta package, the updater falls back to it if the delta cannot be applied
+    /// </summary>
+    public string PathFileFull { get; set; }
+    public string PathFileFullArm { get; set; }
+    public string PathChecksumFull { get; set; }
+    public string PathChecksumFullArm { get; set; }
     public bool AutoUpdateAvailable { get; set; }
     public string UpdaterVersion { get; set; }
     public string Message { get; set; }
@@ -64,6 +71,27 @@ public class UpdateInfo
         }
     }
 
+    /// <summary>
+    /// Gets the url of the full package to fall back to, or null if the release is not a delta package
+    /// </summary>
+    public string GetFullUpdateUrl(string url)
+    {
+        if (PathFileFullArm != null && RuntimeInformation.OSArchitecture == Architecture.Arm64)
+        {
+            return $"{url}{PathFileFullArm}";
+        }
+        return PathFileFull != null ? $"{url}{PathFileFull}" : null;
+    }
+
+    public string GetFullUpdateHashUrl(string url)
+    {
+        if (PathChecksumFullArm != null && RuntimeInformation.OSArchitecture == Architecture.Arm64)
+        {
+            return $"{url}{PathChecksumFullArm}";
+        }
+        return PathChecksumFull != null ? $"{url}{PathChecksumFull}" : null;
+    }
+
     public string GetUpdateInfoPage()
     {
         return ChangelogUrl;
@@ -19,6 +19,8 @@ using System.Collections.Concurrent;
 using System.Collections.Generic;
 using System.IO;
 using System.IO.Pipes;
+using System.Security.AccessControl;
+using System.Security.Principal;
 using System.Text;
 using System.Threading;
 using System.Threading.Tasks;
@@ -37,6 +39,12 @@ class AsyncPipeServer : IMessageServer
     private Task ConnectionHandler { get; set; }
     private readonly int streamTimeout;
     private readonly int abnormalWorkerCount = 2;
+    private readonly PipeSecurity pipeSecurity = CreatePipeSecurity();
+    private static readonly SecurityIdentifier owner = WindowsIdentity.GetCurrent().User;
+    /// <summary>
+    /// Commands administrators of other accounts may send, so an elevated updater can stop the service of every signed in user
+    /// </summary>
+    private static readonly HashSet<string> foreignCommands = new() { Command.Shutdown, Command.GetProtocolVersion };
     private bool disposed = false;
 
     public AsyncPipeServer(Service service, int numWorkers, int streamTimeout = 5000)
@@ -124,7 +132,7 @@ class AsyncPipeServer : IMessageServer
 
     private async void HandleClient()
     {
-        Tuple<string, string> result = await HandleRequest();
+        Tuple<string, string, uint?> result = await HandleRequest();
         // if no string was received, add the worker back to the pool
         if (result.Item1 == null)
         {
@@ -133,23 +141,28 @@ class AsyncPipeServer : IMessageServer
         }
         if (result.Item2 == "")
         {
-            HandleResponse(result.Item1, result.Item2);
+            HandleResponse(result.Item1, result.Item2, result.Item3);
         }
         else
         {
-            HandleResponse(result.Item1, $"_{result.Item2}");
+            HandleResponse(result.Item1, $"_{result.Item2}", result.Item3);
         }
     }
 
-    private async Task<Tuple<string, string>> HandleRequest()
+    /// <summary>
+    /// Reads a request, either as a <see cref="PipeFrame"/> or in the legacy format of two lines, the message and the output pipe address
+    /// </summary>
+    /// <returns>the message, the response channel and the message id if the request was framed</returns>
+    private async Task<Tuple<string, string, uint?>> HandleRequest()
     {
         bool highLoad = false;
         string msg = null;
         string responderPipeId = "";
+        uint? messageId = null;
         try
         {
             // this stream is the main requester loop and should only be cancelled from the outside if the server is stopped
-            using NamedPipeServerStream requestPipe = new(Address.PipePrefix + Address.PipeRequest, PipeDirection.InOut, WorkerCount, PipeTransmissionMode.Message, PipeOptions.Asynchronous);
+            using NamedPipeServerStream requestPipe = CreatePipe(Address.PipePrefix + Address.PipeRequest, WorkerCount);
             await requestPipe.WaitForConnectionAsync(WorkerTokenSource.Token);
             Interlocked.Decrement(ref availableWorkers);
             if (AvailableWorkers == 0)
@@ -174,15 +187,31 @@ class AsyncPipeServer : IMessageServer
             readTimeoutTokenSource.CancelAfter(streamTimeout);
             if (requestPipe.CanRead)
             {
-                using StreamReader sr = new(requestPipe);
+                byte[] prefix = new byte[PipeFrame.PrefixLength];
+                int prefixLength = await requestPipe.ReadAtLeastAsync(prefix, prefix.Length, throwOnEndOfStream: false, readTimeoutTokenSource.Token);
+                if (PipeFrame.IsFramePrefix(prefix, prefixLength))
+                {
+                    PipeFrame request = await PipeFrame.ReadAsync(requestPipe, prefix, readTimeoutTokenSource.Token);
+                    msg = request.Body;
+                    responderPipeId = request.ResponseChannel;
+                    messageId = request.MessageId;
+                }
+                else if (prefixLength > 0)
+                {
+                    using StreamReader sr = new(requestPipe);
+                    (msg, responderPipeId) = ReadLegacyRequest(Encoding.UTF8.GetString(prefix, 0, prefixLength), sr, requestPipe);
+                }
 
-                // read two lines, the message and the output pipe address
-                if (requestPipe.IsConnected) msg = sr.ReadLine();
-                if (requestPipe.IsConnected) responderPipeId = sr.ReadLine() ?? "";
                 if (msg == null)
                 {
                     Logger.Warn("no message received within request window");
-                    return new(null, responderPipeId);
+                    return new(null, responderPipeId, null);
+                }
+
+                if (!foreignCommands.Contains(msg) && !IsOwner(requestPipe))
+                {
+                    Logger.Warn("rejected message {0} from another account, only the owner may send it", msg);
+                    return new(null, responderPipeId, null);
                 }
 
                 if (highLoad)
@@ -199,46 +228,80 @@ class AsyncPipeServer : IMessageServer
             }
             else
             {
-                return new(null, responderPipeId);
+                return new(null, responderPipeId, null);
             }
         }
         catch (TaskCanceledException)
         {
-            return new(null, responderPipeId);
+            return new(null, responderPipeId, null);
         }
         catch (OperationCanceledException)
         {
-            return new(null, responderPipeId);
+            return new(null, responderPipeId, null);
+        }
+        catch (InvalidDataException ex)
+        {
+            Logger.Warn(ex, "received malformed request frame:");
+            return new(null, responderPipeId, null);
         }
         catch (IOException ex)
         {
             await Task.Delay(5000);
             Logger.Warn("request pipe was closed prematurely");
             Logger.Debug(ex, "exception:");
-            return new(null, responderPipeId);
+            return new(null, responderPipeId, null);
         }
         catch (UnauthorizedAccessException ex)
         {
             await Task.Delay(5000);
             Logger.Error(ex, $"system permission missing to create request pipe, attempting to reinstantiate worker:");
-            return new(null, responderPipeId);
+            return new(null, responderPipeId, null);
         }
         catch (Exception ex)
         {
             Logger.Error(ex, "error in npipe server request:");
-            return new(null, responderPipeId);
+            return new(null, responderPipeId, null);
         }
-        return new(msg, responderPipeId);
+        return new(msg, responderPipeId, messageId);
     }
 
-    private async void HandleResponse(string msg, string responderPipeId)
+    /// <summary>
+    /// Completes a legacy request whose first bytes were already consumed while checking for a frame header
+    /// </summary>
+    private static (string, string) ReadLegacyRequest(string prefix, StreamReader sr, NamedPipeServerStream requestPipe)
     {
+        string[] lines = prefix.Split('\n');
+        string msg;
+        string responderPipeId;
+        if (lines.Length == 1)
+        {
+            msg = prefix + (requestPipe.IsConnected ? sr.ReadLine() : "");
+            responderPipeId = requestPipe.IsConnected ? sr.ReadLine() ?? "" : "";
+        }
+        else
+        {
+            msg = lines[0];
+            responderPipeId = lines[1];
+            if (lines.Length == 2 && requestPipe.IsConnected) responderPipeId += sr.ReadLine() ?? "";
+        }
+        return (msg.TrimEnd('\r'), responderPipeId.TrimEnd('\r'));
+    }
+
+    private async void HandleResponse(string msg, string responderPipeId, uint? messageId)
+    {
+        if (msg == Command.Subscribe)
+        {
+            await HandleSubscription(responderPipeId);
+            TryAddWorker();
+            return;
+        }
+
         // for the response timeout, we don't want to wait forever, so it also adheres to streamTimeout
         using CancellationTokenSource connectTimeoutTokenSource = new();
         try
         {
             connectTimeoutTokenSource.CancelAfter(streamTimeout);
-            using NamedPipeServerStream responsePipe = new(Address.PipePrefix + Address.PipeResponse + $"{responderPipeId}", PipeDirection.InOut, 1, PipeTransmissionMode.Message, PipeOptions.Asynchronous);
+            using NamedPipeServerStream responsePipe = CreatePipe(Address.PipePrefix + Address.PipeResponse + $"{responderPipeId}", 1);
             await responsePipe.WaitForConnectionAsync(connectTimeoutTokenSource.Token);
 
             string response = "";
@@ -262,12 +325,21 @@ class AsyncPipeServer : IMessageServer
                 // if not cancel the write operation
                 using CancellationTokenSource writeTimeoutTokenSource = new();
                 writeTimeoutTokenSource.CancelAfter(streamTimeout);
-                StreamWriter sw = new(responsePipe)
-                { AutoFlush = true };
-                using (sw)
+                if (messageId is uint id)
                 {
-                    StringBuilder builder = new(response);
-                    await sw.WriteAsync(builder, writeTimeoutTokenSource.Token);
+                    // framed replies echo the message id so the client can match them to its request
+                    await responsePipe.WriteAsync(new PipeFrame(id, "", response).Encode(), writeTimeoutTokenSource.Token);
+                    await responsePipe.FlushAsync(writeTimeoutTokenSource.Token);
+                }
+                else
+                {
+                    StreamWriter sw = new(responsePipe)
+                    { AutoFlush = true };
+                    using (sw)
+                    {
+                        StringBuilder builder = new(response);
+                        await sw.WriteAsync(builder, writeTimeoutTokenSource.Token);
+                    }
                 }
             }
             catch (OperationCanceledException)
@@ -293,6 +365,74 @@ class AsyncPipeServer : IMessageServer
         TryAddWorker();
     }
 
+    /// <summary>
+    /// Opens the response pipe of a subscriber and hands it to the event publisher, which keeps it open
+    /// </summary>
+    private async Task HandleSubscription(string responderPipeId)
+    {
+        using CancellationTokenSource connectTimeoutTokenSource = new();
+        NamedPipeServerStream eventPipe = null;
+        try
+        {
+            connectTimeoutTokenSource.CancelAfter(streamTimeout);
+            eventPipe = CreatePipe(Address.PipePrefix + Address.PipeResponse + $"{responderPipeId}", 1);
+            await eventPipe.WaitForConnectionAsync(connectTimeoutTokenSource.Token);
+            EventPublisher.Subscribe(eventPipe);
+        }
+        catch (OperationCanceledException)
+        {
+            Logger.Warn("no client connected to event channel within response window");
+            eventPipe?.Dispose();
+        }
+        catch (Exception ex)
+        {
+            Logger.Error(ex, "error while opening event channel:");
+            eventPipe?.Dispose();
+        }
+    }
+
+    private NamedPipeServerStream CreatePipe(string name, int maxInstances)
+    {
+        return NamedPipeServerStreamAcl.Create(name, PipeDirection.InOut, maxInstances, PipeTransmissionMode.Message, PipeOptions.Asynchronous, 0, 0, pipeSecurity);
+    }
+
+    /// <summary>
+    /// Only the user running the service and administrators may open its pipes, other local users and remote clients are denied.<br/>
+    /// Administrators are limited to the commands in <see cref="foreignCommands"/>
+    /// </summary>
+    private static PipeSecurity CreatePipeSecurity()
+    {
+        PipeSecurity security = new();
+        security.SetOwner(owner);
+        security.AddAccessRule(new PipeAccessRule(owner, PipeAccessRights.FullControl, AccessControlType.Allow));
+        security.AddAccessRule(new PipeAccessRule(new SecurityIdentifier(WellKnownSidType.BuiltinAdministratorsSid, null), PipeAccessRights.ReadWrite, AccessControlType.Allow));
+        // connections over the network carry the network sid, denying it rejects remote clients regardless of the account
+        security.AddAccessRule(new PipeAccessRule(new SecurityIdentifier(WellKnownSidType.NetworkSid, null), PipeAccessRights.FullControl, AccessControlType.Deny));
+        return security;
+    }
+
+    /// <summary>
+    /// Checks whether the client of a pipe runs as the user of the service, clients connect with identification level
+    /// </summary>
+    private static bool IsOwner(NamedPipeServerStream pipe)
+    {
+        SecurityIdentifier client = null;
+        try
+        {
+            pipe.RunAsClient(() =>
+            {
+                using WindowsIdentity identity = WindowsIdentity.GetCurrent(TokenAccessLevels.Query);
+                client = identity.User;
+            });
+        }
+        catch (Exception ex)
+        {
+            Logger.Warn(ex, "could not determine pipe client account:");
+            return false;
+        }
+        return owner.Equals(client);
+    }
+
     private void TryAddWorker()
     {
         try
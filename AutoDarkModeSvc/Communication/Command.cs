@@ -93,6 +93,26 @@ public static class Command
     [Includable]
     public const string GetPostponeStatus = "--get-postpone-status";
 
+    /// <summary>
+    /// Enables or disables automatic theme switching, pass true or false as additional parameter<br/>
+    /// Returns an APIResponse with StatusCode.Ok and the new state as message, or StatusCode.Err if the parameter is invalid
+    /// </summary>
+    [Includable]
+    public const string SetAutoSwitch = "--set-auto-switch";
+
+    /// <summary>
+    /// Returns an APIResponse with StatusCode.Ok and a message with true or false to indicate whether automatic theme switching is enabled
+    /// </summary>
+    [Includable]
+    public const string GetAutoSwitch = "--get-auto-switch";
+
+    /// <summary>
+    /// Returns an APIResponse with StatusCode.Ok and the active configuration as json message.<br/>
+    /// Pass a top level key as additional parameter to only return its value, StatusCode.Err is returned if the key does not exist
+    /// </summary>
+    [Includable]
+    public const string GetConfig = "--get-config";
+
     /// <summary>
     /// Returns the internal theme that ADM is currently maintaining
     /// </summary>
@@ -179,6 +199,11 @@ public static class Command
     [Includable]
     public const string Alive = "--alive";
 
+    /// <summary>
+    /// Returns an APIResponse with StatusCode.Ok and the highest pipe protocol version the service understands, see <see cref="PipeFrame"/>
+    /// </summary>
+    public const string GetProtocolVersion = "--get-protocol-version";
+
     [Includable]
     public const string DetectMonitors = "--detect-monitors";
 
@@ -187,6 +212,12 @@ public static class Command
 
     public const string UpdateFailed = "--update-failed";
 
+    /// <summary>
+    /// Keeps the response pipe open and pushes service events to it, see <see cref="EventPublisher"/>.<br/>
+    /// The first line is an ApiResponse with StatusCode.Ok, only available over named pipes
+    /// </summary>
+    public const string Subscribe = "--subscribe";
+
     public const string GetLearnedThemeNames = "--get-learned-theme-names";
 
     [Includable]
@@ -0,0 +1,112 @@
+﻿#region copyright
+//  Copyright (C) 2022 Auto Dark Mode
+//
+//  This program is free software: you can redistribute it and/or modify
+//  it under the terms of the GNU General Public License as published by
+//  the Free Software Foundation, either version 3 of the License, or
+//  (at your option) any later version.
+//
+//  This program is distributed in the hope that it will be useful,
+//  but WITHOUT ANY WARRANTY; without even the implied warranty of
+//  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//  GNU General Public License for more details.
+//
+//  You should have received a copy of the GNU General Public License
+//  along with this program.  If not, see <https://www.gnu.org/licenses/>.
+#endregion
+using System;
+using System.Collections.Generic;
+using System.IO;
+using System.IO.Pipes;
+using System.Threading.Tasks;
+
+namespace AutoDarkModeSvc.Communication;
+
+/// <summary>
+/// Pushes service events to clients that subscribed with <see cref="Command.Subscribe"/>.<br/>
+/// Each subscriber keeps its response pipe open and receives one event per line, formatted as Name or Name=Payload
+/// </summary>
+static class EventPublisher
+{
+    private static readonly NLog.Logger Logger = NLog.LogManager.GetCurrentClassLogger();
+    private static readonly List<StreamWriter> subscribers = new();
+    private static readonly object subscriberLock = new();
+    // a subscriber that does not drain its pipe must not block the thread publishing the event
+    private const int writeTimeout = 1000;
+
+    public const string ThemeSwitched = "ThemeSwitched";
+    public const string UpdateAvailable = "UpdateAvailable";
+    public const string ServiceShutdown = "ServiceShutdown";
+    public const string ConfigChanged = "ConfigChanged";
+
+    /// <summary>
+    /// Acknowledges the subscription on a connected pipe and adds it to the subscriber list
+    /// </summary>
+    public static void Subscribe(NamedPipeServerStream pipe)
+    {
+        StreamWriter writer = new(pipe) { AutoFlush = true, NewLine = "\n" };
+        if (!TryWrite(writer, new ApiResponse() { StatusCode = StatusCode.Ok }.ToString()))
+        {
+            writer.Dispose();
+            return;
+        }
+        lock (subscriberLock)
+        {
+            subscribers.Add(writer);
+            Logger.Debug($"event subscriber added, active subscribers: {subscribers.Count}");
+        }
+    }
+
+    /// <summary>
+    /// Sends an event to all subscribers, subscribers that cannot be reached anymore are removed
+    /// </summary>
+    /// <param name="name">the event name</param>
+    /// <param name="payload">optional event data, must not contain line breaks</param>
+    public static void Publish(string name, string payload = null)
+    {
+        string line = payload == null ? name : $"{name}={payload}";
+        lock (subscriberLock)
+        {
+            if (subscribers.Count == 0) return;
+            subscribers.RemoveAll(writer =>
+            {
+                if (TryWrite(writer, line)) return false;
+                writer.Dispose();
+                return true;
+            });
+            Logger.Trace($"published event {name} to {subscribers.Count} subscriber(s)");
+        }
+    }
+
+    /// <summary>
+    /// Announces the service shutdown and closes all event channels
+    /// </summary>
+    public static void Shutdown()
+    {
+        Publish(ServiceShutdown);
+        lock (subscriberLock)
+        {
+            subscribers.ForEach(writer => writer.Dispose());
+            subscribers.Clear();
+        }
+    }
+
+    private static bool TryWrite(StreamWriter writer, string line)
+    {
+        try
+        {
+            Task write = writer.WriteLineAsync(line);
+            if (write.Wait(writeTimeout)) return true;
+            Logger.Debug("event subscriber did not consume data within write window, removing");
+        }
+        catch (AggregateException ex) when (ex.InnerException is IOException or ObjectDisposedException)
+        {
+            Logger.Debug("event subscriber disconnected");
+        }
+        catch (Exception ex) when (ex is IOException or ObjectDisposedException)
+        {
+            Logger.Debug("event subscriber disconnected");
+        }
+        return false;
+    }
+}
@@ -16,12 +16,15 @@
 #endregion
 using System;
 using System.Collections.Generic;
+using System.Reflection;
 using System.Threading;
 using System.Threading.Tasks;
 using AutoDarkModeLib;
 using AutoDarkModeSvc.Core;
 using AutoDarkModeSvc.Events;
 using AutoDarkModeSvc.Handlers;
+using YamlDotNet.Serialization;
+using YamlDotNet.Serialization.NamingConventions;
 
 namespace AutoDarkModeSvc.Communication;
 
@@ -265,6 +268,16 @@ static class MessageParser
                     break;
                 #endregion
 
+                #region GetProtocolVersion
+                case Command.GetProtocolVersion:
+                    SendResponse(new ApiResponse()
+                    {
+                        StatusCode = StatusCode.Ok,
+                        Message = PipeFrame.ProtocolVersion.ToString()
+                    }.ToString());
+                    break;
+                #endregion
+
                 #region Light
                 case Command.Light:
                     Logger.Info("signal received: set light theme");
@@ -375,6 +388,100 @@ static class MessageParser
                     break;
                 #endregion
 
+                #region SetAutoSwitch
+                case string s when s.StartsWith(Command.SetAutoSwitch):
+                    string autoSwitchString = message.Replace(Command.SetAutoSwitch, "").Trim();
+                    if (bool.TryParse(autoSwitchString, out bool autoSwitch))
+                    {
+                        Logger.Info($"signal received: set automatic theme switch to {autoSwitch}");
+                        try
+                        {
+                            if (builder.Config.AutoThemeSwitchingEnabled != autoSwitch)
+                            {
+                                state.SkipConfigFileReload = true;
+                                builder.Config.AutoThemeSwitchingEnabled = autoSwitch;
+                                builder.Save();
+                            }
+                            SendResponse(new ApiResponse()
+                            {
+                                StatusCode = StatusCode.Ok,
+                                Message = autoSwitch.ToString()
+                            }.ToString());
+                        }
+                        catch (Exception ex)
+                        {
+                            Logger.Error(ex, "error while saving automatic theme switch state:");
+                            SendResponse(new ApiResponse()
+                            {
+                                StatusCode = StatusCode.Err,
+                                Message = ex.Message,
+                                Details = ex.Source
+                            }.ToString());
+                        }
+                    }
+                    else
+                    {
+                        Logger.Info($"signal received: set automatic theme switch with invalid data ({autoSwitchString})");
+                        SendResponse(new ApiResponse()
+                        {
+                            StatusCode = StatusCode.Err,
+                            Message = "expected true or false"
+                        }.ToString());
+                    }
+                    break;
+                #endregion
+
+                #region GetAutoSwitch
+                case Command.GetAutoSwitch:
+                    SendResponse(new ApiResponse()
+                    {
+                        StatusCode = StatusCode.Ok,
+                        Message = builder.Config.AutoThemeSwitchingEnabled.ToString()
+                    }.ToString());
+                    break;
+                #endregion
+
+                #region GetConfig
+                case string s when s.StartsWith(Command.GetConfig):
+                    string configKey = message.Replace(Command.GetConfig, "").Trim();
+                    Logger.Info($"signal received: get config {configKey}");
+                    try
+                    {
+                        object configValue = builder.Config;
+                        if (configKey.Length > 0)
+                        {
+                            PropertyInfo property = builder.Config.GetType().GetProperty(configKey, BindingFlags.Public | BindingFlags.Instance | BindingFlags.IgnoreCase);
+                            if (property == null)
+                            {
+                                SendResponse(new ApiResponse()
+                                {
+                                    StatusCode = StatusCode.Err,
+                                    Message = $"key {configKey} not found in config"
+                                }.ToString());
+                                break;
+                            }
+                            configValue = property.GetValue(builder.Config);
+                        }
+                        ISerializer jsonSerializer = new SerializerBuilder().WithNamingConvention(PascalCaseNamingConvention.Instance).JsonCompatible().Build();
+                        SendResponse(new ApiResponse()
+                        {
+                            StatusCode = StatusCode.Ok,
+                            Message = jsonSerializer.Serialize(configValue).Trim()
+                        }.ToString());
+                    }
+                    catch (Exception ex)
+                    {
+                        Logger.Error(ex, "error while serializing config:");
+                        SendResponse(new ApiResponse()
+                        {
+                            StatusCode = StatusCode.Err,
+                            Message = ex.Message,
+                            Details = ex.Source
+                        }.ToString());
+                    }
+                    break;
+                #endregion
+
                 #region RequestedTheme
                 case Command.GetRequestedTheme:
                     Logger.Info("signal received: get requested theme");
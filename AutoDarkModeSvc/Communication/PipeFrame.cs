@@ -0,0 +1,118 @@
+﻿#region copyright
+//  Copyright (C) 2022 Auto Dark Mode
+//
+//  This program is free software: you can redistribute it and/or modify
+//  it under the terms of the GNU General Public License as published by
+//  the Free Software Foundation, either version 3 of the License, or
+//  (at your option) any later version.
+//
+//  This program is distributed in the hope that it will be useful,
+//  but WITHOUT ANY WARRANTY; without even the implied warranty of
+//  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//  GNU General Public License for more details.
+//
+//  You should have received a copy of the GNU General Public License
+//  along with this program.  If not, see <https://www.gnu.org/licenses/>.
+#endregion
+using System;
+using System.Buffers.Binary;
+using System.IO;
+using System.Text;
+using System.Threading;
+using System.Threading.Tasks;
+
+namespace AutoDarkModeSvc.Communication;
+
+/// <summary>
+/// A single protocol v2 message, the counterpart of adm-updater-rs/src/comms/framing.rs.<br/>
+/// On the wire a frame is a little endian uint header length, followed by the header and the UTF-8 body.
+/// The header holds the magic, protocol version, message id, body length and the response channel prefixed with its ushort length
+/// </summary>
+class PipeFrame
+{
+    public const ushort ProtocolVersion = 2;
+    private static readonly byte[] Magic = "ADMF"u8.ToArray();
+    // magic, version, message id, body length and response channel length
+    private const int FixedHeaderLength = 4 + 2 + 4 + 4 + 2;
+    private const int MaxHeaderLength = 4 * 1024;
+    private const int MaxBodyLength = 16 * 1024 * 1024;
+    public const int PrefixLength = 4;
+
+    public uint MessageId { get; }
+    public string ResponseChannel { get; }
+    public string Body { get; }
+
+    public PipeFrame(uint messageId, string responseChannel, string body)
+    {
+        MessageId = messageId;
+        ResponseChannel = responseChannel;
+        Body = body;
+    }
+
+    /// <summary>
+    /// Checks whether the first bytes of a request are a frame header length rather than legacy text.<br/>
+    /// Header lengths are capped well below 65536, so their upper two bytes are zero, which never occurs in text requests
+    /// </summary>
+    public static bool IsFramePrefix(byte[] prefix, int length)
+    {
+        return length == PrefixLength && prefix[2] == 0 && prefix[3] == 0;
+    }
+
+    /// <summary>
+    /// Reads the remainder of a frame whose header length has already been read into prefix
+    /// </summary>
+    /// <exception cref="InvalidDataException">the frame is malformed</exception>
+    public static async Task<PipeFrame> ReadAsync(Stream stream, byte[] prefix, CancellationToken token)
+    {
+        int headerLength = BinaryPrimitives.ReadInt32LittleEndian(prefix);
+        if (headerLength < FixedHeaderLength || headerLength > MaxHeaderLength)
+        {
+            throw new InvalidDataException($"invalid frame header length {headerLength}");
+        }
+        byte[] header = new byte[headerLength];
+        await stream.ReadExactlyAsync(header, token);
+        if (!header.AsSpan(0, 4).SequenceEqual(Magic))
+        {
+            throw new InvalidDataException("invalid frame magic");
+        }
+        ushort version = BinaryPrimitives.ReadUInt16LittleEndian(header.AsSpan(4));
+        uint messageId = BinaryPrimitives.ReadUInt32LittleEndian(header.AsSpan(6));
+        uint bodyLength = BinaryPrimitives.ReadUInt32LittleEndian(header.AsSpan(10));
+        ushort channelLength = BinaryPrimitives.ReadUInt16LittleEndian(header.AsSpan(14));
+        if (version < ProtocolVersion)
+        {
+            throw new InvalidDataException($"unsupported frame version {version}");
+        }
+        if (bodyLength > MaxBodyLength)
+        {
+            throw new InvalidDataException($"frame body length {bodyLength} exceeds limit");
+        }
+        if (FixedHeaderLength + channelLength > headerLength)
+        {
+            throw new InvalidDataException("response channel exceeds frame header");
+        }
+        // header bytes after the known fields are skipped, so newer clients can extend the header
+        string responseChannel = Encoding.UTF8.GetString(header, FixedHeaderLength, channelLength);
+        byte[] body = new byte[bodyLength];
+        await stream.ReadExactlyAsync(body, token);
+        return new(messageId, responseChannel, Encoding.UTF8.GetString(body));
+    }
+
+    public byte[] Encode()
+    {
+        byte[] channel = Encoding.UTF8.GetBytes(ResponseChannel);
+        byte[] body = Encoding.UTF8.GetBytes(Body);
+        int headerLength = FixedHeaderLength + channel.Length;
+        byte[] frame = new byte[PrefixLength + headerLength + body.Length];
+        Span<byte> span = frame;
+        BinaryPrimitives.WriteInt32LittleEndian(span, headerLength);
+        Magic.CopyTo(span[4..]);
+        BinaryPrimitives.WriteUInt16LittleEndian(span[8..], ProtocolVersion);
+        BinaryPrimitives.WriteUInt32LittleEndian(span[10..], MessageId);
+        BinaryPrimitives.WriteUInt32LittleEndian(span[14..], (uint)body.Length);
+        BinaryPrimitives.WriteUInt16LittleEndian(span[18..], (ushort)channel.Length);
+        channel.CopyTo(span[20..]);
+        body.CopyTo(span[(20 + channel.Length)..]);
+        return frame;
+    }
+}
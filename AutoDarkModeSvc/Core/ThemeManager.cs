@@ -21,6 +21,7 @@ using System.Runtime.CompilerServices;
 using System.Threading;
 using System.Threading.Tasks;
 using AutoDarkModeLib;
+using AutoDarkModeSvc.Communication;
 using AutoDarkModeSvc.Events;
 using AutoDarkModeSvc.Handlers;
 using AutoDarkModeSvc.Interfaces;
@@ -469,6 +470,7 @@ static class ThemeManager
             {
                 Logger.Info($"{Enum.GetName(typeof(Theme), newTheme).ToLower()} theme switch performed, source: {Enum.GetName(typeof(SwitchSource), e.Source)}");
             }
+            EventPublisher.Publish(EventPublisher.ThemeSwitched, Enum.GetName(typeof(Theme), newTheme));
         }
 
         if (!state.InitSyncSwitchPerformed)
@@ -19,7 +19,6 @@ using System.Collections.Generic;
 using System.Diagnostics;
 using System.Globalization;
 using System.IO;
-using System.IO.Compression;
 using System.Linq;
 using System.Net.Http;
 using System.Net.Http.Headers;
@@ -125,6 +124,7 @@ static class UpdateHandler
                 response.Message = $"Version: {currentVersion} {archString}";
                 response.Details = data;
                 UpstreamResponse = response;
+                EventPublisher.Publish(EventPublisher.UpdateAvailable, UpstreamVersion.Tag);
                 return response;
             }
             else if (RuntimeInformation.OSArchitecture == Architecture.Arm64
@@ -136,6 +136,7 @@ static class UpdateHandler
                 response.Message = $"Version: {currentVersion} {archString}";
                 response.Details = data;
                 UpstreamResponse = response;
+                EventPublisher.Publish(EventPublisher.UpdateAvailable, UpstreamVersion.Tag);
                 return response;
             }
             else
@@ -284,42 +285,28 @@ static class UpdateHandler
             Updating = false;
             return false;
         }
-        EndBlockingProcesses(out bool shellRestart, out bool appRestart);
-
-        /*
-        string futureUpdaterDir = Path.Combine(Helper.ExecutionDir, "UpdaterFuture");
-        string futureUpdaterExecutablePath = Path.Combine(futureUpdaterDir, Helper.UpdaterExecutableName);
-        try
+        bool shellRestart = false;
+        bool appRestart = false;
+        if (!builder.Config.Updater.DeferWhenBusy)
         {
-            if (Directory.Exists(futureUpdaterDir)) Directory.Delete(futureUpdaterDir, true);
-            Directory.Move(Helper.ExecutionDirUpdater, futureUpdaterDir);
+            EndBlockingProcesses(out shellRestart, out appRestart);
         }
-        catch (Exception ex)
-        {
-            Logger.Error(ex, "couldn't rename updater directory for downgarde:");
-            ToastHandler.RemoveUpdaterToast();
-            ToastHandler.InvokeFailedUpdateToast();
-            Updating = false;
-            return false;
-        }
-        */
 
         Logger.Info("downgrade preparation complete");
 
+        ProcessStartInfo startInfo = new();
         if (shellRestart || appRestart)
         {
-            ProcessStartInfo startInfo = new();
             startInfo.ArgumentList.Add("--notify");
             startInfo.ArgumentList.Add(shellRestart.ToString());
             startInfo.ArgumentList.Add(appRestart.ToString());
-            startInfo.FileName = Helper.ExecutionPathUpdater;
-            startInfo.WorkingDirectory = Helper.ExecutionDirUpdater;
-            Process.Start(startInfo);
-        }
-        else
-        {
-            Process.Start(Helper.ExecutionPathUpdater);
         }
+        // the updater refuses to install older versions unless told otherwise
+        startInfo.ArgumentList.Add("--allow-downgrade");
+        AddDeferralArguments(startInfo);
+        startInfo.FileName = Helper.ExecutionPathUpdater;
+        startInfo.WorkingDirectory = Helper.ExecutionDirUpdater;
+        Process.Start(startInfo);
         return false;
     }
 
@@ -369,6 +356,7 @@ static class UpdateHandler
             startInfo.ArgumentList.Add("--notify");
             startInfo.ArgumentList.Add(shellRestart.ToString());
             startInfo.ArgumentList.Add(appRestart.ToString());
+            AddDeferralArguments(startInfo);
             startInfo.FileName = Helper.ExecutionPathUpdater;
             startInfo.WorkingDirectory = Helper.ExecutionDirUpdater;
             Process.Start(startInfo);
@@ -376,6 +364,7 @@ static class UpdateHandler
         else
         {
             ProcessStartInfo startInfo = new();
+            AddDeferralArguments(startInfo);
             startInfo.FileName = Helper.ExecutionPathUpdater;
             startInfo.WorkingDirectory = Helper.ExecutionDirUpdater;
             Process.Start(startInfo);
@@ -383,36 +372,53 @@ static class UpdateHandler
     }
 
     /// <summary>
-    /// Prepares the update process by upgrading the updater
+    /// Passes the deferral settings to the updater. With deferral enabled the app and shell keep running,
+    /// the updater stops them once the user is no longer busy and restarts them afterwards
+    /// </summary>
+    private static void AddDeferralArguments(ProcessStartInfo startInfo)
+    {
+        var updater = builder.Config.Updater;
+        if (!updater.DeferWhenBusy)
+        {
+            return;
+        }
+        startInfo.ArgumentList.Add("--defer");
+        startInfo.ArgumentList.Add("--defer-idle-secs");
+        startInfo.ArgumentList.Add((Math.Max(updater.DeferIdleMinutes, 0) * 60).ToString(CultureInfo.InvariantCulture));
+        startInfo.ArgumentList.Add("--defer-max-minutes");
+        startInfo.ArgumentList.Add(Math.Max(updater.DeferMaxMinutes, 0).ToString(CultureInfo.InvariantCulture));
+        if (updater.DeferBlocklist.Count != 0)
+        {
+            startInfo.ArgumentList.Add("--defer-blocklist");
+            startInfo.ArgumentList.Add(string.Join(",", updater.DeferBlocklist));
+        }
+        if (!string.IsNullOrWhiteSpace(updater.DeferMaintenanceWindow))
+        {
+            startInfo.ArgumentList.Add("--defer-window");
+            startInfo.ArgumentList.Add(updater.DeferMaintenanceWindow);
+        }
+    }
+
+    /// <summary>
+    /// Prepares the update process by downloading the update archive, the updater replaces itself when it runs
     /// </summary>
     /// <returns>A bool tuple where the first item holds the value whether the update has been successfully prepared. <br></br>
     /// The second item is to determine whether the shell needs to be restarted <br/>
     /// The third item is to determine whether the app needs to be restarted</returns>
     private static (bool, bool, bool) PrepareUpdate(bool overrideSilent)
     {
-        bool success = GetPatchData(overrideSilent, out string unpackDirectory, false);
+        bool success = GetPatchData(overrideSilent, out _, false);
         if (!success)
         {
             return (false, false, false);
         }
-        EndBlockingProcesses(out bool shellRestart, out bool appRestart);
-        try
-        {
-            if (UpdateUpdater(unpackDirectory))
-            {
-                return (true, shellRestart, appRestart);
-            }
-            else
-            {
-                Logger.Error("updating failed, rollback successful");
-                return (false, false, false);
-            }
-        }
-        catch (Exception ex)
+        // a deferred update must not interrupt the user yet, the updater stops the app and shell when it starts
+        if (builder.Config.Updater.DeferWhenBusy)
         {
-            Logger.Error(ex, "updater failed, rollback failed, the updater is now missing and needs to be restored on next update attempt:");
-            return (false, false, false);
+            return (true, false, false);
         }
+        EndBlockingProcesses(out bool shellRestart, out bool appRestart);
+        return (true, shellRestart, appRestart);
     }
 
     private static bool GetPatchData(bool overrideSilent, out string unpackDirectory, bool downgrade)
@@ -429,6 +435,9 @@ static class UpdateHandler
             useCustomUrls = true;
         }
         string downloadPath = Path.Combine(Helper.UpdateDataDir, "update.zip");
+        string fullDownloadPath = Path.Combine(Helper.UpdateDataDir, "update.full.zip");
+        string expectedHash;
+        string fullExpectedHash = null;
         try
         {
             // show toast if UI components were open to inform the user that the program is being updated
@@ -449,12 +458,6 @@ static class UpdateHandler
             {
                 Logger.Warn("changelog page not found");
             }
-            Task<byte[]> hashDownloadTask = client.GetByteArrayAsync(UpstreamVersion.GetUpdateHashUrl(baseUrlHash, useCustomUrls));
-            hashDownloadTask.Wait();
-            byte[] buffer = hashDownloadTask.Result;
-
-            string expectedHash = Encoding.ASCII.GetString(buffer);
-
             if (!Directory.Exists(Helper.UpdateDataDir))
             {
                 Directory.CreateDirectory(Helper.UpdateDataDir);
@@ -469,27 +472,16 @@ static class UpdateHandler
             var progress = new Progress<(float, long, long)>();
             progress.ProgressChanged += DownloadProgress;
 
-            using (var file = new FileStream(downloadPath, FileMode.Create, FileAccess.Write, FileShare.None))
-            {
-                Task zipDownloadTask = client.DownloadDataAsync(UpstreamVersion.GetUpdateUrl(baseZipUrl, useCustomUrls), file, progress);
-                zipDownloadTask.Wait();
-            }
-
-            // calculate hash of downloaded file, abort if hash mismatches
-            using SHA256 sha256 = SHA256.Create();
-            using FileStream fileStream = File.OpenRead(downloadPath);
-            byte[] downloadHash = sha256.ComputeHash(fileStream);
-            StringBuilder downloadHashStringBuilder = new();
-            for (int i = 0; i < downloadHash.Length; i++)
-            {
-                downloadHashStringBuilder.Append(downloadHash[i].ToString("x2"));
-            }
-            string downloadHashString = downloadHashStringBuilder.ToString();
+            expectedHash = DownloadVerified(client, UpstreamVersion.GetUpdateUrl(baseZipUrl, useCustomUrls),
+                UpstreamVersion.GetUpdateHashUrl(baseUrlHash, useCustomUrls), downloadPath, progress);
 
-            StringComparer comparer = StringComparer.OrdinalIgnoreCase;
-            if (comparer.Compare(expectedHash, downloadHashString) != 0)
+            // delta releases also ship the full package, the updater falls back to it if the delta cannot be applied
+            string fullZipUrl = UpstreamVersion.GetFullUpdateUrl(baseZipUrl);
+            string fullHashUrl = UpstreamVersion.GetFullUpdateHashUrl(baseUrlHash);
+            if (!useCustomUrls && fullZipUrl != null && fullHashUrl != null)
             {
-                throw new ArgumentException($"hash mismatch, expected: {expectedHash}, got: {downloadHashString}");
+                Logger.Info("downloading full package for the delta update fallback");
+                fullExpectedHash = DownloadVerified(client, fullZipUrl, fullHashUrl, fullDownloadPath, null);
             }
         }
         catch (Exception ex)
@@ -500,13 +492,16 @@ static class UpdateHandler
 
         try
         {
-            // unzip download data if hash is valid
-            Directory.CreateDirectory(unpackDirectory);
-            ZipFile.ExtractToDirectory(downloadPath, unpackDirectory, true);
+            // the updater verifies and extracts the archive itself, including its own replacement
+            File.WriteAllText(downloadPath + ".sha256", expectedHash.Trim());
+            if (fullExpectedHash != null)
+            {
+                File.WriteAllText(fullDownloadPath + ".sha256", fullExpectedHash.Trim());
+            }
         }
         catch (Exception ex)
         {
-            Logger.Error(ex, "error while extracting patch:");
+            Logger.Error(ex, "error while writing update archive hash:");
             return false;
         }
 
@@ -514,6 +509,44 @@ static class UpdateHandler
         return true;
     }
 
+    /// <summary>
+    /// Downloads a file and compares it to the published hash
+    /// </summary>
+    /// <returns>the expected hash</returns>
+    /// <exception cref="ArgumentException">if the downloaded file does not match the hash</exception>
+    private static string DownloadVerified(HttpClient client, string zipUrl, string hashUrl, string downloadPath, IProgress<(float, long, long)> progress)
+    {
+        Task<byte[]> hashDownloadTask = client.GetByteArrayAsync(hashUrl);
+        hashDownloadTask.Wait();
+        byte[] buffer = hashDownloadTask.Result;
+
+        string expectedHash = Encoding.ASCII.GetString(buffer);
+
+        using (var file = new FileStream(downloadPath, FileMode.Create, FileAccess.Write, FileShare.None))
+        {
+            Task zipDownloadTask = client.DownloadDataAsync(zipUrl, file, progress);
+            zipDownloadTask.Wait();
+        }
+
+        // calculate hash of downloaded file, abort if hash mismatches
+        using SHA256 sha256 = SHA256.Create();
+        using FileStream fileStream = File.OpenRead(downloadPath);
+        byte[] downloadHash = sha256.ComputeHash(fileStream);
+        StringBuilder downloadHashStringBuilder = new();
+        for (int i = 0; i < downloadHash.Length; i++)
+        {
+            downloadHashStringBuilder.Append(downloadHash[i].ToString("x2"));
+        }
+        string downloadHashString = downloadHashStringBuilder.ToString();
+
+        StringComparer comparer = StringComparer.OrdinalIgnoreCase;
+        if (comparer.Compare(expectedHash, downloadHashString) != 0)
+        {
+            throw new ArgumentException($"hash mismatch, expected: {expectedHash}, got: {downloadHashString}");
+        }
+        return expectedHash;
+    }
+
     public static void EndBlockingProcesses(out bool shellRestart, out bool appRestart)
     {
         shellRestart = false;
@@ -601,56 +634,6 @@ static class UpdateHandler
         }
     }
 
-    private static bool UpdateUpdater(string unpackDirectory)
-    {
-        string tempDir = Path.Combine(Helper.ExecutionDir, "Temp");
-        if (Directory.Exists(tempDir))
-        {
-            Logger.Warn($"emp directory {tempDir} already exists, cleaning");
-            try
-            {
-                Directory.Delete(tempDir, true);
-            }
-            catch (Exception ex)
-            {
-                Logger.Error(ex, "could not clean up updater temp directory, aborting update");
-                return false;
-            }
-        }
-        try
-        {
-            Logger.Info("applying updater patch");
-            if (Directory.Exists(Helper.ExecutionDirUpdater))
-            {
-                Directory.Move(Helper.ExecutionDirUpdater, tempDir);
-            }
-            else
-            {
-                Logger.Warn("huh, the updater is missing. trying to restore updater");
-            }
-            string newUpdaterPath = Path.Combine(unpackDirectory, Helper.UpdaterDirName);
-            Directory.Move(newUpdaterPath, Helper.ExecutionDirUpdater);
-        }
-        catch (Exception ex)
-        {
-            Logger.Error(ex, "could not move updater files directory, rolling back");
-            Directory.Move(tempDir, Helper.ExecutionDirUpdater);
-            return false;
-        }
-
-        try
-        {
-            Directory.Delete(tempDir, true);
-        }
-        catch (Exception ex)
-        {
-            Logger.Warn(ex, "could not delete old updater files, manual investigation required");
-        }
-
-        Logger.Info("updater patched successfully");
-        return true;
-    }
-
     private static void DownloadProgress(object sender, (float, long, long) progress)
     {
         int percent = (int)progress.Item1;
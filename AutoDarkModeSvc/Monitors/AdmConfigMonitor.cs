@@ -19,6 +19,7 @@ using System.IO;
 using System.Threading.Tasks;
 using AutoDarkModeLib;
 using AutoDarkModeLib.Configs;
+using AutoDarkModeSvc.Communication;
 using AutoDarkModeSvc.Core;
 using AutoDarkModeSvc.Handlers;
 using AutoDarkModeSvc.Interfaces;
@@ -158,6 +159,7 @@ class AdmConfigMonitor
             builder.LoadScriptConfig();
             componentManager.UpdateScriptSettings();
             Logger.Debug("updated script config file");
+            EventPublisher.Publish(EventPublisher.ConfigChanged, Path.GetFileName(AdmConfigBuilder.ScriptConfigPath));
         }
         catch (Exception ex)
         {
@@ -189,6 +191,7 @@ class AdmConfigMonitor
             state.PostponeManager.UpdateSkipNextSwitchExpiry();
             if (internalUpdate) Logger.Debug("updated configuration internally");
             else Logger.Debug("updated configuration from file");
+            EventPublisher.Publish(EventPublisher.ConfigChanged, Path.GetFileName(AdmConfigBuilder.ConfigFilePath));
         }
         catch (Exception ex)
         {
@@ -274,6 +274,7 @@ class Service : Form
     private void Exit(object sender, EventArgs e)
     {
         Logger.Info("exiting service");
+        EventPublisher.Shutdown();
 
         state.PostponeManager.FlushPostponesToDisk();
 
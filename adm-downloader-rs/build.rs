@@ -1,6 +1,10 @@
 extern crate winres;
 
+#[path = "../build-support/license_notice.rs"]
+mod license_notice;
+
 fn main() {
+    license_notice::generate();
     if cfg!(target_os = "windows") {
       let mut res = winres::WindowsResource::new();
       res.set_icon("adm_new.ico");
@@ -17,6 +17,10 @@ const ERR_VERIFY: i32 = 13371;
 const ERR_INSTALL_SPAWN: i32 = 13372;
 const ERR_CLEANUP: i32 = 13373;
 
+// third party notice generated by build.rs from the dependencies built for the target.
+const LICENSES_TEXT: &str = include_str!(concat!(env!("OUT_DIR"), "/licenses.txt"));
+const LICENSES_HTML: &str = include_str!(concat!(env!("OUT_DIR"), "/licenses.html"));
+
 // IMAGE_FILE_MACHINE constants
 const IMAGE_FILE_MACHINE_ARM64: u16 = 0xAA64;
 
@@ -69,6 +73,12 @@ fn main() -> anyhow::Result<()> {
         print_updater_licenses();
         exit(0);
     }
+    // print the license notice to stdout, as plain text or with --html as html.
+    // usage: adm-downloader-rs --licenses [--html]
+    if args.iter().any(|a| a == "--info" || a == "--licenses") {
+        print_licenses(args.iter().any(|a| a == "--html"));
+        exit(0);
+    }
     // detect runtime architecture to pick the correct asset (ARM64 or x86)
     let asset_arch = detect_native_arch();
 
@@ -133,14 +143,20 @@ fn download_file(url: &str, dest: &PathBuf) -> anyhow::Result<()> {
 }
 
 /// print package name and version pairs from the embedded Cargo.lock.
-fn print_updater_licenses() {
-    // embed the prepared HTML at compile time and open it in the default browser.
-    const HTML: &str = include_str!("../license.html");
+fn print_licenses(html: bool) {
+    if html {
+        print!("{}", LICENSES_HTML);
+    } else {
+        println!("auto dark mode downloader, Copyright (c) 2025, Spiritreader, Auto Dark Mode - MIT License\n");
+        print!("{}", LICENSES_TEXT);
+    }
+}
 
-    // write to a deterministic temp filename so it can be opened.
+fn print_updater_licenses() {
+    // write the HTML notice generated by build.rs to a deterministic temp filename so it can be opened.
     let mut out = std::env::temp_dir();
     out.push("adm-updater-licenses.html");
-    if let Err(e) = std::fs::write(&out, HTML) {
+    if let Err(e) = std::fs::write(&out, LICENSES_HTML) {
         eprintln!("failed to write embedded license HTML to {:?}: {}", out, e);
         return;
     }
@@ -0,0 +1,406 @@
+use std::{env, process::ExitCode};
+
+use comms::{send_message_and_get_reply, ApiResponse, PipeError};
+use log::debug;
+use serde_json::{json, Value};
+
+#[allow(dead_code)]
+#[path = "../comms/mod.rs"]
+mod comms;
+
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+const DEFAULT_TIMEOUT: u32 = 5000;
+
+const EXIT_OK: u8 = 0;
+const EXIT_ERR: u8 = 1;
+const EXIT_TIMEOUT: u8 = 2;
+const EXIT_UNSUPPORTED: u8 = 3;
+const EXIT_DISABLED: u8 = 4;
+const EXIT_IN_PROGRESS: u8 = 5;
+const EXIT_UPDATE_AVAILABLE: u8 = 10;
+const EXIT_USAGE: u8 = 64;
+
+const USAGE: &str = "usage: admctl [--json] [--timeout <ms>] [--channel <user>] [--verbose] <command>
+
+commands:
+  switch                        re-evaluate the current theme and switch if necessary
+  theme <light|dark>            switch to the given theme until the next scheduled switch
+  force <light|dark|off>        force a theme permanently, or remove the force
+  auto-switch [on|off]          enable or disable automatic theme switching, prints the state without argument
+  pause [minutes]               skip the next switch, or delay switching by the given minutes
+  resume                        clear all pauses and delays
+  status                        show whether the service is running, the requested theme and pause state
+  config [key]                  print the service configuration, or the value of a single top level key
+  check-update                  check for updates without notifying
+  version                       print the admctl version";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Command {
+    Switch,
+    Theme(String),
+    Force(Option<String>),
+    AutoSwitch(Option<bool>),
+    Pause(Option<u32>),
+    Resume,
+    Status,
+    Config(Option<String>),
+    CheckUpdate,
+    Version,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Action {
+    Run(Options),
+    Help,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Options {
+    json: bool,
+    verbose: bool,
+    timeout: u32,
+    channel: String,
+    command: Command,
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let options = match parse_args(&args) {
+        Ok(Action::Run(o)) => o,
+        Ok(Action::Help) => {
+            println!("admctl controls auto dark mode through the service api\n\n{}", USAGE);
+            return ExitCode::from(EXIT_OK);
+        }
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, USAGE);
+            return ExitCode::from(EXIT_USAGE);
+        }
+    };
+    let level = if options.verbose {
+        log::LevelFilter::Debug
+    } else {
+        log::LevelFilter::Warn
+    };
+    if setup_logger_with_level(level).is_err() {
+        eprintln!("failed to setup logger");
+    }
+    debug!("admctl {} on channel {}", VERSION, options.channel);
+    ExitCode::from(run(&options))
+}
+
+fn parse_args(args: &[String]) -> Result<Action, String> {
+    let mut json = false;
+    let mut verbose = false;
+    let mut timeout = DEFAULT_TIMEOUT;
+    let mut channel = whoami::username();
+    let mut rest = args.iter();
+    let mut positional: Vec<&str> = Vec::new();
+    while let Some(arg) = rest.next() {
+        match arg.as_str() {
+            "--json" => json = true,
+            "--verbose" | "-v" => verbose = true,
+            "--timeout" => {
+                let value = rest.next().ok_or("--timeout requires a value in milliseconds")?;
+                timeout = value.parse().map_err(|_| format!("invalid timeout: {}", value))?;
+            }
+            "--channel" => channel = rest.next().ok_or("--channel requires a user name")?.clone(),
+            "--help" | "-h" => return Ok(Action::Help),
+            other => positional.push(other),
+        }
+    }
+
+    let (name, params) = positional.split_first().ok_or("no command given")?;
+    let command = match (*name, params) {
+        ("switch", []) => Command::Switch,
+        ("theme", [theme]) => Command::Theme(parse_theme(theme)?),
+        ("force", ["off"]) => Command::Force(None),
+        ("force", [theme]) => Command::Force(Some(parse_theme(theme)?)),
+        ("auto-switch", []) => Command::AutoSwitch(None),
+        ("auto-switch", ["on"]) => Command::AutoSwitch(Some(true)),
+        ("auto-switch", ["off"]) => Command::AutoSwitch(Some(false)),
+        ("pause", []) => Command::Pause(None),
+        ("pause", [minutes]) => Command::Pause(Some(minutes.parse().map_err(|_| format!("invalid minutes: {}", minutes))?)),
+        ("resume", []) => Command::Resume,
+        ("status", []) => Command::Status,
+        ("config", []) => Command::Config(None),
+        ("config", [key]) => Command::Config(Some(key.to_string())),
+        ("check-update", []) => Command::CheckUpdate,
+        ("version", []) => Command::Version,
+        (name, params) => {
+            return Err(format!("invalid command: {} {}", name, params.join(" "))
+                .trim_end()
+                .to_string())
+        }
+    };
+    Ok(Action::Run(Options {
+        json,
+        verbose,
+        timeout,
+        channel,
+        command,
+    }))
+}
+
+fn parse_theme(theme: &str) -> Result<String, String> {
+    match theme.to_lowercase().as_str() {
+        "light" => Ok("light".to_string()),
+        "dark" => Ok("dark".to_string()),
+        _ => Err(format!("invalid theme: {}, expected light or dark", theme)),
+    }
+}
+
+fn run(options: &Options) -> u8 {
+    match &options.command {
+        Command::Switch => api_command("--switch", options),
+        Command::Theme(theme) => api_command(&format!("--{}", theme), options),
+        Command::Force(Some(theme)) => api_command(&format!("--force-{}", theme), options),
+        Command::Force(None) => api_command("--no-force", options),
+        Command::Pause(None) => pause(options),
+        Command::Pause(Some(minutes)) => api_command(&format!("--delay-by {}", minutes), options),
+        Command::Resume => api_command("--clear-postpone-queue", options),
+        Command::CheckUpdate => api_command("--check-for-update", options),
+        Command::Status => status(options),
+        Command::AutoSwitch(enabled) => auto_switch(*enabled, options),
+        Command::Config(key) => config(key.as_deref(), options),
+        Command::Version => {
+            output(options.json, json!({ "version": VERSION }), VERSION);
+            EXIT_OK
+        }
+    }
+}
+
+fn api_command(message: &str, options: &Options) -> u8 {
+    match send_message_and_get_reply(message, options.timeout, &options.channel) {
+        Ok(response) => {
+            output(options.json, response_json(&response), &response_text(&response));
+            exit_code(&response.status_code)
+        }
+        Err(e) => pipe_failure(&e, options),
+    }
+}
+
+fn status(options: &Options) -> u8 {
+    let alive = match send_message_and_get_reply("--alive", options.timeout, &options.channel) {
+        Ok(response) => response,
+        Err(e) if e.is_timeout => {
+            output(options.json, json!({ "running": false }), "running: false");
+            return EXIT_TIMEOUT;
+        }
+        Err(e) => return pipe_failure(&e, options),
+    };
+    let theme = send_message_and_get_reply("--get-requested-theme", options.timeout, &options.channel)
+        .map(|r| r.message)
+        .unwrap_or_default();
+    let postponed = send_message_and_get_reply("--get-postpone-status", options.timeout, &options.channel)
+        .map(|r| r.message.eq_ignore_ascii_case("true"))
+        .unwrap_or_default();
+    let running = alive.status_code == "Ok";
+    output(
+        options.json,
+        json!({ "running": running, "theme": theme, "postponed": postponed }),
+        &format!("running: {}\ntheme: {}\npostponed: {}", running, theme, postponed),
+    );
+    exit_code(&alive.status_code)
+}
+
+/// Skips the next switch unless a pause is already active, so repeated calls do not toggle it back off
+fn pause(options: &Options) -> u8 {
+    match send_message_and_get_reply("--get-postpone-status", options.timeout, &options.channel) {
+        Ok(response) if response.message.eq_ignore_ascii_case("true") => {
+            output(
+                options.json,
+                json!({ "status": "Ok", "message": "already paused" }),
+                "already paused",
+            );
+            EXIT_OK
+        }
+        Ok(_) => api_command("--toggle-skip-next", options),
+        Err(e) => pipe_failure(&e, options),
+    }
+}
+
+/// Sets or reads the auto switch state through the service, which persists it to the config file
+fn auto_switch(enabled: Option<bool>, options: &Options) -> u8 {
+    let message = match enabled {
+        Some(enabled) => format!("--set-auto-switch {}", enabled),
+        None => "--get-auto-switch".to_string(),
+    };
+    let response = match send_message_and_get_reply(&message, options.timeout, &options.channel) {
+        Ok(response) => response,
+        Err(e) => return pipe_failure(&e, options),
+    };
+    if response.status_code != "Ok" {
+        output(options.json, response_json(&response), &response_text(&response));
+        return exit_code(&response.status_code);
+    }
+    let state = response.message.eq_ignore_ascii_case("true");
+    output(
+        options.json,
+        json!({ "autoSwitch": state }),
+        &format!("auto switch: {}", if state { "on" } else { "off" }),
+    );
+    EXIT_OK
+}
+
+/// Reads the configuration the service of the channel's user is running with
+fn config(key: Option<&str>, options: &Options) -> u8 {
+    let message = match key {
+        Some(key) => format!("--get-config {}", key),
+        None => "--get-config".to_string(),
+    };
+    let response = match send_message_and_get_reply(&message, options.timeout, &options.channel) {
+        Ok(response) => response,
+        Err(e) => return pipe_failure(&e, options),
+    };
+    if response.status_code != "Ok" {
+        output(options.json, response_json(&response), &response_text(&response));
+        return exit_code(&response.status_code);
+    }
+    let value = match parse_config_value(&response.message) {
+        Ok(value) => value,
+        Err(e) => return local_failure(&format!("invalid config returned by the service: {}", e), options),
+    };
+    let text = config_text(&value);
+    match key {
+        Some(key) => output(options.json, json!({ key: value }), &text),
+        None => output(options.json, value, &text),
+    }
+    EXIT_OK
+}
+
+fn parse_config_value(message: &str) -> Result<Value, serde_json::Error> {
+    serde_json::from_str(message)
+}
+
+/// Prints strings without quotes and everything else as indented json
+fn config_text(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Null => String::new(),
+        Value::Bool(_) | Value::Number(_) => value.to_string(),
+        _ => serde_json::to_string_pretty(value).unwrap_or_else(|_| value.to_string()),
+    }
+}
+
+/// Maps an api status code to the process exit code
+fn exit_code(status_code: &str) -> u8 {
+    match status_code {
+        "Ok" | "Available" | "Modified" | "AutostartTask" | "AutostartRegistryEntry" => EXIT_OK,
+        "Timeout" => EXIT_TIMEOUT,
+        "UnsupportedOperation" => EXIT_UNSUPPORTED,
+        "Disabled" | "No" | "NoLocAccess" => EXIT_DISABLED,
+        "InProgress" => EXIT_IN_PROGRESS,
+        "New" | "Downgrade" => EXIT_UPDATE_AVAILABLE,
+        _ => EXIT_ERR,
+    }
+}
+
+fn response_json(response: &ApiResponse) -> Value {
+    json!({
+        "status": response.status_code,
+        "message": response.message,
+        "details": response.details,
+    })
+}
+
+fn response_text(response: &ApiResponse) -> String {
+    [&response.status_code, &response.message, &response.details]
+        .iter()
+        .filter(|part| !part.is_empty())
+        .map(|part| part.as_str())
+        .collect::<Vec<&str>>()
+        .join("\n")
+}
+
+fn pipe_failure(e: &PipeError, options: &Options) -> u8 {
+    let status = if e.is_timeout { "Timeout" } else { "Err" };
+    output(
+        options.json,
+        json!({ "status": status, "message": e.message }),
+        &format!("{}: {}", status, e.message),
+    );
+    exit_code(status)
+}
+
+fn local_failure(message: &str, options: &Options) -> u8 {
+    output(options.json, json!({ "status": "Err", "message": message }), message);
+    EXIT_ERR
+}
+
+fn output(json: bool, value: Value, text: &str) {
+    if json {
+        println!("{}", value);
+    } else {
+        println!("{}", text);
+    }
+}
+
+/// Logs to stderr only, so stdout stays machine readable
+fn setup_logger_with_level(level: log::LevelFilter) -> Result<(), fern::InitError> {
+    fern::Dispatch::new()
+        .format(|out, message, record| out.finish(format_args!("[{}] {}", record.level(), message)))
+        .level(level)
+        .chain(std::io::stderr())
+        .apply()?;
+    Ok(())
+}
+
+/// Logger entry point expected by the shared comms tests
+#[cfg(test)]
+fn setup_logger() -> Result<(), fern::InitError> {
+    setup_logger_with_level(log::LevelFilter::Debug)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(String::from).collect()
+    }
+
+    fn parse(line: &str) -> Options {
+        match parse_args(&args(line)).unwrap() {
+            Action::Run(options) => options,
+            Action::Help => panic!("expected a command"),
+        }
+    }
+
+    #[test]
+    fn parses_commands_and_flags() {
+        let options = parse("--json --timeout 1000 --channel sam force dark");
+        assert!(options.json);
+        assert_eq!(options.timeout, 1000);
+        assert_eq!(options.channel, "sam");
+        assert_eq!(options.command, Command::Force(Some("dark".to_string())));
+        assert_eq!(parse("pause 30").command, Command::Pause(Some(30)));
+        assert_eq!(parse("auto-switch off").command, Command::AutoSwitch(Some(false)));
+        assert_eq!(
+            parse("config Wallpaper").command,
+            Command::Config(Some("Wallpaper".to_string()))
+        );
+        assert_eq!(parse_args(&args("--json --help")), Ok(Action::Help));
+        assert!(parse_args(&args("theme blue")).is_err());
+        assert!(parse_args(&args("switch now")).is_err());
+        assert!(parse_args(&args("--json")).is_err());
+    }
+
+    #[test]
+    fn maps_status_codes_to_exit_codes() {
+        assert_eq!(exit_code("Ok"), EXIT_OK);
+        assert_eq!(exit_code("Err"), EXIT_ERR);
+        assert_eq!(exit_code("Timeout"), EXIT_TIMEOUT);
+        assert_eq!(exit_code("New"), EXIT_UPDATE_AVAILABLE);
+        assert_eq!(exit_code("SomethingElse"), EXIT_ERR);
+    }
+
+    #[test]
+    fn prints_config_values() {
+        assert_eq!(config_text(&parse_config_value("\"false\"").unwrap()), "false");
+        assert_eq!(config_text(&parse_config_value("true").unwrap()), "true");
+        let wallpaper = parse_config_value("{\"Enabled\": true, \"Position\": \"Fill\"}").unwrap();
+        assert_eq!(wallpaper["Position"], "Fill");
+        assert!(config_text(&wallpaper).contains("\"Enabled\": true"));
+        assert!(parse_config_value("Enabled: true").is_err());
+    }
+}
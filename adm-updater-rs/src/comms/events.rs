@@ -0,0 +1,165 @@
+use log::debug;
+use std::io::{BufRead, BufReader, ErrorKind};
+
+use super::{connect_with_timeout, new_response_pipe_id, pipe::SecurePipe, send_message, ApiResponse, PipeError};
+
+const SUBSCRIBE_COMMAND: &str = "--subscribe";
+
+/// Events pushed by the service to subscribed clients
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AdmEvent {
+    /// The service switched themes, carries the theme that is now active
+    ThemeSwitched(String),
+    /// An update check found a new version, carries the upstream version string
+    UpdateAvailable(String),
+    /// The service is about to exit
+    ServiceShutdown,
+    /// The configuration was reloaded, carries the name of the file that changed
+    ConfigChanged(String),
+    /// An event this client does not know about yet
+    Unknown(String),
+}
+
+impl From<&str> for AdmEvent {
+    fn from(line: &str) -> Self {
+        let line = line.trim_end_matches(['\r', '\n']);
+        let (name, payload) = match line.split_once('=') {
+            Some((name, payload)) => (name, payload.to_string()),
+            None => (line, String::new()),
+        };
+        match name {
+            "ThemeSwitched" => AdmEvent::ThemeSwitched(payload),
+            "UpdateAvailable" => AdmEvent::UpdateAvailable(payload),
+            "ServiceShutdown" => AdmEvent::ServiceShutdown,
+            "ConfigChanged" => AdmEvent::ConfigChanged(payload),
+            _ => AdmEvent::Unknown(line.to_string()),
+        }
+    }
+}
+
+/// A long-lived channel over which the service pushes one event per line
+pub struct EventSubscription {
+    reader: BufReader<SecurePipe>,
+}
+
+impl EventSubscription {
+    /// Blocks until the service pushes the next event or the timeout elapses
+    ///
+    /// A closed channel is reported as `ServiceShutdown`, because the service only drops subscribers when it exits
+    pub fn next_event(&mut self, timeout: u32) -> Result<AdmEvent, PipeError> {
+        let duration = std::time::Duration::from_millis(timeout as u64);
+        self.reader.get_mut().set_read_timeout(Some(duration));
+        let mut line = String::new();
+        match self.reader.read_line(&mut line) {
+            Ok(0) => {
+                debug!("event channel closed by service");
+                Ok(AdmEvent::ServiceShutdown)
+            }
+            Ok(_) => Ok(AdmEvent::from(line.as_str())),
+            Err(e) if e.kind() == ErrorKind::TimedOut => Err(PipeError {
+                message: format!("no event received within {} ms", timeout),
+                is_timeout: true,
+            }),
+            Err(e) => Err(PipeError {
+                message: format!("{}", e),
+                is_timeout: false,
+            }),
+        }
+    }
+
+    /// Skips events until one matches the predicate, giving up once the timeout elapses
+    pub fn wait_for<F>(&mut self, timeout: u32, predicate: F) -> Result<AdmEvent, PipeError>
+    where
+        F: Fn(&AdmEvent) -> bool,
+    {
+        let deadline = std::time::Instant::now() + std::time::Duration::from_millis(timeout as u64);
+        loop {
+            let remaining = deadline.saturating_duration_since(std::time::Instant::now()).as_millis() as u32;
+            if remaining == 0 {
+                return Err(PipeError {
+                    message: format!("expected event not received within {} ms", timeout),
+                    is_timeout: true,
+                });
+            }
+            let event = self.next_event(remaining)?;
+            if predicate(&event) {
+                return Ok(event);
+            }
+            debug!("skipping event {:?}", event);
+        }
+    }
+}
+
+/// Opens an event channel to the service running under the given channel
+///
+/// Fails if the service does not acknowledge the subscription, which is the case for versions without event support
+pub fn subscribe(timeout: u32, channel: &str) -> Result<EventSubscription, PipeError> {
+    let response_pipe_id = new_response_pipe_id("rust_evt_");
+    send_message(SUBSCRIBE_COMMAND, timeout, &channel.to_lowercase(), &response_pipe_id)?;
+
+    let mut response_pipe = connect_with_timeout(
+        &format!("\\\\.\\pipe\\admpipe_response_{}", response_pipe_id),
+        timeout,
+        channel,
+    )?;
+    let duration = std::time::Duration::from_millis(timeout as u64);
+    response_pipe.set_read_timeout(Some(duration));
+    let mut reader = BufReader::new(response_pipe);
+    let mut ack = String::new();
+    if let Err(e) = reader.read_line(&mut ack) {
+        return Err(PipeError {
+            message: format!("{}", e),
+            is_timeout: e.kind() == ErrorKind::TimedOut,
+        });
+    }
+    let ack: ApiResponse = ack.trim_end().to_string().into();
+    if ack.status_code != "Ok" {
+        return Err(PipeError {
+            message: format!("service rejected event subscription with status {}", ack.status_code),
+            is_timeout: false,
+        });
+    }
+    debug!("subscribed to service events on {}", response_pipe_id);
+    Ok(EventSubscription { reader })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+
+    use log::info;
+
+    use crate::setup_logger;
+
+    use super::*;
+
+    #[test]
+    fn parse_events() {
+        assert_eq!(
+            AdmEvent::from("ThemeSwitched=Dark\n"),
+            AdmEvent::ThemeSwitched("Dark".to_string())
+        );
+        assert_eq!(
+            AdmEvent::from("UpdateAvailable=11.0.0.23"),
+            AdmEvent::UpdateAvailable("11.0.0.23".to_string())
+        );
+        assert_eq!(AdmEvent::from("ServiceShutdown\r\n"), AdmEvent::ServiceShutdown);
+        assert_eq!(
+            AdmEvent::from("ConfigChanged=config.yaml"),
+            AdmEvent::ConfigChanged("config.yaml".to_string())
+        );
+        assert_eq!(
+            AdmEvent::from("SomethingNew=1"),
+            AdmEvent::Unknown("SomethingNew=1".to_string())
+        );
+    }
+
+    #[test]
+    fn test_subscription() -> Result<(), Box<dyn Error>> {
+        setup_logger()?;
+        let mut subscription = subscribe(5000, "sam")?;
+        let event = subscription.next_event(10000)?;
+        info!("{:?}", event);
+        Ok(())
+    }
+}
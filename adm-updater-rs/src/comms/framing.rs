@@ -0,0 +1,184 @@
+use std::io::{ErrorKind, Read};
+
+use super::PipeError;
+
+/// Protocol version spoken by this client, services that do not know it are addressed with the legacy format
+pub const PROTOCOL_VERSION: u16 = 2;
+/// Legacy protocol, `"{msg}\n{response_channel}"` on the request pipe and the bare response until the pipe closes
+pub const LEGACY_PROTOCOL_VERSION: u16 = 1;
+
+const MAGIC: [u8; 4] = *b"ADMF";
+/// magic, version, message id, body length and response channel length
+const FIXED_HEADER_LEN: usize = 4 + 2 + 4 + 4 + 2;
+const MAX_HEADER_LEN: u32 = 4 * 1024;
+const MAX_BODY_LEN: u32 = 16 * 1024 * 1024;
+
+/// A single protocol v2 message
+///
+/// On the wire a frame is a little endian u32 header length, followed by the header and the UTF-8 body.
+/// The header holds the magic, protocol version, message id, body length and the response channel
+/// prefixed with its u16 length.
+/// Header bytes after the known fields are skipped, so newer services can extend the header.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub version: u16,
+    pub message_id: u32,
+    pub response_channel: String,
+    pub body: String,
+}
+
+impl Frame {
+    pub fn new(message_id: u32, response_channel: &str, body: &str) -> Frame {
+        Frame {
+            version: PROTOCOL_VERSION,
+            message_id,
+            response_channel: response_channel.to_string(),
+            body: body.to_string(),
+        }
+    }
+
+    pub fn encode(&self) -> Result<Vec<u8>, PipeError> {
+        let channel = self.response_channel.as_bytes();
+        let body = self.body.as_bytes();
+        let channel_len = u16::try_from(channel.len()).map_err(|_| frame_error("response channel name too long"))?;
+        let body_len = u32::try_from(body.len())
+            .ok()
+            .filter(|len| *len <= MAX_BODY_LEN)
+            .ok_or_else(|| frame_error("message body too large"))?;
+        let header_len = (FIXED_HEADER_LEN + channel.len()) as u32;
+        if header_len > MAX_HEADER_LEN {
+            return Err(frame_error("frame header too large"));
+        }
+
+        let mut out = Vec::with_capacity(4 + header_len as usize + body.len());
+        out.extend_from_slice(&header_len.to_le_bytes());
+        out.extend_from_slice(&MAGIC);
+        out.extend_from_slice(&self.version.to_le_bytes());
+        out.extend_from_slice(&self.message_id.to_le_bytes());
+        out.extend_from_slice(&body_len.to_le_bytes());
+        out.extend_from_slice(&channel_len.to_le_bytes());
+        out.extend_from_slice(channel);
+        out.extend_from_slice(body);
+        Ok(out)
+    }
+
+    /// Reads exactly one frame from the reader
+    pub fn decode<R: Read>(reader: &mut R) -> Result<Frame, PipeError> {
+        let header_len = u32::from_le_bytes(read_array(reader)?);
+        if header_len < FIXED_HEADER_LEN as u32 || header_len > MAX_HEADER_LEN {
+            return Err(frame_error(&format!("invalid frame header length {}", header_len)));
+        }
+        let mut header = vec![0u8; header_len as usize];
+        read_exact(reader, &mut header)?;
+        if header[0..4] != MAGIC {
+            return Err(frame_error("invalid frame magic"));
+        }
+        let version = u16::from_le_bytes([header[4], header[5]]);
+        let message_id = u32::from_le_bytes([header[6], header[7], header[8], header[9]]);
+        let body_len = u32::from_le_bytes([header[10], header[11], header[12], header[13]]);
+        let channel_len = u16::from_le_bytes([header[14], header[15]]) as usize;
+        if version < PROTOCOL_VERSION {
+            return Err(frame_error(&format!("unsupported frame version {}", version)));
+        }
+        if body_len > MAX_BODY_LEN {
+            return Err(frame_error(&format!("frame body length {} exceeds limit", body_len)));
+        }
+        let channel = header
+            .get(FIXED_HEADER_LEN..FIXED_HEADER_LEN + channel_len)
+            .ok_or_else(|| frame_error("response channel exceeds frame header"))?;
+        let response_channel = String::from_utf8(channel.to_vec()).map_err(|e| frame_error(&format!("{}", e)))?;
+
+        let mut body = vec![0u8; body_len as usize];
+        read_exact(reader, &mut body)?;
+        let body = String::from_utf8(body).map_err(|e| frame_error(&format!("{}", e)))?;
+        Ok(Frame {
+            version,
+            message_id,
+            response_channel,
+            body,
+        })
+    }
+}
+
+fn read_array<R: Read>(reader: &mut R) -> Result<[u8; 4], PipeError> {
+    let mut buf = [0u8; 4];
+    read_exact(reader, &mut buf)?;
+    Ok(buf)
+}
+
+fn read_exact<R: Read>(reader: &mut R, buf: &mut [u8]) -> Result<(), PipeError> {
+    reader.read_exact(buf).map_err(|e| PipeError {
+        message: format!("could not read frame: {}", e),
+        is_timeout: e.kind() == ErrorKind::TimedOut,
+    })
+}
+
+fn frame_error(message: &str) -> PipeError {
+    PipeError {
+        message: message.to_string(),
+        is_timeout: false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    #[test]
+    fn frame_roundtrip() {
+        let frame = Frame::new(42, "rust_abc", "--set-script\nline two\nAdmApiDataRow=x");
+        let encoded = frame.encode().unwrap();
+        let decoded = Frame::decode(&mut Cursor::new(encoded)).unwrap();
+        assert_eq!(decoded, frame);
+    }
+
+    #[test]
+    fn decodes_consecutive_frames() {
+        let mut encoded = Frame::new(1, "", "first").encode().unwrap();
+        encoded.extend(Frame::new(2, "", "second").encode().unwrap());
+        let mut cursor = Cursor::new(encoded);
+        assert_eq!(Frame::decode(&mut cursor).unwrap().body, "first");
+        assert_eq!(Frame::decode(&mut cursor).unwrap().message_id, 2);
+    }
+
+    #[test]
+    fn skips_unknown_header_fields() {
+        let mut encoded = Frame::new(7, "chan", "body").encode().unwrap();
+        let header_len = u32::from_le_bytes(encoded[0..4].try_into().unwrap()) + 3;
+        encoded[0..4].copy_from_slice(&header_len.to_le_bytes());
+        let body_start = encoded.len() - "body".len();
+        encoded.splice(body_start..body_start, [9u8, 9, 9]);
+        let decoded = Frame::decode(&mut Cursor::new(encoded)).unwrap();
+        assert_eq!(decoded.response_channel, "chan");
+        assert_eq!(decoded.body, "body");
+    }
+
+    #[test]
+    fn header_length_never_looks_like_text() {
+        // the service tells frames from legacy requests by the two zero bytes at the top of the header length
+        let channel = "c".repeat(MAX_HEADER_LEN as usize - FIXED_HEADER_LEN);
+        let encoded = Frame::new(1, &channel, "body").encode().unwrap();
+        assert_eq!(encoded[2..4], [0, 0]);
+    }
+
+    #[test]
+    fn rejects_malformed_frames() {
+        let encoded = Frame::new(1, "chan", "body").encode().unwrap();
+
+        let mut bad_magic = encoded.clone();
+        bad_magic[4] = b'X';
+        assert!(Frame::decode(&mut Cursor::new(bad_magic)).is_err());
+
+        let truncated = encoded[..encoded.len() - 1].to_vec();
+        assert!(Frame::decode(&mut Cursor::new(truncated)).is_err());
+
+        let mut huge_body = encoded.clone();
+        huge_body[14..18].copy_from_slice(&(MAX_BODY_LEN + 1).to_le_bytes());
+        assert!(Frame::decode(&mut Cursor::new(huge_body)).is_err());
+
+        // a legacy plain text response must never be mistaken for a frame
+        assert!(Frame::decode(&mut Cursor::new(b"Ok\nAdmApiDataRow=fine".to_vec())).is_err());
+    }
+}
@@ -0,0 +1,336 @@
+use framing::{Frame, LEGACY_PROTOCOL_VERSION, PROTOCOL_VERSION};
+use lazy_static::lazy_static;
+use log::{debug, warn};
+use pipe::SecurePipe;
+use rand::distr::{Alphanumeric, Distribution};
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt,
+    io::{Read, Write},
+    str::FromStr,
+    sync::Mutex,
+};
+
+pub mod events;
+mod framing;
+mod pipe;
+mod security;
+pub mod zmq;
+
+const PROTOCOL_QUERY: &str = "--get-protocol-version";
+const TRANSPORT_ENV: &str = "ADM_COMMS_TRANSPORT";
+/// Messages that must only reach the service through its access controlled pipe, never through the unauthenticated tcp backend
+const PIPE_ONLY_MESSAGES: [&str; 1] = ["--exit"];
+
+lazy_static! {
+    /// Protocol version spoken by the service of each channel, negotiated once per process
+    static ref NEGOTIATED_PROTOCOLS: Mutex<HashMap<String, u16>> = Mutex::new(HashMap::new());
+}
+
+/// Length of the random part of response pipe names, long enough that other processes cannot guess them
+const RESPONSE_PIPE_ID_LEN: usize = 32;
+
+#[derive(Debug, Clone)]
+pub struct PipeError {
+    pub message: String,
+    pub is_timeout: bool,
+}
+
+impl Error for PipeError {}
+
+impl fmt::Display for PipeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Clone)]
+pub struct ApiResponse {
+    pub status_code: String,
+    pub message: String,
+    pub details: String,
+}
+
+impl From<String> for ApiResponse {
+    fn from(string: String) -> Self {
+        let mut parts = string.split("\nAdmApiDataRow=");
+        let status_code = parts.next().unwrap_or("").to_string();
+        let message = parts.next().unwrap_or("").to_string();
+        let details = parts.next().unwrap_or("").to_string();
+        ApiResponse {
+            status_code,
+            message,
+            details,
+        }
+    }
+}
+
+/// Transport used to reach the service
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transport {
+    /// Named pipes, which the service listens on by default
+    Pipe,
+    /// ZeroMQ request/reply over loopback tcp
+    ZeroMq,
+    /// Named pipes, retried over ZeroMQ if the pipe times out, only used when explicitly configured
+    Auto,
+}
+
+impl Transport {
+    /// Reads the transport from the ADM_COMMS_TRANSPORT environment variable, defaulting to named pipes
+    ///
+    /// The ZeroMQ backend accepts any local connection, so it is never used unless configured
+    pub fn from_env() -> Transport {
+        match std::env::var(TRANSPORT_ENV) {
+            Ok(value) => value.parse().unwrap_or_else(|e| {
+                warn!("{}, using named pipes", e);
+                Transport::Pipe
+            }),
+            Err(_) => Transport::Pipe,
+        }
+    }
+}
+
+impl FromStr for Transport {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "pipe" | "" => Ok(Transport::Pipe),
+            "zmq" | "zeromq" => Ok(Transport::ZeroMq),
+            "auto" => Ok(Transport::Auto),
+            other => Err(format!("unknown transport {}", other)),
+        }
+    }
+}
+
+pub fn send_message_and_get_reply(msg: &str, timeout: u32, channel: &str) -> Result<ApiResponse, PipeError> {
+    send_message_with_transport(msg, timeout, channel, Transport::from_env())
+}
+
+/// Sends a message over the given transport
+///
+/// In automatic mode a ZeroMQ failure after a pipe timeout returns the pipe error,
+/// so callers that treat timeouts as an exited service keep working when no ZeroMQ backend exists.
+/// Pipe only messages such as `--exit` are never retried over ZeroMQ
+pub fn send_message_with_transport(
+    msg: &str,
+    timeout: u32,
+    channel: &str,
+    transport: Transport,
+) -> Result<ApiResponse, PipeError> {
+    match transport {
+        Transport::Pipe => send_pipe_message_and_get_reply(msg, timeout, channel),
+        Transport::ZeroMq => zmq::send_message_and_get_reply(msg, timeout, zmq::backend_address()),
+        Transport::Auto => match send_pipe_message_and_get_reply(msg, timeout, channel) {
+            Err(e) if e.is_timeout && !PIPE_ONLY_MESSAGES.contains(&msg) => {
+                debug!("pipe transport timed out, trying zeromq: {}", e);
+                zmq::send_message_and_get_reply(msg, timeout, zmq::backend_address()).map_err(|zmq_error| {
+                    debug!("zeromq fallback failed: {}", zmq_error);
+                    e
+                })
+            }
+            result => result,
+        },
+    }
+}
+
+fn send_pipe_message_and_get_reply(msg: &str, timeout: u32, channel: &str) -> Result<ApiResponse, PipeError> {
+    if negotiate_protocol(timeout, channel)? >= PROTOCOL_VERSION {
+        return send_framed_message_and_get_reply(msg, timeout, channel);
+    }
+    let response_pipe_id = new_response_pipe_id("rust_");
+    send_message(msg, timeout, &channel.to_lowercase(), &response_pipe_id)?;
+    receive_reply(&response_pipe_id, timeout, channel)
+}
+
+/// Returns the protocol version to use for the channel, asking the service over the legacy protocol the first time
+///
+/// Services that predate framing answer the query with an error status and are addressed with the legacy format.
+/// Only a failure to reach the request pipe is reported as a timeout, a service that accepts the query but never
+/// answers is still running and must not be mistaken for one that exited
+fn negotiate_protocol(timeout: u32, channel: &str) -> Result<u16, PipeError> {
+    let key = channel.to_lowercase();
+    if let Some(version) = NEGOTIATED_PROTOCOLS.lock().ok().and_then(|p| p.get(&key).copied()) {
+        return Ok(version);
+    }
+    let response_pipe_id = new_response_pipe_id("rust_");
+    send_message(PROTOCOL_QUERY, timeout, &key, &response_pipe_id)?;
+    let response = receive_reply(&response_pipe_id, timeout, channel).map_err(|e| PipeError {
+        message: format!("service did not answer the protocol query: {}", e),
+        is_timeout: false,
+    })?;
+    let version = match response.status_code.as_str() {
+        "Ok" => response
+            .message
+            .trim()
+            .parse::<u16>()
+            .map(|v| v.min(PROTOCOL_VERSION))
+            .unwrap_or(LEGACY_PROTOCOL_VERSION),
+        _ => LEGACY_PROTOCOL_VERSION,
+    };
+    debug!("using protocol version {} for channel {}", version, key);
+    if let Ok(mut protocols) = NEGOTIATED_PROTOCOLS.lock() {
+        protocols.insert(key, version);
+    }
+    Ok(version)
+}
+
+fn send_framed_message_and_get_reply(msg: &str, timeout: u32, channel: &str) -> Result<ApiResponse, PipeError> {
+    let response_pipe_id = new_response_pipe_id("rust_");
+    let message_id: u32 = rand::random();
+    let request = Frame::new(message_id, &response_pipe_id, msg);
+    let mut request_pipe = connect_with_timeout(
+        &format!("\\\\.\\pipe\\admpipe_request_{}", channel.to_lowercase()),
+        timeout,
+        channel,
+    )?;
+    let duration = std::time::Duration::from_millis(timeout as u64);
+    request_pipe.set_write_timeout(Some(duration));
+    if let Err(e) = request_pipe.write_all(&request.encode()?) {
+        return Err(PipeError {
+            message: format!("{}", e),
+            is_timeout: false,
+        });
+    };
+
+    let mut response_pipe = connect_with_timeout(
+        &format!("\\\\.\\pipe\\admpipe_response_{}", response_pipe_id),
+        timeout,
+        channel,
+    )?;
+    response_pipe.set_read_timeout(Some(duration));
+    let reply = Frame::decode(&mut response_pipe)?;
+    if reply.message_id != message_id {
+        return Err(PipeError {
+            message: format!("reply belongs to message {}, expected {}", reply.message_id, message_id),
+            is_timeout: false,
+        });
+    }
+    Ok(reply.body.into())
+}
+
+fn new_response_pipe_id(prefix: &str) -> String {
+    let mut response_pipe_id = String::from(prefix);
+    let mut rng = rand::rng();
+    let unique_id: String = Alphanumeric
+        .sample_iter(&mut rng)
+        .take(RESPONSE_PIPE_ID_LEN)
+        .map(char::from)
+        .collect();
+    response_pipe_id.push_str(&unique_id);
+    response_pipe_id
+}
+
+fn send_message(msg: &str, timeout: u32, request_channel: &str, response_channel: &str) -> Result<(), PipeError> {
+    let mut request_pipe = connect_with_timeout(
+        &format!("\\\\.\\pipe\\admpipe_request_{}", request_channel),
+        timeout,
+        request_channel,
+    )?;
+
+    let message = format!("{}\n{}", msg, response_channel);
+    let duration = std::time::Duration::from_millis(timeout as u64);
+    request_pipe.set_write_timeout(Some(duration));
+    if let Err(e) = request_pipe.write_all(message.as_bytes()) {
+        return Err(PipeError {
+            message: format!("{}", e),
+            is_timeout: false,
+        });
+    };
+    Ok(())
+}
+
+fn receive_reply(response_channel: &str, timeout: u32, channel: &str) -> Result<ApiResponse, PipeError> {
+    let mut response_pipe = connect_with_timeout(
+        &format!("\\\\.\\pipe\\admpipe_response_{}", response_channel),
+        timeout,
+        channel,
+    )?;
+    let duration = std::time::Duration::from_millis(timeout as u64);
+    response_pipe.set_read_timeout(Some(duration));
+    let mut buf: Vec<u8> = Vec::new();
+    if let Err(result) = response_pipe.read_to_end(&mut buf) {
+        return Err(PipeError {
+            message: format!("{}", result),
+            is_timeout: false,
+        });
+    };
+    let out = match String::from_utf8(buf) {
+        Ok(converted) => converted,
+        Err(e) => {
+            return Err(PipeError {
+                message: format!("{}", e),
+                is_timeout: false,
+            })
+        }
+    };
+
+    Ok(out.into())
+}
+
+/// Connects to the given pipe and verifies that its server runs under the account the channel belongs to
+fn connect_with_timeout(address: &str, timeout: u32, channel: &str) -> Result<SecurePipe, PipeError> {
+    let mut pipe_connection_attempt = Err(PipeError {
+        message: String::from("never connected"),
+        is_timeout: true,
+    });
+    let retries = timeout / 100;
+    for _ in 0..retries {
+        pipe_connection_attempt = match SecurePipe::connect_ms(address, timeout) {
+            Ok(pipe) => Ok(pipe),
+            Err(e) => {
+                std::thread::sleep(std::time::Duration::from_millis(100));
+                Err(PipeError {
+                    message: format!("{}", e),
+                    is_timeout: true,
+                })
+            }
+        };
+        if pipe_connection_attempt.is_ok() {
+            debug!("connected!");
+            break;
+        }
+    }
+    let pipe = pipe_connection_attempt?;
+    security::verify_server_user(&pipe, channel)?;
+    Ok(pipe)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+
+    use log::info;
+
+    use crate::setup_logger;
+
+    use super::*;
+
+    #[test]
+    fn test_message_capabilities() -> Result<(), Box<dyn Error>> {
+        setup_logger()?;
+        let response = send_message_and_get_reply("--alive", 5000, "sam")?;
+        info!("{:?}", response);
+        Ok(())
+    }
+
+    #[test]
+    fn parse_transport() {
+        assert_eq!("pipe".parse::<Transport>(), Ok(Transport::Pipe));
+        assert_eq!("ZeroMQ".parse::<Transport>(), Ok(Transport::ZeroMq));
+        assert_eq!(" auto ".parse::<Transport>(), Ok(Transport::Auto));
+        assert_eq!("".parse::<Transport>(), Ok(Transport::Pipe));
+        assert!("carrier-pigeon".parse::<Transport>().is_err());
+    }
+
+    #[test]
+    fn response_pipe_ids_are_unguessable() {
+        let first = new_response_pipe_id("rust_");
+        let second = new_response_pipe_id("rust_");
+        assert_eq!(first.len(), "rust_".len() + RESPONSE_PIPE_ID_LEN);
+        assert_ne!(first, second);
+    }
+}
@@ -0,0 +1,145 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Read, Write},
+    os::windows::{fs::OpenOptionsExt, io::AsRawHandle},
+    sync::mpsc,
+    thread,
+    time::{Duration, Instant},
+};
+
+use windows::Win32::{
+    Foundation::{ERROR_BROKEN_PIPE, ERROR_PIPE_BUSY, HANDLE},
+    Storage::FileSystem::SECURITY_IDENTIFICATION,
+    System::{
+        Pipes::{GetNamedPipeServerProcessId, PeekNamedPipe, WaitNamedPipeW},
+        IO::CancelSynchronousIo,
+    },
+};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Client end of a named pipe
+///
+/// The pipe is opened with the identification impersonation level, so a server squatting on the pipe name
+/// can find out who connected but cannot act on the client's behalf
+pub struct SecurePipe {
+    file: File,
+    read_timeout: Option<Duration>,
+    write_timeout: Option<Duration>,
+}
+
+impl SecurePipe {
+    /// Connects to the given pipe address, waiting up to `timeout` ms once if all server instances are busy
+    pub fn connect_ms(address: &str, timeout: u32) -> io::Result<SecurePipe> {
+        let mut waited = false;
+        loop {
+            let result = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .security_qos_flags(SECURITY_IDENTIFICATION.0)
+                .open(address);
+            match result {
+                Ok(file) => {
+                    return Ok(SecurePipe {
+                        file,
+                        read_timeout: None,
+                        write_timeout: None,
+                    })
+                }
+                Err(e) if e.raw_os_error() == Some(ERROR_PIPE_BUSY.0 as i32) && !waited => {
+                    waited = true;
+                    let name = windows::core::HSTRING::from(address);
+                    if !unsafe { WaitNamedPipeW(&name, timeout) }.as_bool() {
+                        return Err(e);
+                    }
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    pub fn set_read_timeout(&mut self, read_timeout: Option<Duration>) {
+        self.read_timeout = read_timeout;
+    }
+
+    pub fn set_write_timeout(&mut self, write_timeout: Option<Duration>) {
+        self.write_timeout = write_timeout;
+    }
+
+    /// Returns the id of the process that created the server end of the pipe
+    pub fn server_process_id(&self) -> io::Result<u32> {
+        let mut pid: u32 = 0;
+        unsafe { GetNamedPipeServerProcessId(self.handle(), &mut pid) }?;
+        Ok(pid)
+    }
+
+    fn handle(&self) -> HANDLE {
+        HANDLE(self.file.as_raw_handle())
+    }
+
+    /// Returns the number of bytes waiting in the pipe, or None if the server has closed its end
+    fn available(&self) -> io::Result<Option<u32>> {
+        let mut available: u32 = 0;
+        match unsafe { PeekNamedPipe(self.handle(), None, 0, None, Some(&mut available), None) } {
+            Ok(()) => Ok(Some(available)),
+            Err(e) if e.code() == ERROR_BROKEN_PIPE.to_hresult() => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+impl Read for SecurePipe {
+    /// Polls the pipe until data arrives, so reads honor the read timeout without overlapped io
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let deadline = self.read_timeout.map(|timeout| Instant::now() + timeout);
+        loop {
+            match self.available()? {
+                None => return Ok(0),
+                Some(0) => {}
+                Some(available) => {
+                    let len = buf.len().min(available as usize);
+                    return self.file.read(&mut buf[..len]);
+                }
+            }
+            if deadline.is_some_and(|d| Instant::now() >= d) {
+                return Err(io::Error::new(io::ErrorKind::TimedOut, "pipe read timed out"));
+            }
+            std::thread::sleep(POLL_INTERVAL);
+        }
+    }
+}
+
+impl Write for SecurePipe {
+    /// Writes on a helper thread if a write timeout is set, so a server that never reads cannot block the caller
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let Some(timeout) = self.write_timeout else {
+            return self.file.write(buf);
+        };
+        let mut file = self.file.try_clone()?;
+        let data = buf.to_vec();
+        let (sender, receiver) = mpsc::channel();
+        let writer = thread::spawn(move || {
+            let _ = sender.send(file.write(&data));
+        });
+        if let Ok(result) = receiver.recv_timeout(timeout) {
+            let _ = writer.join();
+            return result;
+        }
+        // the write may not have started when the first cancellation arrives, so keep cancelling until it returns
+        loop {
+            let _ = unsafe { CancelSynchronousIo(HANDLE(writer.as_raw_handle())) };
+            if !matches!(receiver.recv_timeout(POLL_INTERVAL), Err(mpsc::RecvTimeoutError::Timeout)) {
+                break;
+            }
+        }
+        let _ = writer.join();
+        Err(io::Error::new(io::ErrorKind::TimedOut, "pipe write timed out"))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
@@ -0,0 +1,92 @@
+use std::ffi::OsStr;
+
+use windows::Win32::{
+    Foundation::{CloseHandle, LocalFree, HANDLE, HLOCAL},
+    Security::{Authorization::ConvertSidToStringSidW, GetTokenInformation, TokenUser, TOKEN_QUERY, TOKEN_USER},
+    System::Threading::{OpenProcess, OpenProcessToken, PROCESS_QUERY_LIMITED_INFORMATION},
+};
+use windows_permissions::wrappers::LookupAccountName;
+
+use super::{pipe::SecurePipe, PipeError};
+
+/// Verifies that the process serving the pipe runs under the account the channel belongs to
+///
+/// Another local user could otherwise create the pipe first and read or answer requests in place of the service
+pub fn verify_server_user(pipe: &SecurePipe, channel: &str) -> Result<(), PipeError> {
+    let pid = pipe
+        .server_process_id()
+        .map_err(|e| auth_error(&format!("could not determine pipe server process: {}", e)))?;
+    let server_sid = process_user_sid(pid)?;
+    let expected_sid = account_sid(channel)?;
+    if !server_sid.eq_ignore_ascii_case(&expected_sid) {
+        return Err(auth_error(&format!(
+            "pipe server process {} runs as {}, expected {}, refusing to communicate",
+            pid, server_sid, expected_sid
+        )));
+    }
+    Ok(())
+}
+
+fn account_sid(username: &str) -> Result<String, PipeError> {
+    let (sid, _, _) = LookupAccountName(Option::<&OsStr>::None, username)
+        .map_err(|e| auth_error(&format!("could not get sid for {}: {}", username, e)))?;
+    Ok(sid.to_string())
+}
+
+fn process_user_sid(pid: u32) -> Result<String, PipeError> {
+    let process = unsafe { OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, false, pid) }
+        .map_err(|e| auth_error(&format!("could not open pipe server process {}: {}", pid, e)))?;
+    let mut token = HANDLE::default();
+    let result = unsafe { OpenProcessToken(process, TOKEN_QUERY, &mut token) };
+    let _ = unsafe { CloseHandle(process) };
+    result.map_err(|e| auth_error(&format!("could not open token of process {}: {}", pid, e)))?;
+    let sid = token_user_sid(token);
+    let _ = unsafe { CloseHandle(token) };
+    sid
+}
+
+fn token_user_sid(token: HANDLE) -> Result<String, PipeError> {
+    let mut len: u32 = 0;
+    // the first call only queries the required buffer size and is expected to fail
+    let _ = unsafe { GetTokenInformation(token, TokenUser, None, 0, &mut len) };
+    if len == 0 {
+        return Err(auth_error("could not query token user size"));
+    }
+    // u64 storage keeps the buffer aligned for the TOKEN_USER struct
+    let mut buffer: Vec<u64> = vec![0; (len as usize).div_ceil(8)];
+    unsafe { GetTokenInformation(token, TokenUser, Some(buffer.as_mut_ptr() as *mut _), len, &mut len) }
+        .map_err(|e| auth_error(&format!("could not query token user: {}", e)))?;
+    let token_user = unsafe { &*(buffer.as_ptr() as *const TOKEN_USER) };
+
+    let mut sid_string = windows::core::PWSTR::null();
+    unsafe { ConvertSidToStringSidW(token_user.User.Sid, &mut sid_string) }
+        .map_err(|e| auth_error(&format!("could not convert token sid: {}", e)))?;
+    let result = unsafe { sid_string.to_string() }.map_err(|e| auth_error(&format!("invalid token sid string: {}", e)));
+    unsafe { LocalFree(Some(HLOCAL(sid_string.0 as _))) };
+    result
+}
+
+fn auth_error(message: &str) -> PipeError {
+    PipeError {
+        message: message.to_string(),
+        is_timeout: false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+
+    use crate::setup_logger;
+
+    use super::*;
+
+    #[test]
+    fn own_process_matches_own_account() -> Result<(), Box<dyn Error>> {
+        setup_logger()?;
+        let own_sid = process_user_sid(std::process::id())?;
+        let account = account_sid(&whoami::username())?;
+        assert!(own_sid.eq_ignore_ascii_case(&account));
+        Ok(())
+    }
+}
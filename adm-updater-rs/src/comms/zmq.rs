@@ -0,0 +1,310 @@
+use std::{
+    io::{ErrorKind, Read, Write},
+    net::{Ipv4Addr, SocketAddr, TcpStream},
+    time::Duration,
+};
+
+use log::debug;
+use windows::Win32::{
+    Foundation::CloseHandle,
+    System::Memory::{MapViewOfFile, OpenFileMappingW, UnmapViewOfFile, FILE_MAP_READ},
+};
+use windows_strings::w;
+
+use super::{ApiResponse, PipeError};
+
+/// Port the service binds to if it does not publish a random one, mirrors Address.DefaultPort
+pub const DEFAULT_PORT: u16 = 54345;
+
+const GREETING_LEN: usize = 64;
+const FLAG_MORE: u8 = 0x01;
+const FLAG_LONG: u8 = 0x02;
+const FLAG_COMMAND: u8 = 0x04;
+
+/// Returns the loopback address of the service's ZeroMQ backend
+///
+/// The service publishes its randomly bound port in the `adm-backend-port` shared memory section,
+/// the default port is used if that section does not exist
+pub fn backend_address() -> SocketAddr {
+    let port = published_port().unwrap_or(DEFAULT_PORT);
+    SocketAddr::from((Ipv4Addr::LOCALHOST, port))
+}
+
+fn published_port() -> Option<u16> {
+    let mapping = unsafe { OpenFileMappingW(FILE_MAP_READ.0, false, w!("adm-backend-port")) }.ok()?;
+    let view = unsafe { MapViewOfFile(mapping, FILE_MAP_READ, 0, 0, std::mem::size_of::<i32>()) };
+    let port = if view.Value.is_null() {
+        None
+    } else {
+        let value = unsafe { std::ptr::read_unaligned(view.Value as *const i32) };
+        let _ = unsafe { UnmapViewOfFile(view) };
+        u16::try_from(value).ok().filter(|p| *p != 0)
+    };
+    let _ = unsafe { CloseHandle(mapping) };
+    port
+}
+
+/// Sends a message to the service over ZeroMQ request/reply and waits for the answer
+pub fn send_message_and_get_reply(msg: &str, timeout: u32, address: SocketAddr) -> Result<ApiResponse, PipeError> {
+    let mut socket = RequestSocket::connect(address, timeout)?;
+    socket.send(msg.as_bytes())?;
+    let reply = socket.receive()?;
+    let out = String::from_utf8(reply).map_err(|e| PipeError {
+        message: format!("{}", e),
+        is_timeout: false,
+    })?;
+    Ok(out.into())
+}
+
+/// Minimal ZMTP 3.0 REQ socket using the NULL security mechanism, which is what NetMQ speaks on loopback
+pub struct RequestSocket {
+    stream: TcpStream,
+}
+
+impl RequestSocket {
+    pub fn connect(address: SocketAddr, timeout: u32) -> Result<RequestSocket, PipeError> {
+        let duration = Duration::from_millis(timeout as u64);
+        let stream = TcpStream::connect_timeout(&address, duration).map_err(io_error)?;
+        stream.set_read_timeout(Some(duration)).map_err(io_error)?;
+        stream.set_write_timeout(Some(duration)).map_err(io_error)?;
+        stream.set_nodelay(true).map_err(io_error)?;
+        let mut socket = RequestSocket { stream };
+        socket.handshake("REQ", &["REP", "ROUTER"])?;
+        debug!("zeromq connection to {} established", address);
+        Ok(socket)
+    }
+
+    /// Sends one request, prefixed with the empty delimiter frame a REP peer expects
+    pub fn send(&mut self, body: &[u8]) -> Result<(), PipeError> {
+        write_frame(&mut self.stream, FLAG_MORE, &[])?;
+        write_frame(&mut self.stream, 0, body)
+    }
+
+    /// Receives one reply, dropping the delimiter frame and joining multipart bodies
+    pub fn receive(&mut self) -> Result<Vec<u8>, PipeError> {
+        receive_message(&mut self.stream)
+    }
+
+    fn handshake(&mut self, socket_type: &str, accepted_peers: &[&str]) -> Result<(), PipeError> {
+        handshake(&mut self.stream, socket_type, accepted_peers, false)
+    }
+}
+
+fn handshake<S: Read + Write>(
+    stream: &mut S,
+    socket_type: &str,
+    accepted_peers: &[&str],
+    as_server: bool,
+) -> Result<(), PipeError> {
+    stream.write_all(&greeting(as_server)).map_err(io_error)?;
+    let mut peer_greeting = [0u8; GREETING_LEN];
+    stream.read_exact(&mut peer_greeting).map_err(io_error)?;
+    if peer_greeting[0] != 0xFF || peer_greeting[9] != 0x7F {
+        return Err(protocol_error("peer is not a zeromq socket"));
+    }
+    if peer_greeting[10] < 3 {
+        return Err(protocol_error(&format!("unsupported zmtp version {}", peer_greeting[10])));
+    }
+    let mechanism = String::from_utf8_lossy(&peer_greeting[12..32])
+        .trim_end_matches('\0')
+        .to_string();
+    if mechanism != "NULL" {
+        return Err(protocol_error(&format!("unsupported security mechanism {}", mechanism)));
+    }
+
+    write_frame(stream, FLAG_COMMAND, &ready_command(socket_type))?;
+    let (flags, body) = read_frame(stream)?;
+    if flags & FLAG_COMMAND == 0 {
+        return Err(protocol_error("expected READY command from peer"));
+    }
+    let peer_type = parse_ready_command(&body)?;
+    if !accepted_peers.iter().any(|t| t.eq_ignore_ascii_case(&peer_type)) {
+        return Err(protocol_error(&format!("incompatible peer socket type {}", peer_type)));
+    }
+    Ok(())
+}
+
+fn greeting(as_server: bool) -> [u8; GREETING_LEN] {
+    let mut greeting = [0u8; GREETING_LEN];
+    greeting[0] = 0xFF;
+    greeting[9] = 0x7F;
+    greeting[10] = 3;
+    greeting[11] = 0;
+    greeting[12..16].copy_from_slice(b"NULL");
+    greeting[32] = as_server as u8;
+    greeting
+}
+
+fn ready_command(socket_type: &str) -> Vec<u8> {
+    let mut body = Vec::new();
+    body.push(5);
+    body.extend_from_slice(b"READY");
+    body.push(11);
+    body.extend_from_slice(b"Socket-Type");
+    body.extend_from_slice(&(socket_type.len() as u32).to_be_bytes());
+    body.extend_from_slice(socket_type.as_bytes());
+    body
+}
+
+/// Returns the socket type announced in a READY command
+fn parse_ready_command(body: &[u8]) -> Result<String, PipeError> {
+    let name_len = *body.first().ok_or_else(|| protocol_error("empty command"))? as usize;
+    let name = body
+        .get(1..1 + name_len)
+        .ok_or_else(|| protocol_error("truncated command name"))?;
+    if name != b"READY" {
+        return Err(protocol_error(&format!(
+            "unexpected command {}",
+            String::from_utf8_lossy(name)
+        )));
+    }
+    let mut rest = &body[1 + name_len..];
+    while !rest.is_empty() {
+        let key_len = rest[0] as usize;
+        let key = rest
+            .get(1..1 + key_len)
+            .ok_or_else(|| protocol_error("truncated property name"))?;
+        let len_bytes = rest
+            .get(1 + key_len..5 + key_len)
+            .ok_or_else(|| protocol_error("truncated property length"))?;
+        let value_len = u32::from_be_bytes([len_bytes[0], len_bytes[1], len_bytes[2], len_bytes[3]]) as usize;
+        let value = rest
+            .get(5 + key_len..5 + key_len + value_len)
+            .ok_or_else(|| protocol_error("truncated property value"))?;
+        if key.eq_ignore_ascii_case(b"Socket-Type") {
+            return Ok(String::from_utf8_lossy(value).to_string());
+        }
+        rest = &rest[5 + key_len + value_len..];
+    }
+    Err(protocol_error("peer did not announce its socket type"))
+}
+
+fn write_frame<W: Write>(stream: &mut W, flags: u8, body: &[u8]) -> Result<(), PipeError> {
+    let mut frame = Vec::with_capacity(body.len() + 9);
+    if body.len() > u8::MAX as usize {
+        frame.push(flags | FLAG_LONG);
+        frame.extend_from_slice(&(body.len() as u64).to_be_bytes());
+    } else {
+        frame.push(flags);
+        frame.push(body.len() as u8);
+    }
+    frame.extend_from_slice(body);
+    stream.write_all(&frame).map_err(io_error)
+}
+
+fn read_frame<R: Read>(stream: &mut R) -> Result<(u8, Vec<u8>), PipeError> {
+    let mut flags = [0u8; 1];
+    stream.read_exact(&mut flags).map_err(io_error)?;
+    let flags = flags[0];
+    let len = if flags & FLAG_LONG != 0 {
+        let mut len = [0u8; 8];
+        stream.read_exact(&mut len).map_err(io_error)?;
+        u64::from_be_bytes(len)
+    } else {
+        let mut len = [0u8; 1];
+        stream.read_exact(&mut len).map_err(io_error)?;
+        len[0] as u64
+    };
+    if len > 16 * 1024 * 1024 {
+        return Err(protocol_error(&format!("frame of {} bytes exceeds limit", len)));
+    }
+    let mut body = vec![0u8; len as usize];
+    stream.read_exact(&mut body).map_err(io_error)?;
+    Ok((flags, body))
+}
+
+fn receive_message<R: Read>(stream: &mut R) -> Result<Vec<u8>, PipeError> {
+    let mut parts: Vec<Vec<u8>> = Vec::new();
+    loop {
+        let (flags, body) = read_frame(stream)?;
+        if flags & FLAG_COMMAND != 0 {
+            // heartbeats and other commands may be interleaved with messages
+            continue;
+        }
+        parts.push(body);
+        if flags & FLAG_MORE == 0 {
+            break;
+        }
+    }
+    if parts.first().is_some_and(|p| p.is_empty()) {
+        parts.remove(0);
+    }
+    Ok(parts.concat())
+}
+
+fn io_error(e: std::io::Error) -> PipeError {
+    PipeError {
+        message: format!("{}", e),
+        is_timeout: matches!(e.kind(), ErrorKind::TimedOut | ErrorKind::WouldBlock),
+    }
+}
+
+fn protocol_error(message: &str) -> PipeError {
+    PipeError {
+        message: format!("zeromq protocol error: {}", message),
+        is_timeout: false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::TcpListener, thread};
+
+    use super::*;
+
+    /// Answers a single request like a NetMQ ResponseSocket would
+    fn spawn_reply_server(reply: &'static str) -> (SocketAddr, thread::JoinHandle<Vec<u8>>) {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let address = listener.local_addr().unwrap();
+        let handle = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            handshake(&mut stream, "REP", &["REQ"], true).unwrap();
+            let request = receive_message(&mut stream).unwrap();
+            write_frame(&mut stream, FLAG_MORE, &[]).unwrap();
+            write_frame(&mut stream, 0, reply.as_bytes()).unwrap();
+            request
+        });
+        (address, handle)
+    }
+
+    #[test]
+    fn request_reply_over_loopback() {
+        let (address, server) = spawn_reply_server("Ok\nAdmApiDataRow=pong");
+        let response = send_message_and_get_reply("--alive", 2000, address).unwrap();
+        assert_eq!(response.status_code, "Ok");
+        assert_eq!(response.message, "pong");
+        assert_eq!(server.join().unwrap(), b"--alive");
+    }
+
+    #[test]
+    fn long_frames_roundtrip() {
+        let body = "x".repeat(1000);
+        let mut buf = Vec::new();
+        write_frame(&mut buf, FLAG_MORE, &[]).unwrap();
+        write_frame(&mut buf, 0, body.as_bytes()).unwrap();
+        assert_eq!(buf[2], FLAG_LONG);
+        let received = receive_message(&mut std::io::Cursor::new(buf)).unwrap();
+        assert_eq!(received, body.as_bytes());
+    }
+
+    #[test]
+    fn rejects_incompatible_peers() {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let _ = handshake(&mut stream, "PUB", &["SUB"], true);
+        });
+        assert!(RequestSocket::connect(address, 2000).is_err());
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn unreachable_backend_fails_fast() {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let address = listener.local_addr().unwrap();
+        drop(listener);
+        let result = send_message_and_get_reply("--alive", 2000, address);
+        assert!(result.is_err_and(|e| !e.is_timeout));
+    }
+}
@@ -0,0 +1,205 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use log::{debug, error, info, warn};
+use walkdir::WalkDir;
+
+use crate::{extract::sha256_file, OpError};
+
+/// Moves a directory tree by copying it, for when it cannot be renamed
+///
+/// Every file is copied into `<to>.copying` and verified against its source before the copy becomes `to`, and only
+/// then are the source files removed. If a source file cannot be removed, the files removed so far are restored from
+/// the copy and the copy is deleted again, so at any point either the source or the destination is complete.
+pub fn copy_verify_move(from: &Path, to: &Path) -> Result<(), OpError> {
+    if to.exists() {
+        // an empty directory is what a previous fallback leaves behind after removing the source files
+        fs::remove_dir(to).map_err(|e| OpError::new(&format!("destination {} already exists: {}", to.display(), e), false))?;
+    }
+    let (dirs, files) = collect(from)?;
+    let total_size: u64 = files.iter().map(|(_, size)| size).sum();
+    info!(
+        "copying {} files ({} MB) from {} to {}",
+        files.len(),
+        total_size / 1024 / 1024,
+        from.display(),
+        to.display()
+    );
+
+    let mut staging = to.as_os_str().to_owned();
+    staging.push(".copying");
+    let staging_dir = PathBuf::from(staging);
+    if staging_dir.exists() {
+        fs::remove_dir_all(&staging_dir).map_err(|e| {
+            OpError::new(
+                &format!("could not remove stale copy {}: {}", staging_dir.display(), e),
+                false,
+            )
+        })?;
+    }
+    if let Err(op) = copy_tree(from, &staging_dir, &dirs, &files) {
+        if let Err(e) = fs::remove_dir_all(&staging_dir) {
+            warn!("could not remove incomplete copy {}: {}", staging_dir.display(), e);
+        }
+        return Err(op);
+    }
+    fs::rename(&staging_dir, to).map_err(|e| {
+        let _ = fs::remove_dir_all(&staging_dir);
+        OpError::new(&format!("could not move verified copy into place: {}", e), false)
+    })?;
+
+    remove_source(from, to, &files)?;
+    info!("copied and verified {} files", files.len());
+    Ok(())
+}
+
+/// A file relative to the tree root with its size
+type FileEntry = (PathBuf, u64);
+
+/// Returns the directories and the files below `root`, relative to it
+fn collect(root: &Path) -> Result<(Vec<PathBuf>, Vec<FileEntry>), OpError> {
+    let mut dirs = Vec::new();
+    let mut files = Vec::new();
+    for entry in WalkDir::new(root).min_depth(1) {
+        let entry = entry.map_err(|e| OpError::new(&format!("could not read {}: {}", root.display(), e), false))?;
+        let relative = entry.path().strip_prefix(root).unwrap_or(entry.path()).to_path_buf();
+        let file_type = entry.file_type();
+        if file_type.is_dir() {
+            dirs.push(relative);
+        } else if file_type.is_file() {
+            let size = entry.metadata().map(|m| m.len()).unwrap_or(0);
+            files.push((relative, size));
+        } else {
+            return Err(OpError::new(
+                &format!("{} is not a regular file, refusing to copy", entry.path().display()),
+                false,
+            ));
+        }
+    }
+    Ok((dirs, files))
+}
+
+fn copy_tree(from: &Path, to: &Path, dirs: &[PathBuf], files: &[FileEntry]) -> Result<(), OpError> {
+    fs::create_dir_all(to).map_err(|e| OpError::new(&format!("could not create {}: {}", to.display(), e), false))?;
+    for dir in dirs {
+        fs::create_dir_all(to.join(dir))
+            .map_err(|e| OpError::new(&format!("could not create directory {}: {}", dir.display(), e), false))?;
+    }
+    let mut last_percent = 0;
+    for (i, (file, _)) in files.iter().enumerate() {
+        let source = from.join(file);
+        let target = to.join(file);
+        fs::copy(&source, &target).map_err(|e| OpError::new(&format!("could not copy {}: {}", file.display(), e), false))?;
+        let expected = sha256_file(&source)?;
+        let actual = sha256_file(&target)?;
+        if expected != actual {
+            return Err(OpError::new(
+                &format!(
+                    "copy of {} does not match, expected: {}, got: {}",
+                    file.display(),
+                    expected,
+                    actual
+                ),
+                false,
+            ));
+        }
+        let percent = (i + 1) * 100 / files.len();
+        if percent / 10 > last_percent / 10 {
+            info!("copied {} of {} files ({}%)", i + 1, files.len(), percent);
+            last_percent = percent;
+        }
+    }
+    Ok(())
+}
+
+/// Removes the source files one by one, restoring the removed ones from the copy if one of them is in use
+fn remove_source(from: &Path, to: &Path, files: &[FileEntry]) -> Result<(), OpError> {
+    for (i, (file, _)) in files.iter().enumerate() {
+        if let Err(e) = fs::remove_file(from.join(file)) {
+            let op = OpError::new(
+                &format!("could not remove {} after copying: {}", from.join(file).display(), e),
+                false,
+            );
+            error!("{}, restoring {} removed files", op, i);
+            for (restored, _) in &files[..i] {
+                if let Err(e) = fs::copy(to.join(restored), from.join(restored)) {
+                    return Err(OpError::new(
+                        &format!("{}, restoring {} failed: {}", op, from.join(restored).display(), e),
+                        true,
+                    ));
+                }
+            }
+            if let Err(e) = fs::remove_dir_all(to) {
+                warn!("could not remove copy {} after restoring the source: {}", to.display(), e);
+            }
+            return Err(op);
+        }
+    }
+    if let Err(e) = fs::remove_dir_all(from) {
+        warn!("could not remove empty source directories of {}: {}", from.display(), e);
+    } else {
+        debug!("removed {}", from.display());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::test_dir;
+
+    fn populate(dir: &Path) {
+        fs::create_dir_all(dir.join("core").join("runtimes")).unwrap();
+        fs::create_dir_all(dir.join("empty")).unwrap();
+        fs::write(dir.join("core").join("AutoDarkModeSvc.exe"), "svc").unwrap();
+        fs::write(dir.join("core").join("runtimes").join("native.dll"), "native").unwrap();
+        fs::write(dir.join("ui.dll"), "ui").unwrap();
+    }
+
+    #[test]
+    fn copies_verifies_and_removes_source() {
+        let dir = test_dir("copy-move-verify");
+        let from = dir.join("adm-app");
+        let to = dir.join("tmp");
+        populate(&from);
+        fs::create_dir_all(&to).unwrap();
+
+        copy_verify_move(&from, &to).unwrap();
+        assert!(!from.exists());
+        assert!(!dir.join("tmp.copying").exists());
+        assert_eq!(
+            fs::read_to_string(to.join("core").join("runtimes").join("native.dll")).unwrap(),
+            "native"
+        );
+        assert!(to.join("empty").is_dir());
+
+        populate(&from);
+        assert!(copy_verify_move(&from, &to).is_err());
+        assert!(from.join("ui.dll").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn restores_source_when_removal_fails() {
+        let dir = test_dir("copy-move-restore");
+        let from = dir.join("adm-app");
+        let to = dir.join("tmp");
+        populate(&from);
+        let (dirs, mut files) = collect(&from).unwrap();
+        files.sort();
+        copy_tree(&from, &to, &dirs, &files).unwrap();
+        // a file that vanished from the source makes its removal fail after the others were removed
+        files.push((PathBuf::from("missing.dll"), 0));
+
+        assert!(remove_source(&from, &to, &files).is_err());
+        assert!(!to.exists());
+        assert_eq!(
+            fs::read_to_string(from.join("core").join("AutoDarkModeSvc.exe")).unwrap(),
+            "svc"
+        );
+        assert_eq!(fs::read_to_string(from.join("ui.dll")).unwrap(), "ui");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,379 @@
+use std::{cell::Cell, fmt, thread, time::Duration};
+
+use chrono::{DateTime, Local, NaiveTime};
+use log::{info, warn};
+use sysinfo::{ProcessRefreshKind, ProcessesToUpdate, System};
+use windows::Win32::System::SystemInformation::GetTickCount;
+use windows::Win32::UI::Input::KeyboardAndMouse::{GetLastInputInfo, LASTINPUTINFO};
+use windows::Win32::UI::Shell::{SHQueryUserNotificationState, QUNS_BUSY, QUNS_PRESENTATION_MODE, QUNS_RUNNING_D3D_FULL_SCREEN};
+
+const ENABLED_ENV: &str = "ADM_UPDATER_DEFER";
+const IDLE_ENV: &str = "ADM_UPDATER_DEFER_IDLE_SECS";
+const BLOCKLIST_ENV: &str = "ADM_UPDATER_DEFER_BLOCKLIST";
+const WINDOW_ENV: &str = "ADM_UPDATER_DEFER_WINDOW";
+const MAX_ENV: &str = "ADM_UPDATER_DEFER_MAX_MINUTES";
+
+/// Source of the current time, injected so the policy can be tested without waiting
+pub trait Clock {
+    fn now(&self) -> DateTime<Local>;
+    fn sleep(&self, duration: Duration);
+}
+
+/// What the user is currently doing, injected so the policy can be tested without a desktop session
+pub trait SystemProbe {
+    /// Time since the last keyboard or mouse input
+    fn idle_time(&self) -> Duration;
+    /// A fullscreen game or video, or presentation mode
+    fn fullscreen_active(&self) -> bool;
+    /// Executable names of the running processes
+    fn running_processes(&self) -> Vec<String>;
+}
+
+/// When an update may interrupt the user
+///
+/// An update proceeds right away unless a fullscreen application or a blocklisted process is running. While that is
+/// the case it waits until the user has been idle for `idle_threshold`, the maintenance window is reached or
+/// `max_deferral` has passed, whichever comes first.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeferralPolicy {
+    pub enabled: bool,
+    pub idle_threshold: Duration,
+    /// Lowercase executable names without extension, e.g. `powerpnt`
+    pub blocklist: Vec<String>,
+    /// Start and end of the daily maintenance window, the window may span midnight
+    pub maintenance_window: Option<(NaiveTime, NaiveTime)>,
+    pub max_deferral: Duration,
+    pub poll_interval: Duration,
+}
+
+impl Default for DeferralPolicy {
+    fn default() -> Self {
+        DeferralPolicy {
+            enabled: false,
+            idle_threshold: Duration::from_secs(5 * 60),
+            blocklist: Vec::new(),
+            maintenance_window: None,
+            max_deferral: Duration::from_secs(4 * 60 * 60),
+            poll_interval: Duration::from_secs(30),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Decision {
+    Proceed(Reason),
+    /// Holds what the user is busy with
+    Defer(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reason {
+    NotBusy,
+    Idle,
+    MaintenanceWindow,
+    Deadline,
+}
+
+impl fmt::Display for Reason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Reason::NotBusy => write!(f, "no fullscreen or blocklisted application is running"),
+            Reason::Idle => write!(f, "the user is idle"),
+            Reason::MaintenanceWindow => write!(f, "the maintenance window was reached"),
+            Reason::Deadline => write!(f, "the maximum deferral was reached"),
+        }
+    }
+}
+
+impl DeferralPolicy {
+    /// Applies `--defer`, `--defer-idle-secs`, `--defer-blocklist`, `--defer-window` and `--defer-max-minutes` or their
+    /// environment variables to the defaults
+    ///
+    /// The blocklist is comma separated, the window is given as `HH:MM-HH:MM` in local time
+    pub fn resolve(args: &[String], var: impl Fn(&str) -> Option<String>) -> DeferralPolicy {
+        let mut policy = DeferralPolicy::default();
+        let value = |flag: &str, key: &str| {
+            let arg = args.iter().position(|a| a == flag).and_then(|i| args.get(i + 1)).cloned();
+            arg.or_else(|| var(key))
+        };
+        policy.enabled = args.iter().any(|a| a == "--defer") || var(ENABLED_ENV).is_some_and(|v| v == "1" || v == "true");
+        if let Some(secs) = value("--defer-idle-secs", IDLE_ENV) {
+            match secs.parse() {
+                Ok(secs) => policy.idle_threshold = Duration::from_secs(secs),
+                Err(_) => warn!("invalid idle threshold {}, using {}s", secs, policy.idle_threshold.as_secs()),
+            }
+        }
+        if let Some(list) = value("--defer-blocklist", BLOCKLIST_ENV) {
+            policy.blocklist = list
+                .split(',')
+                .map(|name| normalize(name.trim()))
+                .filter(|name| !name.is_empty())
+                .collect();
+        }
+        if let Some(window) = value("--defer-window", WINDOW_ENV) {
+            match parse_window(&window) {
+                Some(window) => policy.maintenance_window = Some(window),
+                None => warn!("invalid maintenance window {}, expected HH:MM-HH:MM", window),
+            }
+        }
+        if let Some(minutes) = value("--defer-max-minutes", MAX_ENV) {
+            match minutes.parse::<u64>() {
+                Ok(minutes) => policy.max_deferral = Duration::from_secs(minutes * 60),
+                Err(_) => warn!(
+                    "invalid maximum deferral {}, using {} minutes",
+                    minutes,
+                    policy.max_deferral.as_secs() / 60
+                ),
+            }
+        }
+        policy
+    }
+
+    /// Decides whether the update may start now, `waited` is how long it has been deferred so far
+    pub fn evaluate(&self, probe: &dyn SystemProbe, now: DateTime<Local>, waited: Duration) -> Decision {
+        if waited >= self.max_deferral {
+            return Decision::Proceed(Reason::Deadline);
+        }
+        if self.in_maintenance_window(now.time()) {
+            return Decision::Proceed(Reason::MaintenanceWindow);
+        }
+        let busy = if probe.fullscreen_active() {
+            Some("a fullscreen application is running".to_string())
+        } else {
+            probe
+                .running_processes()
+                .iter()
+                .find(|p| self.blocklist.contains(&normalize(p)))
+                .map(|p| format!("{} is running", p))
+        };
+        match busy {
+            None => Decision::Proceed(Reason::NotBusy),
+            Some(_) if probe.idle_time() >= self.idle_threshold => Decision::Proceed(Reason::Idle),
+            Some(busy) => Decision::Defer(busy),
+        }
+    }
+
+    fn in_maintenance_window(&self, time: NaiveTime) -> bool {
+        match self.maintenance_window {
+            Some((start, end)) if start <= end => time >= start && time < end,
+            Some((start, end)) => time >= start || time < end,
+            None => false,
+        }
+    }
+
+    /// Blocks until the update may start and returns why it may
+    pub fn wait_until_ready(&self, clock: &dyn Clock, probe: &dyn SystemProbe) -> Reason {
+        if !self.enabled {
+            return Reason::NotBusy;
+        }
+        let started = clock.now();
+        let mut logged = false;
+        loop {
+            let now = clock.now();
+            let waited = (now - started).to_std().unwrap_or_default();
+            match self.evaluate(probe, now, waited) {
+                Decision::Proceed(reason) => {
+                    if logged {
+                        info!("resuming update after {} minutes, {}", waited.as_secs() / 60, reason);
+                    }
+                    return reason;
+                }
+                Decision::Defer(busy) => {
+                    if !logged {
+                        info!(
+                            "deferring update, {}, waiting up to {} minutes",
+                            busy,
+                            self.max_deferral.as_secs() / 60
+                        );
+                        logged = true;
+                    }
+                    clock.sleep(self.poll_interval);
+                }
+            }
+        }
+    }
+}
+
+/// Compares process names without case and extension
+fn normalize(name: &str) -> String {
+    let name = name.to_lowercase();
+    name.strip_suffix(".exe").map(str::to_string).unwrap_or(name)
+}
+
+fn parse_window(window: &str) -> Option<(NaiveTime, NaiveTime)> {
+    let (start, end) = window.split_once('-')?;
+    let start = NaiveTime::parse_from_str(start.trim(), "%H:%M").ok()?;
+    let end = NaiveTime::parse_from_str(end.trim(), "%H:%M").ok()?;
+    Some((start, end))
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Local> {
+        Local::now()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        thread::sleep(duration)
+    }
+}
+
+/// Queries the interactive session of the user running the updater
+#[derive(Default)]
+pub struct DesktopProbe {
+    system: Cell<Option<System>>,
+}
+
+impl SystemProbe for DesktopProbe {
+    fn idle_time(&self) -> Duration {
+        let mut info = LASTINPUTINFO {
+            cbSize: size_of::<LASTINPUTINFO>() as u32,
+            dwTime: 0,
+        };
+        if !unsafe { GetLastInputInfo(&mut info) }.as_bool() {
+            warn!("could not query last input time, assuming the user is active");
+            return Duration::ZERO;
+        }
+        // both tick counts wrap after 49.7 days, the wrapping difference stays correct
+        let now = unsafe { GetTickCount() };
+        Duration::from_millis(now.wrapping_sub(info.dwTime) as u64)
+    }
+
+    fn fullscreen_active(&self) -> bool {
+        match unsafe { SHQueryUserNotificationState() } {
+            Ok(state) => state == QUNS_BUSY || state == QUNS_RUNNING_D3D_FULL_SCREEN || state == QUNS_PRESENTATION_MODE,
+            Err(e) => {
+                warn!("could not query user notification state: {}", e);
+                false
+            }
+        }
+    }
+
+    fn running_processes(&self) -> Vec<String> {
+        let mut system = self.system.take().unwrap_or_default();
+        system.refresh_processes_specifics(ProcessesToUpdate::All, true, ProcessRefreshKind::nothing());
+        let names = system
+            .processes()
+            .values()
+            .map(|p| p.name().to_string_lossy().to_string())
+            .collect();
+        self.system.set(Some(system));
+        names
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use chrono::TimeZone;
+
+    use super::*;
+
+    struct FakeClock {
+        now: RefCell<DateTime<Local>>,
+    }
+
+    impl Clock for FakeClock {
+        fn now(&self) -> DateTime<Local> {
+            *self.now.borrow()
+        }
+
+        fn sleep(&self, duration: Duration) {
+            let mut now = self.now.borrow_mut();
+            *now += chrono::Duration::from_std(duration).unwrap();
+        }
+    }
+
+    #[derive(Default)]
+    struct FakeProbe {
+        idle: Duration,
+        fullscreen: bool,
+        processes: Vec<String>,
+    }
+
+    impl SystemProbe for FakeProbe {
+        fn idle_time(&self) -> Duration {
+            self.idle
+        }
+
+        fn fullscreen_active(&self) -> bool {
+            self.fullscreen
+        }
+
+        fn running_processes(&self) -> Vec<String> {
+            self.processes.clone()
+        }
+    }
+
+    fn at(hour: u32, minute: u32) -> DateTime<Local> {
+        Local.with_ymd_and_hms(2025, 3, 14, hour, minute, 0).unwrap()
+    }
+
+    fn policy(args: &str) -> DeferralPolicy {
+        let args: Vec<String> = args.split_whitespace().map(String::from).collect();
+        DeferralPolicy::resolve(&args, |_| None)
+    }
+
+    #[test]
+    fn evaluates_busy_idle_window_and_deadline() {
+        let policy = policy("updater.exe --defer --defer-blocklist PowerPnt.exe,obs64 --defer-window 22:00-02:00");
+        assert_eq!(policy.blocklist, vec!["powerpnt", "obs64"]);
+        let gaming = FakeProbe {
+            fullscreen: true,
+            ..Default::default()
+        };
+        let presenting = FakeProbe {
+            processes: vec!["explorer.exe".to_string(), "POWERPNT.EXE".to_string()],
+            ..Default::default()
+        };
+        let noon = at(12, 0);
+
+        assert_eq!(
+            policy.evaluate(&FakeProbe::default(), noon, Duration::ZERO),
+            Decision::Proceed(Reason::NotBusy)
+        );
+        assert!(matches!(policy.evaluate(&gaming, noon, Duration::ZERO), Decision::Defer(_)));
+        assert_eq!(
+            policy.evaluate(&presenting, noon, Duration::ZERO),
+            Decision::Defer("POWERPNT.EXE is running".to_string())
+        );
+        let away = FakeProbe {
+            idle: Duration::from_secs(10 * 60),
+            ..presenting
+        };
+        assert_eq!(policy.evaluate(&away, noon, Duration::ZERO), Decision::Proceed(Reason::Idle));
+        assert_eq!(
+            policy.evaluate(&gaming, at(1, 30), Duration::ZERO),
+            Decision::Proceed(Reason::MaintenanceWindow)
+        );
+        assert!(matches!(
+            policy.evaluate(&gaming, at(2, 0), Duration::ZERO),
+            Decision::Defer(_)
+        ));
+        assert_eq!(
+            policy.evaluate(&gaming, noon, policy.max_deferral),
+            Decision::Proceed(Reason::Deadline)
+        );
+    }
+
+    #[test]
+    fn waits_until_the_deadline() {
+        let clock = FakeClock {
+            now: RefCell::new(at(12, 0)),
+        };
+        let gaming = FakeProbe {
+            fullscreen: true,
+            ..Default::default()
+        };
+        assert_eq!(policy("updater.exe").wait_until_ready(&clock, &gaming), Reason::NotBusy);
+        assert_eq!(*clock.now.borrow(), at(12, 0));
+
+        let policy = policy("updater.exe --defer --defer-max-minutes 60");
+        assert_eq!(policy.wait_until_ready(&clock, &gaming), Reason::Deadline);
+        assert_eq!(*clock.now.borrow(), at(13, 0));
+        assert_eq!(policy.wait_until_ready(&clock, &FakeProbe::default()), Reason::NotBusy);
+        assert_eq!(*clock.now.borrow(), at(13, 0));
+    }
+}
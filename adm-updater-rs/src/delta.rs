@@ -0,0 +1,334 @@
+use std::{
+    fs::{self, File},
+    io::{self, BufReader},
+    path::Path,
+};
+
+use log::{debug, info, warn};
+use serde::Deserialize;
+
+use crate::{
+    extensions::APP_DIR,
+    extract::{self, sanitize_entry_name, sha256_file},
+    version::Version,
+    OpError,
+};
+
+pub const DELTA_MANIFEST_FILE: &str = "delta.json";
+/// Largest zstd window accepted when applying patches, patches of large files are created with `--long`
+const MAX_WINDOW_LOG: u32 = 31;
+
+/// Describes how to turn the installed release into the next one
+///
+/// Every file of the target release is listed as unchanged, added or patched, files of the installed release that are
+/// no longer shipped are listed as removed. Paths are relative to the app directory, sources and patches relative to
+/// the unpacked delta package.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeltaManifest {
+    pub base_version: Option<String>,
+    pub target_version: Option<String>,
+    pub files: Vec<DeltaEntry>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct DeltaEntry {
+    pub path: String,
+    #[serde(flatten)]
+    pub action: DeltaAction,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum DeltaAction {
+    /// Copied from the installed release
+    Unchanged { sha256: String },
+    /// Shipped in full with the delta package
+    Added { source: String, sha256: String },
+    /// Rebuilt from the installed file with a zstd patch created by `zstd --patch-from=<old> <new>`
+    #[serde(rename_all = "camelCase")]
+    Patched {
+        patch: String,
+        base_sha256: String,
+        sha256: String,
+    },
+    /// Not part of the target release
+    Removed,
+}
+
+impl DeltaManifest {
+    pub fn load(unpacked_dir: &Path) -> Result<DeltaManifest, OpError> {
+        let path = unpacked_dir.join(DELTA_MANIFEST_FILE);
+        let content =
+            fs::read_to_string(&path).map_err(|e| OpError::new(&format!("could not read delta manifest: {}", e), true))?;
+        serde_json::from_str(&content).map_err(|e| OpError::new(&format!("invalid delta manifest: {}", e), true))
+    }
+}
+
+/// Whether the unpacked update is a delta package instead of a full release
+pub fn is_delta_package(unpacked_dir: &Path) -> bool {
+    unpacked_dir.join(DELTA_MANIFEST_FILE).is_file()
+}
+
+/// Builds the target release from the installed app directory and the delta package
+///
+/// The result is assembled in a staging directory and every file is verified against its hash before the staging
+/// directory becomes `unpacked/adm-app`, which io_v3 then swaps in like a full package.
+/// On failure nothing but the staging directory is touched and it is removed again.
+pub fn apply_delta(unpacked_dir: &Path, installed_dir: &Path, installed_version: Option<&Version>) -> Result<usize, OpError> {
+    let manifest = DeltaManifest::load(unpacked_dir)?;
+    if let Some(base) = &manifest.base_version {
+        let base: Version = base.parse()?;
+        match installed_version {
+            Some(installed) if *installed == base => {}
+            Some(installed) => {
+                return Err(OpError::new(
+                    &format!("delta package requires version {}, installed version is {}", base, installed),
+                    true,
+                ))
+            }
+            None => {
+                return Err(OpError::new(
+                    &format!("delta package requires version {}, installed version is unknown", base),
+                    true,
+                ))
+            }
+        }
+    }
+    info!(
+        "applying delta package {} -> {}",
+        manifest.base_version.as_deref().unwrap_or("any"),
+        manifest.target_version.as_deref().unwrap_or("unknown")
+    );
+
+    let staging_dir = unpacked_dir.join(format!("{}.delta", APP_DIR));
+    if staging_dir.exists() {
+        fs::remove_dir_all(&staging_dir)
+            .map_err(|e| OpError::new(&format!("could not remove stale delta staging directory: {}", e), true))?;
+    }
+    let written = match build_target(&manifest, unpacked_dir, installed_dir, &staging_dir) {
+        Ok(written) => written,
+        Err(op) => {
+            if let Err(e) = fs::remove_dir_all(&staging_dir) {
+                warn!("could not remove delta staging directory: {}", e);
+            }
+            return Err(op);
+        }
+    };
+
+    let target_dir = unpacked_dir.join(APP_DIR);
+    if target_dir.exists() {
+        fs::remove_dir_all(&target_dir)
+            .map_err(|e| OpError::new(&format!("could not remove previously unpacked app directory: {}", e), true))?;
+    }
+    fs::rename(&staging_dir, &target_dir)
+        .map_err(|e| OpError::new(&format!("could not move delta result into place: {}", e), true))?;
+    info!("delta package applied, {} files verified", written);
+    Ok(written)
+}
+
+/// Replaces the unpacked delta package with the full package the service downloaded alongside it
+pub fn extract_full_package(full_archive: &Path, unpacked_dir: &Path) -> Result<usize, OpError> {
+    if !full_archive.is_file() {
+        return Err(OpError::new(
+            &format!("full package {} was not downloaded", full_archive.display()),
+            true,
+        ));
+    }
+    let hash = extract::read_hash_file(full_archive)?;
+    extract::extract_update(full_archive, &hash, unpacked_dir)
+}
+
+fn build_target(
+    manifest: &DeltaManifest,
+    unpacked_dir: &Path,
+    installed_dir: &Path,
+    staging_dir: &Path,
+) -> Result<usize, OpError> {
+    let mut written = 0;
+    for entry in &manifest.files {
+        let relative = sanitize_entry_name(&entry.path)?;
+        let target = staging_dir.join(&relative);
+        let expected = match &entry.action {
+            DeltaAction::Removed => {
+                debug!("dropping removed file {}", relative.display());
+                continue;
+            }
+            DeltaAction::Unchanged { sha256 } => {
+                create_parent(&target)?;
+                copy(&installed_dir.join(&relative), &target)?;
+                sha256
+            }
+            DeltaAction::Added { source, sha256 } => {
+                create_parent(&target)?;
+                copy(&unpacked_dir.join(sanitize_entry_name(source)?), &target)?;
+                sha256
+            }
+            DeltaAction::Patched {
+                patch,
+                base_sha256,
+                sha256,
+            } => {
+                let base = installed_dir.join(&relative);
+                verify(&base, base_sha256, "installed")?;
+                create_parent(&target)?;
+                apply_patch(&base, &unpacked_dir.join(sanitize_entry_name(patch)?), &target)?;
+                sha256
+            }
+        };
+        verify(&target, expected, "patched")?;
+        written += 1;
+    }
+    Ok(written)
+}
+
+/// Decompresses a zstd patch using the installed file as dictionary
+fn apply_patch(base: &Path, patch: &Path, target: &Path) -> Result<(), OpError> {
+    let fail = |e: io::Error| OpError::new(&format!("could not apply patch {}: {}", patch.display(), e), true);
+    let base_content = fs::read(base).map_err(fail)?;
+    let patch_file = File::open(patch).map_err(fail)?;
+    let mut decoder = zstd::stream::read::Decoder::with_dictionary(BufReader::new(patch_file), &base_content).map_err(fail)?;
+    decoder.window_log_max(MAX_WINDOW_LOG).map_err(fail)?;
+    let mut out = File::create(target).map_err(fail)?;
+    io::copy(&mut decoder, &mut out).map_err(fail)?;
+    Ok(())
+}
+
+fn verify(path: &Path, expected: &str, kind: &str) -> Result<(), OpError> {
+    let actual = sha256_file(path)?;
+    if !actual.eq_ignore_ascii_case(expected) {
+        return Err(OpError::new(
+            &format!(
+                "{} file {} does not match, expected: {}, got: {}",
+                kind,
+                path.display(),
+                expected,
+                actual
+            ),
+            true,
+        ));
+    }
+    Ok(())
+}
+
+fn copy(from: &Path, to: &Path) -> Result<(), OpError> {
+    fs::copy(from, to)
+        .map(|_| ())
+        .map_err(|e| OpError::new(&format!("could not copy {}: {}", from.display(), e), true))
+}
+
+fn create_parent(path: &Path) -> Result<(), OpError> {
+    match path.parent() {
+        Some(parent) => fs::create_dir_all(parent)
+            .map_err(|e| OpError::new(&format!("could not create directory {}: {}", parent.display(), e), true)),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Write, path::PathBuf};
+
+    use super::*;
+    use crate::test_support::test_dir;
+
+    fn hash(content: &[u8]) -> String {
+        use sha2::{Digest, Sha256};
+        hex::encode(Sha256::digest(content))
+    }
+
+    fn create_patch(base: &[u8], target: &[u8], path: &Path) {
+        let mut encoder = zstd::stream::write::Encoder::with_dictionary(File::create(path).unwrap(), 19, base).unwrap();
+        encoder.write_all(target).unwrap();
+        encoder.finish().unwrap();
+    }
+
+    /// An installed release with an unchanged, a patched and a removed file, and a delta package against it
+    fn setup(dir: &Path) -> (PathBuf, PathBuf, Vec<u8>) {
+        let installed = dir.join("adm-app");
+        fs::create_dir_all(installed.join("core")).unwrap();
+        let old_dll: Vec<u8> = (0..20_000u32).flat_map(|i| i.to_le_bytes()).collect();
+        let mut new_dll = old_dll.clone();
+        new_dll[4_000..4_010].copy_from_slice(b"0123456789");
+        fs::write(installed.join("core").join("AutoDarkModeSvc.exe"), "svc").unwrap();
+        fs::write(installed.join("core").join("AutoDarkModeLib.dll"), &old_dll).unwrap();
+        fs::write(installed.join("old.dll"), "old").unwrap();
+
+        let unpacked = dir.join("unpacked");
+        fs::create_dir_all(unpacked.join("patches")).unwrap();
+        fs::create_dir_all(unpacked.join("files")).unwrap();
+        create_patch(&old_dll, &new_dll, &unpacked.join("patches").join("AutoDarkModeLib.dll.zst"));
+        fs::write(unpacked.join("files").join("new.dll"), "new").unwrap();
+        let manifest = serde_json::json!({
+            "baseVersion": "11.0.0.23",
+            "targetVersion": "11.0.0.24",
+            "files": [
+                {"path": "core/AutoDarkModeSvc.exe", "action": "unchanged", "sha256": hash(b"svc")},
+                {"path": "core/AutoDarkModeLib.dll", "action": "patched", "patch": "patches/AutoDarkModeLib.dll.zst",
+                 "baseSha256": hash(&old_dll), "sha256": hash(&new_dll)},
+                {"path": "new.dll", "action": "added", "source": "files/new.dll", "sha256": hash(b"new")},
+                {"path": "old.dll", "action": "removed"}
+            ]
+        });
+        fs::write(unpacked.join(DELTA_MANIFEST_FILE), manifest.to_string()).unwrap();
+        (installed, unpacked, new_dll)
+    }
+
+    #[test]
+    fn applies_delta_into_unpacked_app_dir() {
+        let dir = test_dir("delta-apply");
+        let (installed, unpacked, new_dll) = setup(&dir);
+        let version: Version = "11.0.0.23".parse().unwrap();
+        assert!(is_delta_package(&unpacked));
+
+        assert_eq!(apply_delta(&unpacked, &installed, Some(&version)).unwrap(), 3);
+        let result = unpacked.join(APP_DIR);
+        assert_eq!(fs::read(result.join("core").join("AutoDarkModeLib.dll")).unwrap(), new_dll);
+        assert_eq!(sha256_file(&result.join("new.dll")).unwrap(), hash(b"new"));
+        assert!(result.join("core").join("AutoDarkModeSvc.exe").is_file());
+        assert!(!result.join("old.dll").exists());
+        assert!(!unpacked.join("adm-app.delta").exists());
+        // the installed release is left alone, io_v3 swaps it later
+        assert!(installed.join("old.dll").is_file());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn falls_back_to_full_package() {
+        let dir = test_dir("delta-fallback");
+        let (installed, unpacked, _) = setup(&dir);
+        let full_archive = dir.join(extract::FULL_ARCHIVE_FILE);
+        assert!(extract_full_package(&full_archive, &unpacked).is_err());
+        assert!(is_delta_package(&unpacked));
+
+        let mut writer = zip::ZipWriter::new(File::create(&full_archive).unwrap());
+        writer
+            .start_file("adm-app/core/AutoDarkModeSvc.exe", zip::write::SimpleFileOptions::default())
+            .unwrap();
+        writer.write_all(b"full svc").unwrap();
+        writer.finish().unwrap();
+        fs::write(dir.join("update.full.zip.sha256"), sha256_file(&full_archive).unwrap()).unwrap();
+
+        assert!(apply_delta(&unpacked, &installed, None).is_err());
+        assert_eq!(extract_full_package(&full_archive, &unpacked).unwrap(), 1);
+        assert!(!is_delta_package(&unpacked));
+        let service = unpacked.join(APP_DIR).join("core").join("AutoDarkModeSvc.exe");
+        assert_eq!(fs::read_to_string(service).unwrap(), "full svc");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn refuses_mismatching_base() {
+        let dir = test_dir("delta-mismatch");
+        let (installed, unpacked, _) = setup(&dir);
+        let version: Version = "11.0.0.23".parse().unwrap();
+        assert!(apply_delta(&unpacked, &installed, Some(&"11.0.0.22".parse().unwrap())).is_err());
+        assert!(apply_delta(&unpacked, &installed, None).is_err());
+
+        fs::write(installed.join("core").join("AutoDarkModeLib.dll"), "modified locally").unwrap();
+        assert!(apply_delta(&unpacked, &installed, Some(&version)).is_err());
+        assert!(!unpacked.join(APP_DIR).exists());
+        assert!(!unpacked.join("adm-app.delta").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
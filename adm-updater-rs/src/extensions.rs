@@ -1,6 +1,9 @@
 #![allow(dead_code)]
 
-use std::path::PathBuf;
+use std::{
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
 
 #[allow(unused_imports)]
 use log::error;
@@ -10,9 +13,32 @@ pub static APP_EXE: &'static str = "AutoDarkModeApp.exe";
 pub static SHELL_EXE: &'static str = "AutoDarkModeShell.exe";
 pub static APP_DIR: &'static str = "adm-app";
 
+/// Set when the updater runs as a relaunched copy outside of its installation directory
+static ASSEMBLY_DIR_OVERRIDE: OnceLock<PathBuf> = OnceLock::new();
+
+/// Makes all paths resolve relative to the given updater directory instead of the running executable
+pub fn set_assembly_dir_override(path: PathBuf) {
+    if ASSEMBLY_DIR_OVERRIDE.set(path).is_err() {
+        error!("assembly dir override was already set, ignoring");
+    }
+}
+
+/// Returns the overridden execution directory, applying the same root dir check as for the real one
+fn get_assembly_dir_override() -> Option<PathBuf> {
+    let path = ASSEMBLY_DIR_OVERRIDE.get()?;
+    if path.parent().is_none() {
+        error!("adm updater must not be in root dir. this is forbidden, panicking!");
+        panic!("adm executed in root");
+    }
+    Some(path.clone())
+}
+
 #[cfg(debug_assertions)]
 /// Returns the execution directory the updater resides in
 pub fn get_assembly_dir() -> PathBuf {
+    if let Some(path) = get_assembly_dir_override() {
+        return path;
+    }
     let path = PathBuf::from(r"F:\Programs\ADM-Test-Environment\adm-updater");
     //let path = PathBuf::from(r"F:\\");
     let parent = path.parent();
@@ -26,6 +52,9 @@ pub fn get_assembly_dir() -> PathBuf {
 #[cfg(not(debug_assertions))]
 /// Returns the execution directory the updater resides in
 pub fn get_assembly_dir() -> PathBuf {
+    if let Some(path) = get_assembly_dir_override() {
+        return path;
+    }
     let dir = match std::env::current_exe() {
         Ok(path) => {
             let exec_dir = path.parent();
@@ -96,6 +125,12 @@ pub fn get_adm_app_dir() -> PathBuf {
     path
 }
 
+/// Whether the path is inside the directory, paths on windows are case insensitive
+pub fn is_below(path: &Path, dir: &Path) -> bool {
+    let lower = |p: &Path| PathBuf::from(p.to_string_lossy().to_lowercase());
+    lower(path).starts_with(lower(dir))
+}
+
 #[cfg(test)]
 mod tests {
     use windows::Win32::System::Console::{AttachConsole, ATTACH_PARENT_PROCESS};
@@ -0,0 +1,260 @@
+use std::{
+    fs::{self, File},
+    io,
+    path::{Component, Path, PathBuf},
+    time::SystemTime,
+};
+
+use chrono::{Local, NaiveDate};
+use log::{debug, info, warn};
+use sha2::{Digest, Sha256};
+use zip::ZipArchive;
+
+use crate::OpError;
+
+pub const ARCHIVE_FILE: &str = "update.zip";
+/// The full package the service downloads alongside a delta package, used if the delta cannot be applied
+pub const FULL_ARCHIVE_FILE: &str = "update.full.zip";
+
+/// Reads the expected hash from the `<archive>.sha256` file written next to the downloaded archive
+///
+/// Like the published `.sha256` files, the hash is the first word, optionally followed by the file name
+pub fn read_hash_file(archive: &Path) -> Result<String, OpError> {
+    let mut path = archive.as_os_str().to_owned();
+    path.push(".sha256");
+    let path = PathBuf::from(path);
+    let content = fs::read_to_string(&path)
+        .map_err(|e| OpError::new(&format!("could not read archive hash from {}: {}", path.display(), e), true))?;
+    content
+        .split_whitespace()
+        .next()
+        .map(str::to_string)
+        .ok_or_else(|| OpError::new(&format!("archive hash file {} is empty", path.display()), true))
+}
+
+/// Computes the hex encoded sha256 of a file
+pub fn sha256_file(path: &Path) -> Result<String, OpError> {
+    let mut file = File::open(path).map_err(|e| OpError::new(&format!("could not open {}: {}", path.display(), e), true))?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher).map_err(|e| OpError::new(&format!("could not hash {}: {}", path.display(), e), true))?;
+    Ok(hex::encode(hasher.finalize()))
+}
+
+/// Computes the sha256 of the archive and compares it to the expected hex encoded hash
+pub fn verify_sha256(archive: &Path, expected: &str) -> Result<(), OpError> {
+    let actual = sha256_file(archive)?;
+    if !actual.eq_ignore_ascii_case(expected.trim()) {
+        return Err(OpError::new(
+            &format!("update archive hash mismatch, expected: {}, got: {}", expected.trim(), actual),
+            true,
+        ));
+    }
+    debug!("update archive hash verified: {}", actual);
+    Ok(())
+}
+
+/// Verifies the archive and extracts it into the target directory
+///
+/// Entries are streamed into a staging directory next to the target, which replaces the target once everything was
+/// extracted. A failed extraction removes the staging directory and leaves the target untouched.
+pub fn extract_update(archive: &Path, expected_hash: &str, target_dir: &Path) -> Result<usize, OpError> {
+    verify_sha256(archive, expected_hash)?;
+    let mut staging = target_dir.as_os_str().to_owned();
+    staging.push(".staging");
+    let staging_dir = PathBuf::from(staging);
+    if staging_dir.exists() {
+        warn!("removing staging directory of a previous extraction");
+        fs::remove_dir_all(&staging_dir)
+            .map_err(|e| OpError::new(&format!("could not remove stale staging directory: {}", e), true))?;
+    }
+
+    let extracted = match extract_archive(archive, &staging_dir) {
+        Ok(count) => count,
+        Err(op) => {
+            if let Err(e) = fs::remove_dir_all(&staging_dir) {
+                warn!("could not remove staging directory after failed extraction: {}", e);
+            }
+            return Err(op);
+        }
+    };
+    if target_dir.exists() {
+        fs::remove_dir_all(target_dir)
+            .map_err(|e| OpError::new(&format!("could not remove previously unpacked update files: {}", e), true))?;
+    }
+    fs::rename(&staging_dir, target_dir)
+        .map_err(|e| OpError::new(&format!("could not move extracted update files into place: {}", e), true))?;
+    info!("extracted {} files to {}", extracted, target_dir.display());
+    Ok(extracted)
+}
+
+/// Extracts all entries of the archive below the target directory and returns the number of files written
+///
+/// Entries with absolute paths, drive prefixes or `..` components and symbolic links are rejected
+pub fn extract_archive(archive: &Path, target_dir: &Path) -> Result<usize, OpError> {
+    let file = File::open(archive)
+        .map_err(|e| OpError::new(&format!("could not open update archive {}: {}", archive.display(), e), true))?;
+    let mut zip = ZipArchive::new(file).map_err(|e| OpError::new(&format!("invalid update archive: {}", e), true))?;
+    fs::create_dir_all(target_dir).map_err(|e| OpError::new(&format!("could not create extraction directory: {}", e), true))?;
+
+    let mut extracted = 0;
+    for i in 0..zip.len() {
+        let mut entry = zip
+            .by_index(i)
+            .map_err(|e| OpError::new(&format!("could not read entry {} of update archive: {}", i, e), true))?;
+        let relative = sanitize_entry_name(entry.name())?;
+        if entry.is_symlink() {
+            return Err(OpError::new(
+                &format!("update archive contains symbolic link {}, refusing to extract", entry.name()),
+                true,
+            ));
+        }
+        let path = target_dir.join(&relative);
+        if entry.is_dir() {
+            fs::create_dir_all(&path)
+                .map_err(|e| OpError::new(&format!("could not create directory {}: {}", relative.display(), e), true))?;
+            continue;
+        }
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|e| OpError::new(&format!("could not create directory for {}: {}", relative.display(), e), true))?;
+        }
+        let mut out =
+            File::create(&path).map_err(|e| OpError::new(&format!("could not create {}: {}", relative.display(), e), true))?;
+        io::copy(&mut entry, &mut out)
+            .map_err(|e| OpError::new(&format!("could not extract {}: {}", relative.display(), e), true))?;
+        if let Some(modified) = entry.last_modified().and_then(to_system_time) {
+            if let Err(e) = out.set_modified(modified) {
+                debug!("could not preserve timestamp of {}: {}", relative.display(), e);
+            }
+        }
+        extracted += 1;
+    }
+    Ok(extracted)
+}
+
+/// Turns an archive entry name into a relative path that cannot leave the extraction directory
+pub fn sanitize_entry_name(name: &str) -> Result<PathBuf, OpError> {
+    let reject = |reason: &str| OpError::new(&format!("update entry {:?} {}, refusing to extract", name, reason), true);
+    let normalized = name.replace('\\', "/");
+    if normalized.starts_with('/') || normalized.contains(':') {
+        return Err(reject("has an absolute path"));
+    }
+    let mut relative = PathBuf::new();
+    for component in Path::new(&normalized).components() {
+        match component {
+            Component::Normal(part) => relative.push(part),
+            Component::CurDir => {}
+            Component::ParentDir => return Err(reject("points outside of the extraction directory")),
+            Component::RootDir | Component::Prefix(_) => return Err(reject("has an absolute path")),
+        }
+    }
+    if relative.as_os_str().is_empty() {
+        return Err(reject("has an empty path"));
+    }
+    Ok(relative)
+}
+
+/// Zip timestamps are stored in local time without a time zone
+fn to_system_time(time: zip::DateTime) -> Option<SystemTime> {
+    let local = NaiveDate::from_ymd_opt(time.year() as i32, time.month() as u32, time.day() as u32)?
+        .and_hms_opt(time.hour() as u32, time.minute() as u32, time.second() as u32)?
+        .and_local_timezone(Local)
+        .earliest()?;
+    Some(local.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use zip::{write::SimpleFileOptions, CompressionMethod, ZipWriter};
+
+    use super::*;
+    use crate::test_support::test_dir;
+
+    fn write_archive(path: &Path, entries: &[(&str, &str)], symlink: Option<(&str, &str)>) -> String {
+        let mut writer = ZipWriter::new(File::create(path).unwrap());
+        let modified = zip::DateTime::from_date_and_time(2024, 5, 17, 12, 30, 10).unwrap();
+        let options = SimpleFileOptions::default()
+            .compression_method(CompressionMethod::Stored)
+            .last_modified_time(modified);
+        for (name, content) in entries {
+            writer.start_file(*name, options).unwrap();
+            writer.write_all(content.as_bytes()).unwrap();
+        }
+        if let Some((name, target)) = symlink {
+            writer.add_symlink(name, target, options).unwrap();
+        }
+        writer.finish().unwrap();
+        hex::encode(Sha256::digest(fs::read(path).unwrap()))
+    }
+
+    #[test]
+    fn extracts_into_place_and_preserves_timestamps() {
+        let dir = test_dir("extract-ok");
+        let archive = dir.join(ARCHIVE_FILE);
+        let hash = write_archive(
+            &archive,
+            &[("adm-app/core/AutoDarkModeSvc.exe", "svc"), ("manifest.json", "{}")],
+            None,
+        );
+        let target = dir.join("unpacked");
+        fs::create_dir_all(&target).unwrap();
+        fs::write(target.join("stale.txt"), "old").unwrap();
+
+        assert_eq!(extract_update(&archive, &hash.to_uppercase(), &target).unwrap(), 2);
+        let service = target.join("adm-app").join("core").join("AutoDarkModeSvc.exe");
+        assert_eq!(fs::read_to_string(&service).unwrap(), "svc");
+        assert!(!target.join("stale.txt").exists());
+        let modified: chrono::DateTime<Local> = fs::metadata(&service).unwrap().modified().unwrap().into();
+        assert_eq!(modified.format("%Y-%m-%d %H:%M:%S").to_string(), "2024-05-17 12:30:10");
+
+        fs::write(
+            dir.join(format!("{}.sha256", ARCHIVE_FILE)),
+            format!("{}  {}\n", hash, ARCHIVE_FILE),
+        )
+        .unwrap();
+        assert_eq!(read_hash_file(&archive).unwrap(), hash);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn rejects_bad_hashes_and_unsafe_entries() {
+        let dir = test_dir("extract-reject");
+        let target = dir.join("unpacked");
+        let archive = dir.join(ARCHIVE_FILE);
+        write_archive(&archive, &[("adm-app/a.dll", "a")], None);
+        assert!(extract_update(&archive, &"0".repeat(64), &target).is_err());
+        assert!(!target.exists());
+
+        for name in [
+            "../evil.dll",
+            "adm-app/../../evil.dll",
+            "/evil.dll",
+            "C:/evil.dll",
+            "..\\evil.dll",
+        ] {
+            let hash = write_archive(&archive, &[("adm-app/a.dll", "a"), (name, "evil")], None);
+            assert!(extract_update(&archive, &hash, &target).is_err(), "{} was extracted", name);
+            assert!(!target.exists());
+            assert!(!dir.join("unpacked.staging").exists());
+            assert!(!dir.join("evil.dll").exists());
+        }
+
+        let hash = write_archive(&archive, &[("adm-app/a.dll", "a")], Some(("adm-app/link", "../../evil")));
+        assert!(extract_update(&archive, &hash, &target).is_err());
+        assert!(!target.exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn sanitizes_entry_names() {
+        assert_eq!(
+            sanitize_entry_name("adm-app/./core/x.dll").unwrap(),
+            Path::new("adm-app").join("core").join("x.dll")
+        );
+        assert_eq!(sanitize_entry_name("adm-app\\ui\\").unwrap(), Path::new("adm-app").join("ui"));
+        assert!(sanitize_entry_name("").is_err());
+        assert!(sanitize_entry_name("\\\\server\\share\\x.dll").is_err());
+    }
+}
@@ -0,0 +1,315 @@
+use std::path::{Path, PathBuf};
+
+use log::{info, warn};
+use sysinfo::{Pid, ProcessRefreshKind, ProcessesToUpdate, System, UpdateKind};
+use walkdir::WalkDir;
+use windows::core::{HSTRING, PCWSTR, PWSTR};
+use windows::Win32::Foundation::ERROR_MORE_DATA;
+use windows::Win32::System::RestartManager::{
+    RmEndSession, RmGetList, RmRegisterResources, RmStartSession, CCH_RM_SESSION_KEY, RM_PROCESS_INFO,
+};
+use windows::Win32::UI::WindowsAndMessaging::{MessageBoxW, IDYES, MB_ICONWARNING, MB_SETFOREGROUND, MB_TOPMOST, MB_YESNO};
+
+use crate::{extensions::is_below, OpError};
+
+/// A process holding a handle on one of the queried files
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LockingProcess {
+    pub pid: u32,
+    /// Executable name, e.g. `AutoDarkModeSvc.exe`
+    pub image: String,
+    /// Display name reported by the system, e.g. the service or window title
+    pub app_name: String,
+    /// Full path of the executable, if it could be read
+    pub exe: Option<PathBuf>,
+    /// Whether the process runs as the same user as the updater
+    pub same_user: bool,
+}
+
+impl LockingProcess {
+    /// Auto dark mode processes of the current user started from the installation may be stopped by the updater,
+    /// the running updater itself excluded
+    pub fn belongs_to_adm(&self, install_dir: &Path) -> bool {
+        self.pid != std::process::id() && self.same_user && self.exe.as_deref().is_some_and(|exe| is_below(exe, install_dir))
+    }
+}
+
+/// Finds and stops processes that hold files open
+pub trait LockProvider {
+    fn locking_processes(&self, files: &[PathBuf]) -> Result<Vec<LockingProcess>, OpError>;
+    /// Asks the user whether the auto dark mode processes may be stopped
+    fn confirm_stop(&self, processes: &[&LockingProcess]) -> bool;
+    fn terminate(&self, process: &LockingProcess) -> Result<(), OpError>;
+}
+
+/// Logs which processes hold files below `dir` and offers to stop the ones that belong to the auto dark mode
+/// installation in `install_dir`
+///
+/// Returns true if a process was stopped, in which case the failed operation is worth another attempt
+pub fn release_adm_locks(provider: &dyn LockProvider, dir: &Path, install_dir: &Path) -> bool {
+    let files: Vec<PathBuf> = WalkDir::new(dir)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file())
+        .map(|e| e.into_path())
+        .collect();
+    if files.is_empty() {
+        return false;
+    }
+    let processes = match provider.locking_processes(&files) {
+        Ok(processes) => processes,
+        Err(e) => {
+            warn!("could not determine which processes lock files in {}: {}", dir.display(), e);
+            return false;
+        }
+    };
+    if processes.is_empty() {
+        info!(
+            "no process holds files in {}, the lock may have been released already",
+            dir.display()
+        );
+        return false;
+    }
+
+    let mut adm_processes = Vec::new();
+    for process in &processes {
+        if process.belongs_to_adm(install_dir) {
+            info!(
+                "{} (pid {}) of this installation holds files in {}",
+                process.image,
+                process.pid,
+                dir.display()
+            );
+            adm_processes.push(process);
+        } else {
+            warn!(
+                "{} (pid {}, {}) holds files in {}, close it or exclude the directory from scanning and retry the update",
+                process.image,
+                process.pid,
+                process.app_name,
+                dir.display()
+            );
+        }
+    }
+    if adm_processes.is_empty() {
+        return false;
+    }
+    if !provider.confirm_stop(&adm_processes) {
+        info!("stopping auto dark mode processes declined");
+        return false;
+    }
+
+    let mut stopped = false;
+    for process in adm_processes {
+        info!("stopping {} (pid {})", process.image, process.pid);
+        match provider.terminate(process) {
+            Ok(()) => stopped = true,
+            Err(e) => warn!("could not stop {} (pid {}): {}", process.image, process.pid, e),
+        }
+    }
+    stopped
+}
+
+/// Queries the Restart Manager, which knows about every process with an open handle on the registered files
+pub struct RestartManager;
+
+impl LockProvider for RestartManager {
+    fn locking_processes(&self, files: &[PathBuf]) -> Result<Vec<LockingProcess>, OpError> {
+        let mut session = 0u32;
+        let mut key = [0u16; CCH_RM_SESSION_KEY as usize + 1];
+        unsafe { RmStartSession(&mut session, None, PWSTR(key.as_mut_ptr())) }
+            .ok()
+            .map_err(|e| rm_error("session", e))?;
+        let result = query_session(session, files);
+        unsafe {
+            let _ = RmEndSession(session);
+        }
+        result
+    }
+
+    fn confirm_stop(&self, processes: &[&LockingProcess]) -> bool {
+        let names: Vec<String> = processes.iter().map(|p| format!("{} (pid {})", p.image, p.pid)).collect();
+        let text = HSTRING::from(format!(
+            "The update cannot continue because these Auto Dark Mode processes still use its files:\n\n{}\n\nStop them and continue the update?",
+            names.join("\n")
+        ));
+        let result = unsafe {
+            MessageBoxW(
+                None,
+                &text,
+                &HSTRING::from("Auto Dark Mode Updater"),
+                MB_YESNO | MB_ICONWARNING | MB_TOPMOST | MB_SETFOREGROUND,
+            )
+        };
+        result == IDYES
+    }
+
+    fn terminate(&self, process: &LockingProcess) -> Result<(), OpError> {
+        let pid = Pid::from_u32(process.pid);
+        let mut s = System::new();
+        s.refresh_processes_specifics(ProcessesToUpdate::Some(&[pid]), true, ProcessRefreshKind::nothing());
+        match s.process(pid) {
+            Some(p) if p.kill() => Ok(()),
+            Some(_) => Err(OpError::new("kill signal was not delivered", false)),
+            None => Ok(()),
+        }
+    }
+}
+
+fn query_session(session: u32, files: &[PathBuf]) -> Result<Vec<LockingProcess>, OpError> {
+    let names: Vec<HSTRING> = files.iter().map(|f| HSTRING::from(f.as_os_str())).collect();
+    let names: Vec<PCWSTR> = names.iter().map(|n| PCWSTR(n.as_ptr())).collect();
+    unsafe { RmRegisterResources(session, Some(&names), None, None) }
+        .ok()
+        .map_err(|e| rm_error("registration", e))?;
+
+    let mut infos: Vec<RM_PROCESS_INFO> = Vec::new();
+    loop {
+        let mut needed = 0u32;
+        let mut count = infos.len() as u32;
+        let mut reasons = 0u32;
+        let status = unsafe { RmGetList(session, &mut needed, &mut count, Some(infos.as_mut_ptr()), &mut reasons) };
+        if status == ERROR_MORE_DATA {
+            infos.resize(needed as usize, RM_PROCESS_INFO::default());
+            continue;
+        }
+        status.ok().map_err(|e| rm_error("query", e))?;
+        infos.truncate(count as usize);
+        return Ok(infos.iter().map(to_locking_process).collect());
+    }
+}
+
+fn rm_error(what: &str, e: windows::core::Error) -> OpError {
+    OpError::new(&format!("restart manager {} failed: {}", what, e), false)
+}
+
+fn to_locking_process(info: &RM_PROCESS_INFO) -> LockingProcess {
+    let text = |buffer: &[u16]| {
+        let len = buffer.iter().position(|c| *c == 0).unwrap_or(buffer.len());
+        String::from_utf16_lossy(&buffer[..len])
+    };
+    let pid = info.Process.dwProcessId;
+    let app_name = text(&info.strAppName);
+
+    let own_pid = Pid::from_u32(std::process::id());
+    let sys_pid = Pid::from_u32(pid);
+    let mut s = System::new();
+    s.refresh_processes_specifics(
+        ProcessesToUpdate::Some(&[sys_pid, own_pid]),
+        true,
+        ProcessRefreshKind::nothing()
+            .with_user(UpdateKind::OnlyIfNotSet)
+            .with_exe(UpdateKind::OnlyIfNotSet),
+    );
+    let own_user = s.process(own_pid).and_then(|p| p.user_id());
+    match s.process(sys_pid) {
+        Some(p) => LockingProcess {
+            pid,
+            image: p.name().to_string_lossy().to_string(),
+            app_name,
+            exe: p.exe().map(Path::to_path_buf),
+            same_user: own_user.is_some() && p.user_id() == own_user,
+        },
+        None => LockingProcess {
+            pid,
+            image: app_name.clone(),
+            app_name,
+            exe: None,
+            same_user: false,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, fs};
+
+    use super::*;
+    use crate::test_support::test_dir;
+
+    struct FakeLocks {
+        processes: Vec<LockingProcess>,
+        confirm: bool,
+        offered: RefCell<Vec<u32>>,
+        terminated: RefCell<Vec<u32>>,
+        queried: RefCell<usize>,
+    }
+
+    impl FakeLocks {
+        fn new(processes: Vec<LockingProcess>, confirm: bool) -> FakeLocks {
+            FakeLocks {
+                processes,
+                confirm,
+                offered: RefCell::new(Vec::new()),
+                terminated: RefCell::new(Vec::new()),
+                queried: RefCell::new(0),
+            }
+        }
+    }
+
+    impl LockProvider for FakeLocks {
+        fn locking_processes(&self, files: &[PathBuf]) -> Result<Vec<LockingProcess>, OpError> {
+            *self.queried.borrow_mut() = files.len();
+            Ok(self.processes.clone())
+        }
+
+        fn confirm_stop(&self, processes: &[&LockingProcess]) -> bool {
+            *self.offered.borrow_mut() = processes.iter().map(|p| p.pid).collect();
+            self.confirm
+        }
+
+        fn terminate(&self, process: &LockingProcess) -> Result<(), OpError> {
+            self.terminated.borrow_mut().push(process.pid);
+            Ok(())
+        }
+    }
+
+    fn process(pid: u32, exe: &Path, same_user: bool) -> LockingProcess {
+        let image = exe.file_name().unwrap().to_string_lossy().to_string();
+        LockingProcess {
+            pid,
+            app_name: image.trim_end_matches(".exe").to_string(),
+            image,
+            exe: Some(exe.to_path_buf()),
+            same_user,
+        }
+    }
+
+    #[test]
+    fn offers_to_stop_only_processes_of_this_installation() {
+        let dir = test_dir("file-locks");
+        let app_dir = dir.join("adm-app");
+        fs::create_dir_all(app_dir.join("core")).unwrap();
+        fs::write(app_dir.join("core").join("AutoDarkModeSvc.exe"), "svc").unwrap();
+        fs::write(app_dir.join("ui.dll"), "ui").unwrap();
+        let other_install = Path::new("C:/Other/adm-app/AutoDarkModeShell.exe");
+
+        let processes = vec![
+            process(10, Path::new("C:/ProgramData/Defender/MsMpEng.exe"), false),
+            process(11, &app_dir.join("AutoDarkModeShell.exe"), true),
+            process(
+                std::process::id(),
+                &dir.join("adm-updater").join("AutoDarkModeUpdater.exe"),
+                true,
+            ),
+            // another user's instance and another installation are left alone
+            process(12, &app_dir.join("core").join("AutoDarkModeSvc.exe"), false),
+            process(13, other_install, true),
+        ];
+        let fake = FakeLocks::new(processes.clone(), true);
+        assert!(release_adm_locks(&fake, &app_dir, &dir));
+        assert_eq!(*fake.queried.borrow(), 2);
+        assert_eq!(*fake.offered.borrow(), vec![11]);
+        assert_eq!(*fake.terminated.borrow(), vec![11]);
+
+        let fake = FakeLocks::new(processes, false);
+        assert!(!release_adm_locks(&fake, &app_dir, &dir));
+        assert_eq!(*fake.offered.borrow(), vec![11]);
+        assert!(fake.terminated.borrow().is_empty());
+
+        let fake = FakeLocks::new(vec![process(10, Path::new("C:/MsMpEng.exe"), false)], true);
+        assert!(!release_adm_locks(&fake, &app_dir, &dir));
+        assert!(fake.offered.borrow().is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
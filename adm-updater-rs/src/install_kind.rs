@@ -0,0 +1,150 @@
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use log::{debug, warn};
+
+use crate::regedit::{self, InstallScope};
+
+/// How auto dark mode was installed, determined once before the update starts
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InstallKind {
+    /// Installed by the Inno Setup installer for the current user
+    InnoPerUser { sid: String },
+    /// Installed by the Inno Setup installer for all users
+    InnoPerMachine { wow64: bool },
+    /// Unpacked without an installer, nothing outside the working directory belongs to it
+    Portable,
+    /// A debug build or a binary run from a cargo target directory
+    DevBuild,
+}
+
+impl InstallKind {
+    /// Maps the registry scope of the uninstall entry, if one was found, to an installation kind
+    pub fn classify(dev_build: bool, scope: Option<InstallScope>) -> InstallKind {
+        if dev_build {
+            return InstallKind::DevBuild;
+        }
+        match scope {
+            Some(InstallScope::PerUser(sid)) => InstallKind::InnoPerUser { sid },
+            Some(InstallScope::PerMachine) => InstallKind::InnoPerMachine { wow64: false },
+            Some(InstallScope::PerMachineWow64) => InstallKind::InnoPerMachine { wow64: true },
+            None => InstallKind::Portable,
+        }
+    }
+
+    /// The uninstall entry that has to be kept in sync, None for installations without one
+    pub fn registry_scope(&self) -> Option<InstallScope> {
+        match self {
+            InstallKind::InnoPerUser { sid } => Some(InstallScope::PerUser(sid.clone())),
+            InstallKind::InnoPerMachine { wow64: false } => Some(InstallScope::PerMachine),
+            InstallKind::InnoPerMachine { wow64: true } => Some(InstallScope::PerMachineWow64),
+            InstallKind::Portable | InstallKind::DevBuild => None,
+        }
+    }
+
+    /// Where the current installation is moved to while patching
+    ///
+    /// Dev builds keep their previous installation next to the app directory for inspection,
+    /// everything else uses the update data directory, which is removed after a successful update
+    pub fn backup_dir(&self, working_dir: &Path, update_data_dir: &Path) -> PathBuf {
+        match self {
+            InstallKind::DevBuild => working_dir.join("adm-app.previous"),
+            _ => update_data_dir.join("tmp"),
+        }
+    }
+}
+
+impl fmt::Display for InstallKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InstallKind::InnoPerUser { .. } => write!(f, "inno setup (per user)"),
+            InstallKind::InnoPerMachine { wow64: false } => write!(f, "inno setup (per machine)"),
+            InstallKind::InnoPerMachine { wow64: true } => write!(f, "inno setup (per machine, wow64)"),
+            InstallKind::Portable => write!(f, "portable"),
+            InstallKind::DevBuild => write!(f, "dev build"),
+        }
+    }
+}
+
+/// Detects the installation kind for the given user and working directory
+pub fn detect_install_kind(username: &str, assembly_dir: &Path, working_dir: &Path) -> InstallKind {
+    let dev_build = cfg!(debug_assertions) || is_cargo_target_dir(assembly_dir);
+    if dev_build {
+        return InstallKind::classify(true, None);
+    }
+    let sid = regedit::lookup_user_sid(username)
+        .inspect_err(|e| warn!("{}, checking per machine installation only", e))
+        .ok();
+    let scope = regedit::detect_install_scope(&regedit::WinRegistry, sid.as_deref(), working_dir);
+    if scope.is_none() && working_dir.join("unins000.exe").exists() {
+        debug!("uninstaller present without uninstall entry, treating installation as portable");
+    }
+    InstallKind::classify(false, scope)
+}
+
+fn is_cargo_target_dir(dir: &Path) -> bool {
+    let name = dir.file_name().map(|n| n.to_string_lossy().to_lowercase());
+    let parent = dir
+        .parent()
+        .and_then(|p| p.file_name())
+        .map(|n| n.to_string_lossy().to_lowercase());
+    matches!(name.as_deref(), Some("debug" | "release")) && parent.as_deref() == Some("target")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classify_kinds() {
+        let sid = "S-1-5-21-1000".to_string();
+        assert_eq!(
+            InstallKind::classify(false, Some(InstallScope::PerUser(sid.clone()))),
+            InstallKind::InnoPerUser { sid: sid.clone() }
+        );
+        assert_eq!(
+            InstallKind::classify(false, Some(InstallScope::PerMachineWow64)),
+            InstallKind::InnoPerMachine { wow64: true }
+        );
+        assert_eq!(InstallKind::classify(false, None), InstallKind::Portable);
+        assert_eq!(
+            InstallKind::classify(true, Some(InstallScope::PerMachine)),
+            InstallKind::DevBuild
+        );
+        for scope in [
+            InstallScope::PerUser(sid),
+            InstallScope::PerMachine,
+            InstallScope::PerMachineWow64,
+        ] {
+            assert_eq!(
+                InstallKind::classify(false, Some(scope.clone())).registry_scope(),
+                Some(scope)
+            );
+        }
+        assert_eq!(InstallKind::Portable.registry_scope(), None);
+        assert_eq!(InstallKind::DevBuild.registry_scope(), None);
+    }
+
+    #[test]
+    fn backup_locations() {
+        let working_dir = Path::new("adm");
+        let update_data_dir = working_dir.join("adm-update-data");
+        assert_eq!(
+            InstallKind::Portable.backup_dir(working_dir, &update_data_dir),
+            update_data_dir.join("tmp")
+        );
+        assert_eq!(
+            InstallKind::DevBuild.backup_dir(working_dir, &update_data_dir),
+            working_dir.join("adm-app.previous")
+        );
+    }
+
+    #[test]
+    fn detects_cargo_target_dirs() {
+        assert!(is_cargo_target_dir(&Path::new("adm-updater-rs").join("target").join("debug")));
+        assert!(is_cargo_target_dir(&Path::new("x").join("TARGET").join("Release")));
+        assert!(!is_cargo_target_dir(&Path::new("AutoDarkMode").join("adm-updater")));
+    }
+}
@@ -0,0 +1,139 @@
+use std::{
+    fs::{File, OpenOptions, TryLockError},
+    io::{Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, Instant},
+};
+
+use log::{debug, info};
+
+use crate::OpError;
+
+/// Created in the installation directory, outside of everything the update moves or deletes
+pub const LOCK_FILE: &str = "adm-updater.lock";
+/// Exit code of an updater that gave up waiting for another updater to finish
+pub const EXIT_CODE_LOCKED: i32 = 3;
+/// How long a second updater waits for the running one by default, long enough for a relaunched copy
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(60);
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Exclusive lock over the installation directory, held for the whole update run and released on drop
+///
+/// The lock file is never removed, the lock is taken on the open file (`LockFileEx` on windows). The system releases
+/// it when the holder exits, so an updater that crashed or was killed never leaves a stale lock behind and there is
+/// no file to take over.
+#[derive(Debug)]
+pub struct InstanceLock {
+    path: PathBuf,
+    _file: File,
+}
+
+impl InstanceLock {
+    /// Acquires the lock, waiting up to `timeout` for a running updater to release it
+    pub fn acquire(working_dir: &Path, timeout: Duration) -> Result<InstanceLock, OpError> {
+        let path = working_dir.join(LOCK_FILE);
+        let file =
+            open(&path).map_err(|e| OpError::new(&format!("could not open instance lock {}: {}", path.display(), e), true))?;
+        let started = Instant::now();
+        let mut waiting = false;
+        loop {
+            match file.try_lock() {
+                Ok(()) => break,
+                Err(TryLockError::WouldBlock) => {}
+                Err(TryLockError::Error(e)) => {
+                    return Err(OpError::new(
+                        &format!("could not lock instance lock {}: {}", path.display(), e),
+                        true,
+                    ))
+                }
+            }
+            if started.elapsed() >= timeout {
+                return Err(OpError::new(
+                    &format!("another updater is running in {}, giving up", working_dir.display()),
+                    false,
+                ));
+            }
+            if !waiting {
+                info!(
+                    "another updater is running, waiting up to {}s for it to finish",
+                    timeout.as_secs()
+                );
+                waiting = true;
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+
+        // only for diagnostics, the lock itself does not depend on the content
+        if let Err(e) = write_owner(&file) {
+            debug!("could not write owner to instance lock: {}", e);
+        }
+        debug!("acquired instance lock {}", path.display());
+        Ok(InstanceLock { path, _file: file })
+    }
+
+    /// Fails if the lock file was removed behind this updater's back
+    pub fn verify(&self) -> Result<(), OpError> {
+        if !self.path.is_file() {
+            return Err(OpError::new(
+                &format!("instance lock {} is no longer held by this updater", self.path.display()),
+                false,
+            ));
+        }
+        Ok(())
+    }
+}
+
+fn open(path: &Path) -> std::io::Result<File> {
+    let mut options = OpenOptions::new();
+    options.read(true).write(true).create(true).truncate(false);
+    #[cfg(windows)]
+    {
+        use std::os::windows::fs::OpenOptionsExt;
+        // FILE_SHARE_READ | FILE_SHARE_WRITE without FILE_SHARE_DELETE, the file cannot be removed while in use
+        options.share_mode(0x1 | 0x2);
+    }
+    options.open(path)
+}
+
+fn write_owner(mut file: &File) -> std::io::Result<()> {
+    file.set_len(0)?;
+    file.seek(SeekFrom::Start(0))?;
+    write!(file, "{}", std::process::id())?;
+    file.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::test_support::test_dir;
+
+    #[test]
+    fn second_instance_is_refused_until_released() {
+        let dir = test_dir("lock-exclusive");
+        let lock = InstanceLock::acquire(&dir, Duration::ZERO).unwrap();
+        assert!(dir.join(LOCK_FILE).exists());
+        assert!(InstanceLock::acquire(&dir, Duration::from_millis(600)).is_err());
+        lock.verify().unwrap();
+        drop(lock);
+        drop(InstanceLock::acquire(&dir, Duration::ZERO).unwrap());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn lock_file_left_behind_is_not_a_lock() {
+        let dir = test_dir("lock-leftover");
+        // what a killed updater leaves behind, including a pid that is alive
+        fs::write(dir.join(LOCK_FILE), std::process::id().to_string()).unwrap();
+        let lock = InstanceLock::acquire(&dir, Duration::ZERO).unwrap();
+        assert_eq!(
+            fs::read_to_string(dir.join(LOCK_FILE)).unwrap(),
+            std::process::id().to_string()
+        );
+        drop(lock);
+        assert!(dir.join(LOCK_FILE).exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
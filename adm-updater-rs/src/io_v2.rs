@@ -1,9 +1,7 @@
 use log::{debug, error, info, warn};
 use std::{
     ffi::c_void,
-    fmt::Formatter,
-    fs::{self, File},
-    io::{self, BufRead},
+    fs, io,
     path::{Path, PathBuf},
 };
 use walkdir::WalkDir;
@@ -12,54 +10,23 @@ use windows_strings::w;
 
 use crate::{
     extensions::{self, get_assembly_dir, get_update_data_dir, get_working_dir},
+    pe_version,
+    version::Version,
+    whitelist::Whitelist,
     OpError,
 };
 
-pub struct Version {
-    major: i32,
-    minor: i32,
-    build: i32,
-    revision: i32,
-}
-
-impl From<String> for Version {
-    fn from(version: String) -> Self {
-        let mut version_parts = version.split('.');
-        let major = version_parts.next().unwrap_or_default().parse::<i32>().unwrap_or(-1);
-        let minor = version_parts.next().unwrap_or_default().parse::<i32>().unwrap_or(-1);
-        let build = version_parts.next().unwrap_or_default().parse::<i32>().unwrap_or(-1);
-        let revision = version_parts.next().unwrap_or_default().parse::<i32>().unwrap_or(-1);
-        Version {
-            major,
-            minor,
-            build,
-            revision,
-        }
-    }
+lazy_static! {
+    static ref WHITELIST: Result<Whitelist, OpError> = Whitelist::load(&get_assembly_dir().join("whitelist.txt"));
 }
 
-impl std::fmt::Display for Version {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}.{}.{}.{}", self.major, self.minor, self.build, self.revision)
-    }
-}
-
-lazy_static! {
-    static ref WHITELIST: Result<Vec<String>, OpError> = {
-        let mut v = Vec::new();
-        let file = File::open(get_assembly_dir().join("whitelist.txt"))
-            .map_err(|op| OpError::new(&format!("failed to open whitelist file: {}", op), true))?;
-        let reader = io::BufReader::new(file);
-        for line in reader.lines() {
-            let line = line.map_err(|op| OpError::new(&format!("failed to read whitelist file: {}", op), true))?;
-            v.push(line);
-        }
-        Ok(v)
-    };
+/// Returns the whitelist next to the updater, it is only read and compiled once
+pub fn whitelist() -> Result<&'static Whitelist, OpError> {
+    WHITELIST.as_ref().map_err(|e| e.clone())
 }
 
 /// gets all files recursively that match the filter criteria
-pub fn get_files_recurse(path: &PathBuf, filter_criteria: fn(&Path) -> bool) -> Vec<PathBuf> {
+pub fn get_files_recurse(path: &PathBuf, filter_criteria: impl Fn(&Path) -> bool) -> Vec<PathBuf> {
     let mut old_files: Vec<PathBuf> = Vec::new();
     WalkDir::new(path)
         .into_iter()
@@ -72,7 +39,7 @@ pub fn get_files_recurse(path: &PathBuf, filter_criteria: fn(&Path) -> bool) ->
 }
 
 /// gets all directories recursively not matching the filter criteria
-pub fn get_dirs(path: &PathBuf, filter_criteria: fn(&Path) -> bool) -> Result<Vec<PathBuf>, OpError> {
+pub fn get_dirs(path: &PathBuf, filter_criteria: impl Fn(&Path) -> bool) -> Result<Vec<PathBuf>, OpError> {
     let entries = fs::read_dir(path)
         .map_err(|e| OpError::new(&format!("could not read root directory in get_dirs {:?}: {}", path, e), true))?;
     let mut old_dirs = entries
@@ -92,13 +59,14 @@ pub fn get_dirs(path: &PathBuf, filter_criteria: fn(&Path) -> bool) -> Result<Ve
 /// This is required for the update to complete, because the updater must not touch its own files
 #[allow(dead_code)]
 pub fn get_adm_files(path: &PathBuf) -> Result<Vec<PathBuf>, OpError> {
+    let whitelist = whitelist()?;
     let entries = fs::read_dir(path)
         .map_err(|e| OpError::new(&format!("could not read directory in get_files {:?}: {}", path, e), true))?;
     let result = entries
         .into_iter()
         .filter(|r| r.is_ok())
         .filter(|ent| match ent.as_ref() {
-            Ok(e) => is_whitelisted(e.path().as_path()),
+            Ok(e) => is_whitelisted(e.path().as_path(), whitelist),
             Err(_) => false,
         })
         .map(|res| res.map(|e| e.path()))
@@ -108,42 +76,24 @@ pub fn get_adm_files(path: &PathBuf) -> Result<Vec<PathBuf>, OpError> {
 }
 
 /// Checks if files should be ignored by the file collector
-fn is_whitelisted(entry: &Path) -> bool {
-    let execution_dir_str = get_assembly_dir().to_str().unwrap_or("").to_string();
-    let update_data_dir_str = get_update_data_dir().to_str().unwrap_or("").to_string();
-    //let work_dir_str = get_working_dir().to_str().unwrap_or("").to_string();
-    let entry_str = entry.to_str().unwrap_or_default();
-    if entry_str.contains("unins000.dat") {
-        return false;
-    } else if entry_str.contains("unins000.exe") {
-        return false;
-    } else if entry_str.contains("AutoDarkMode.VisualElementsManifest.xml") {
-        return false;
-    } else if execution_dir_str.len() > 0 && entry_str.contains(&execution_dir_str) {
-        return false;
-    } else if update_data_dir_str.len() > 0 && entry_str.contains(&update_data_dir_str) {
+fn is_whitelisted(entry: &Path, whitelist: &Whitelist) -> bool {
+    let execution_dir = get_assembly_dir();
+    let update_data_dir = get_update_data_dir();
+    if entry.starts_with(&execution_dir) || entry.starts_with(&update_data_dir) {
         return false;
     }
 
-    let whitelist = match WHITELIST.as_ref() {
-        Ok(v) => v,
-        Err(e) => {
-            error!("{}", e);
-            error!("aborting patch");
-            panic!("{}", e);
-        }
-    };
-
-    let file_name = match entry.file_name().and_then(|stem| stem.to_str()) {
-        Some(f) => f,
-        None => {
-            warn!("skipping file, could not retrieve file name for {}", entry.display());
-            return false;
-        }
+    let relative = match entry.strip_prefix(get_working_dir()) {
+        Ok(relative) => relative,
+        Err(_) => match entry.file_name() {
+            Some(name) => Path::new(name),
+            None => {
+                warn!("skipping file, could not retrieve file name for {}", entry.display());
+                return false;
+            }
+        },
     };
-    let file_name_lower = file_name.to_lowercase();
-    // check both ways, that ensures a whitelist entry can be wildcarded by ommitting the end string
-    let matches = whitelist.iter().any(|e| file_name_lower.starts_with(&e.to_lowercase()));
+    let matches = whitelist.is_match(relative, entry.is_dir());
     if !matches {
         warn!("found non-whitelisted entity in adm directory: {}", entry.display());
     }
@@ -152,12 +102,13 @@ fn is_whitelisted(entry: &Path) -> bool {
 
 #[allow(dead_code)]
 pub fn clean_adm_dir() -> Result<(), OpError> {
-    let files = get_files_recurse(&extensions::get_working_dir(), is_whitelisted);
+    let whitelist = whitelist()?;
+    let files = get_files_recurse(&extensions::get_working_dir(), |e| is_whitelisted(e, whitelist));
     for file in files {
         debug!("removing file {}", file.display());
         std::fs::remove_file(file).map_err(|e| OpError::new(&format!("could not remove file: {}", e), true))?;
     }
-    let dirs = get_dirs(&extensions::get_working_dir(), is_whitelisted)?;
+    let dirs = get_dirs(&extensions::get_working_dir(), |e| is_whitelisted(e, whitelist))?;
     for dir in dirs {
         debug!("removing dir {}", dir.display());
         std::fs::remove_dir(dir).map_err(|e| OpError::new(&format!("could not remove directory: {}", e), true))?;
@@ -202,8 +153,29 @@ pub fn move_files(source: &PathBuf, target: &PathBuf, files: Vec<PathBuf>) -> Re
     Ok(())
 }
 
-/// Makes calls to the WinAPI, retrieving the file version of the given path
+/// Retrieves the file version of the given path, reading the version resource directly and falling back to the WinAPI
 pub fn get_file_version(path: PathBuf) -> Result<Version, OpError> {
+    match pe_version::read_version_info(&path) {
+        Ok(info) => {
+            debug!(
+                "{} by {}, product version {:?} ({:?})",
+                info.file_description().unwrap_or("unknown file"),
+                info.company_name().unwrap_or("unknown company"),
+                info.product_version(),
+                info.fixed.product_version()
+            );
+            let (major, minor, build, revision) = info.fixed.file_version();
+            Ok(Version::new(major.into(), minor.into(), build.into(), revision.into()))
+        }
+        Err(e) => {
+            debug!("{}, retrying with winapi", e.message);
+            get_file_version_winapi(path)
+        }
+    }
+}
+
+/// Makes calls to the WinAPI, retrieving the file version of the given path
+pub fn get_file_version_winapi(path: PathBuf) -> Result<Version, OpError> {
     let path = windows::core::HSTRING::from(path.as_os_str());
     let mut handle: u32 = 2;
     let size = unsafe { GetFileVersionInfoSizeW(&path, Some(&mut handle)) };
@@ -256,14 +228,12 @@ pub fn get_file_version(path: PathBuf) -> Result<Version, OpError> {
         });
     }
     let version_info = unsafe { &*(pads as *const VS_FIXEDFILEINFO) };
-    let version_info_string = format!(
-        "{}.{}.{}.{}",
+    Ok(Version::new(
         (version_info.dwFileVersionMS >> 16) & 0xffff,
-        (version_info.dwFileVersionMS >> 0) & 0xffff,
+        version_info.dwFileVersionMS & 0xffff,
         (version_info.dwFileVersionLS >> 16) & 0xffff,
-        (version_info.dwFileVersionLS >> 0) & 0xffff
-    );
-    Ok(version_info_string.into())
+        version_info.dwFileVersionLS & 0xffff,
+    ))
 }
 
 #[allow(dead_code)]
@@ -336,8 +306,9 @@ mod tests {
     #[test]
     fn get_service_version() -> Result<(), Box<dyn Error>> {
         let service_path = get_service_path();
-        let result = get_file_version(service_path)?;
+        let result = get_file_version(service_path.clone())?;
         println!("{}", result);
+        assert_eq!(result.to_string(), get_file_version_winapi(service_path)?.to_string());
         Ok(())
     }
 
@@ -1,3 +1,5 @@
+use std::io;
+use std::path::Path;
 use std::thread;
 use std::time::Duration;
 use std::{fs, path::PathBuf};
@@ -5,86 +7,158 @@ use std::{fs, path::PathBuf};
 use log::warn;
 use log::{error, info};
 
-use crate::{extensions, OpError};
+use crate::{copy_move, extensions, OpError};
 
-pub fn rollback(temp_dir: &PathBuf) -> Result<(), OpError> {
+const RETRIES_ENV: &str = "ADM_UPDATER_IO_RETRIES";
+const DELAY_ENV: &str = "ADM_UPDATER_IO_RETRY_DELAY_MS";
+const BACKOFF_ENV: &str = "ADM_UPDATER_IO_RETRY_BACKOFF";
+const ERROR_ACCESS_DENIED: i32 = 5;
+const ERROR_SHARING_VIOLATION: i32 = 32;
+
+/// How often a rename is attempted while files are in use and how long to wait between attempts
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RetryPolicy {
+    pub attempts: u32,
+    pub delay: Duration,
+    /// The delay is multiplied by this after every attempt
+    pub backoff: u32,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            attempts: 3,
+            delay: Duration::from_secs(1),
+            backoff: 2,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Applies `--io-retries`, `--io-retry-delay-ms` and `--io-retry-backoff` or their environment variables to the defaults
+    pub fn resolve(args: &[String], var: impl Fn(&str) -> Option<String>) -> RetryPolicy {
+        let mut policy = RetryPolicy::default();
+        let value = |flag: &str, key: &str| {
+            let arg = args.iter().position(|a| a == flag).and_then(|i| args.get(i + 1)).cloned();
+            arg.or_else(|| var(key)).and_then(|v| match v.parse::<u64>() {
+                Ok(v) => Some(v),
+                Err(_) => {
+                    warn!("invalid value {} for {}, using default", v, flag);
+                    None
+                }
+            })
+        };
+        if let Some(attempts) = value("--io-retries", RETRIES_ENV) {
+            policy.attempts = (attempts as u32).max(1);
+        }
+        if let Some(delay) = value("--io-retry-delay-ms", DELAY_ENV) {
+            policy.delay = Duration::from_millis(delay);
+        }
+        if let Some(backoff) = value("--io-retry-backoff", BACKOFF_ENV) {
+            policy.backoff = (backoff as u32).max(1);
+        }
+        policy
+    }
+
+    fn delay_after(&self, attempt: u32) -> Duration {
+        self.delay.saturating_mul(self.backoff.saturating_pow(attempt))
+    }
+}
+
+/// Whether a rename failed because files are in use (os error 32) or access is denied (os error 5)
+fn is_in_use(e: &io::Error) -> bool {
+    matches!(e.raw_os_error(), Some(ERROR_SHARING_VIOLATION) | Some(ERROR_ACCESS_DENIED))
+}
+
+/// Renames a directory, retrying while files are in use (os error 32) or access is denied (os error 5)
+///
+/// If the rename fails for another reason, for example because the target is on another volume (os error 17), the
+/// tree is copied and verified instead. Files that are still in use would fail the copy just the same, so those errors
+/// are returned as they are.
+pub fn move_dir(from: &Path, to: &Path, policy: &RetryPolicy) -> Result<(), OpError> {
+    move_dir_releasing(from, to, policy, &|_| false)
+}
+
+/// Like `move_dir`, but once the retries are exhausted while files are still in use, `release` may free them
+///
+/// If `release` returns true, the rename is retried before giving up or copying
+pub fn move_dir_releasing(from: &Path, to: &Path, policy: &RetryPolicy, release: &dyn Fn(&Path) -> bool) -> Result<(), OpError> {
+    let mut result = rename_with_retries(from, to, policy);
+    if result.as_ref().is_err_and(is_in_use) && release(from) {
+        info!("retrying after files in {} were released", from.display());
+        result = rename_with_retries(from, to, policy);
+    }
+    let e = match result {
+        Ok(()) => return Ok(()),
+        Err(e) => e,
+    };
+    if !from.exists() {
+        return Err(OpError::new(&format!("{} not found: {}", from.display(), e), false));
+    }
+    if is_in_use(&e) {
+        return Err(OpError::new(
+            &format!("could not move {}, files are still in use: {}", from.display(), e),
+            false,
+        ));
+    }
+    warn!("could not rename {}, falling back to copying: {}", from.display(), e);
+    copy_move::copy_verify_move(from, to)
+}
+
+fn rename_with_retries(from: &Path, to: &Path, policy: &RetryPolicy) -> io::Result<()> {
+    let mut attempt = 0;
+    loop {
+        match fs::rename(from, to) {
+            Ok(()) => return Ok(()),
+            Err(e) if is_in_use(&e) && attempt + 1 < policy.attempts => {
+                let delay = policy.delay_after(attempt);
+                attempt += 1;
+                info!(
+                    "waiting for os to release files, attempt {} of {}, retrying in {} ms",
+                    attempt,
+                    policy.attempts,
+                    delay.as_millis()
+                );
+                thread::sleep(delay);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+pub fn rollback(temp_dir: &PathBuf, policy: &RetryPolicy) -> Result<(), OpError> {
     let adm_data_dir_pathbuf = extensions::get_adm_app_dir();
-    fs::rename(temp_dir, adm_data_dir_pathbuf).map_err(|op| {
-        let op_error = OpError::new(format!("{op}",).as_str(), true);
+    move_dir(temp_dir, &adm_data_dir_pathbuf, policy).map_err(|op_error| {
         error!("{}", op_error);
         op_error
     })?;
     Ok(())
 }
 
-pub fn move_to_temp(temp_dir: &PathBuf) -> Result<(), OpError> {
+/// `release` is called if files of the installation are still in use after all retries, see `move_dir_releasing`
+pub fn move_to_temp(temp_dir: &PathBuf, policy: &RetryPolicy, release: &dyn Fn(&Path) -> bool) -> Result<(), OpError> {
     let data_dir = extensions::get_adm_app_dir();
     if !data_dir.exists() {
         let msg = "update data directory not found, aborting patch";
         return Err(OpError::new(msg, false));
     }
 
-    let retries = 3;
-    let mut os_error_32 = None;
-    for i in 0..retries {
-        if let Err(e) = fs::rename(&data_dir, temp_dir) {
-            let raw_os_err = e.raw_os_error().unwrap_or(-1);
-            if raw_os_err == 32 {
-                os_error_32 = Some(e);
-                info!("waiting for os to release files, attempt {} of {}", i + 1, retries);
-                thread::sleep(Duration::from_secs(1));
-                continue;
-            } else {
-                let msg = "error moving current installation to temp directory, aborting patch";
-                let op_error = OpError::new(format!("{msg}: {e}",).as_str(), false);
-                return Err(op_error);
-            }
-        } else {
-            os_error_32 = None;
-            break;
-        }
-    }
-    if let Some(e) = os_error_32 {
+    move_dir_releasing(&data_dir, temp_dir, policy, release).map_err(|e| {
         let msg = "error moving current installation to temp directory, aborting patch";
-        let op_error = OpError::new(format!("{msg}: {e}",).as_str(), false);
-        return Err(op_error);
-    }
-
-    Ok(())
+        OpError::new(format!("{msg}: {e}",).as_str(), e.severe)
+    })
 }
 
-pub fn patch(update_dir: &PathBuf, adm_app_dir: &PathBuf) -> Result<(), OpError> {
+pub fn patch(update_dir: &PathBuf, adm_app_dir: &PathBuf, policy: &RetryPolicy) -> Result<(), OpError> {
     let patch_content_dir = update_dir.join("unpacked").join(extensions::APP_DIR);
-    let retries = 3;
-    let mut os_error_32 = None;
-    for i in 0..retries {
-        if let Err(e) = fs::rename(&patch_content_dir, adm_app_dir) {
-            let raw_os_err = e.raw_os_error().unwrap_or(-1);
-            if raw_os_err == 32 || raw_os_err == 5 {
-                info!("waiting for os to release files, attempt {} of {}", i + 1, retries);
-                os_error_32 = Some(e);
-                thread::sleep(Duration::from_secs(1));
-                continue;
-            } else {
-                let msg = "error patching auto dark mode, aborting patch";
-                let op_error = OpError::new(format!("{msg}: {e}").as_str(), false);
-                return Err(op_error);
-            }
-        } else {
-            os_error_32 = None;
-            break;
-        }
-    }
-    if let Some(e) = os_error_32 {
+    move_dir(&patch_content_dir, adm_app_dir, policy).map_err(|e| {
         let msg = "error patching auto dark mode, aborting patch";
-        let op_error = OpError::new(format!("{msg}: {e}").as_str(), false);
-        return Err(op_error);
-    }
-    Ok(())
+        OpError::new(format!("{msg}: {e}").as_str(), e.severe)
+    })
 }
 
-pub fn clean_update_files(update_dir: &PathBuf) {
-    let previous_service = update_dir.join("tmp").join("core").join(extensions::SERVICE_EXE);
+pub fn clean_update_files(update_dir: &PathBuf, backup_dir: &PathBuf) {
+    let previous_service = backup_dir.join("core").join(extensions::SERVICE_EXE);
     if !previous_service.exists() {
         warn!("could not find valid tmp directory with previous service data, skipping update file removal");
         return;
@@ -93,3 +167,54 @@ pub fn clean_update_files(update_dir: &PathBuf) {
         warn!("could not remove old update files, manual investigation required: {}", e);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::test_dir;
+
+    #[test]
+    fn resolves_retry_policy() {
+        let args: Vec<String> = ["updater.exe", "--io-retries", "5", "--io-retry-backoff", "x"]
+            .iter()
+            .map(|a| a.to_string())
+            .collect();
+        let policy = RetryPolicy::resolve(&args, |key| (key == DELAY_ENV).then(|| "200".to_string()));
+        assert_eq!(policy.attempts, 5);
+        assert_eq!(policy.delay, Duration::from_millis(200));
+        assert_eq!(policy.backoff, 2);
+        assert_eq!(policy.delay_after(0), Duration::from_millis(200));
+        assert_eq!(policy.delay_after(2), Duration::from_millis(800));
+    }
+
+    #[test]
+    fn copies_only_when_files_are_not_in_use() {
+        assert!(is_in_use(&io::Error::from_raw_os_error(ERROR_SHARING_VIOLATION)));
+        assert!(is_in_use(&io::Error::from_raw_os_error(ERROR_ACCESS_DENIED)));
+        // ERROR_NOT_SAME_DEVICE, the target is on another volume
+        assert!(!is_in_use(&io::Error::from_raw_os_error(17)));
+    }
+
+    #[test]
+    fn moves_directories() {
+        let dir = test_dir("io-v3-move");
+        fs::create_dir_all(dir.join("adm-app").join("core")).unwrap();
+        fs::write(dir.join("adm-app").join("core").join("AutoDarkModeSvc.exe"), "svc").unwrap();
+        let policy = RetryPolicy {
+            attempts: 1,
+            ..Default::default()
+        };
+        move_dir(&dir.join("adm-app"), &dir.join("tmp"), &policy).unwrap();
+        assert!(dir.join("tmp").join("core").join("AutoDarkModeSvc.exe").is_file());
+        assert!(move_dir(&dir.join("adm-app"), &dir.join("tmp2"), &policy).is_err());
+        // only files in use are worth releasing
+        let released = std::cell::Cell::new(false);
+        let release = |_: &Path| {
+            released.set(true);
+            true
+        };
+        assert!(move_dir_releasing(&dir.join("adm-app"), &dir.join("tmp2"), &policy, &release).is_err());
+        assert!(!released.get());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,215 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use log::{debug, info, warn};
+use serde::Deserialize;
+use walkdir::WalkDir;
+
+use crate::{extract::sha256_file, manifest::PayloadManifest, whitelist::Whitelist, OpError};
+
+/// Lives next to `adm-app`, so it survives the directory swap itself
+pub const USER_KEEP_FILE: &str = "adm-keep.txt";
+
+/// What happens when the update ships a file that matches the keep list
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KeepConflict {
+    /// The shipped file is installed, the user's file is kept next to it as `<name>.user`
+    #[default]
+    Update,
+    /// The user's file is installed, the shipped file is kept next to it as `<name>.new`
+    User,
+}
+
+/// Files inside `adm-app` that are carried from the installed tree into the new one
+///
+/// Patterns come from the payload manifest and from `adm-keep.txt` in the installation directory and use the
+/// whitelist syntax, including `!` exclusions. Paths are relative to `adm-app`.
+#[derive(Debug, Clone)]
+pub struct KeepList {
+    whitelist: Whitelist,
+    conflict: KeepConflict,
+    empty: bool,
+}
+
+impl KeepList {
+    /// Invalid entries of the manifest fail the update, invalid lines of `adm-keep.txt` are skipped with a warning
+    pub fn load(manifest: &PayloadManifest, working_dir: &Path) -> Result<KeepList, OpError> {
+        let mut patterns = manifest.keep.join("\n");
+        let user_file = working_dir.join(USER_KEEP_FILE);
+        if user_file.exists() {
+            let content = fs::read_to_string(&user_file)
+                .map_err(|e| OpError::new(&format!("could not read {}: {}", user_file.display(), e), true))?;
+            for (i, line) in content.lines().enumerate() {
+                if let Err(e) = Whitelist::check_entry(line) {
+                    warn!(
+                        "ignoring invalid entry {:?} on line {} of {}: {}",
+                        line.trim(),
+                        i + 1,
+                        USER_KEEP_FILE,
+                        e
+                    );
+                    continue;
+                }
+                patterns.push('\n');
+                patterns.push_str(line);
+            }
+        }
+        let whitelist = Whitelist::parse(&patterns).map_err(|e| OpError::new(&format!("invalid keep list: {}", e), true))?;
+        let empty = patterns.lines().map(str::trim).all(|l| l.is_empty() || l.starts_with('#'));
+        Ok(KeepList {
+            whitelist,
+            conflict: manifest.keep_conflict.unwrap_or_default(),
+            empty,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.empty
+    }
+
+    /// Copies every matching file of the installed tree into the new tree and returns how many were carried over
+    ///
+    /// The installed tree is left untouched, so it stays a complete backup until the swap succeeded
+    pub fn carry_over(&self, installed_dir: &Path, new_dir: &Path) -> Result<usize, OpError> {
+        let mut carried = 0;
+        for entry in WalkDir::new(installed_dir).min_depth(1) {
+            let entry = entry.map_err(|e| OpError::new(&format!("could not read installed files: {}", e), true))?;
+            if !entry.file_type().is_file() {
+                continue;
+            }
+            let relative = entry.path().strip_prefix(installed_dir).unwrap_or(entry.path());
+            if !self.whitelist.is_match(relative, false) {
+                continue;
+            }
+            self.carry_file(entry.path(), relative, &new_dir.join(relative))?;
+            carried += 1;
+        }
+        if carried > 0 {
+            info!("carried {} kept files over into the new installation", carried);
+        }
+        Ok(carried)
+    }
+
+    fn carry_file(&self, source: &Path, relative: &Path, target: &Path) -> Result<(), OpError> {
+        if target.exists() {
+            if sha256_file(source)? == sha256_file(target)? {
+                debug!("kept file {} is identical to the shipped one", relative.display());
+                return Ok(());
+            }
+            match self.conflict {
+                KeepConflict::Update => {
+                    let saved = with_suffix(target, "user");
+                    warn!(
+                        "update ships {}, your version is kept as {}",
+                        relative.display(),
+                        saved.display()
+                    );
+                    return copy(source, &saved);
+                }
+                KeepConflict::User => {
+                    let saved = with_suffix(target, "new");
+                    warn!(
+                        "update ships {}, keeping your version, the shipped one is saved as {}",
+                        relative.display(),
+                        saved.display()
+                    );
+                    fs::rename(target, &saved)
+                        .map_err(|e| OpError::new(&format!("could not set aside shipped {}: {}", relative.display(), e), true))?;
+                }
+            }
+        } else if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|e| OpError::new(&format!("could not create directory for {}: {}", relative.display(), e), true))?;
+        }
+        debug!("keeping {}", relative.display());
+        copy(source, target)
+    }
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".");
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+fn copy(from: &Path, to: &Path) -> Result<(), OpError> {
+    fs::copy(from, to)
+        .map(|_| ())
+        .map_err(|e| OpError::new(&format!("could not keep {}: {}", from.display(), e), true))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::test_dir;
+
+    fn setup(name: &str) -> (PathBuf, PathBuf, PathBuf) {
+        let dir = test_dir(&format!("keep-{}", name));
+        let installed = dir.join("adm-app");
+        let new = dir.join("unpacked").join("adm-app");
+        fs::create_dir_all(installed.join("scripts")).unwrap();
+        fs::create_dir_all(installed.join("core")).unwrap();
+        fs::create_dir_all(new.join("core")).unwrap();
+        fs::write(installed.join("scripts").join("wallpaper.ps1"), "user script").unwrap();
+        fs::write(installed.join("scripts").join("notes.tmp"), "scratch").unwrap();
+        fs::write(installed.join("core").join("theme.json"), "user theme").unwrap();
+        fs::write(installed.join("core").join("AutoDarkModeSvc.exe"), "old svc").unwrap();
+        fs::write(new.join("core").join("theme.json"), "shipped theme").unwrap();
+        fs::write(new.join("core").join("AutoDarkModeSvc.exe"), "new svc").unwrap();
+        (dir, installed, new)
+    }
+
+    fn manifest(json: &str) -> PayloadManifest {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn carries_matching_files_and_keeps_shipped_ones() {
+        let (dir, installed, new) = setup("update");
+        fs::write(dir.join(USER_KEEP_FILE), "# my files\nscripts/\n[invalid\n!*.tmp\n").unwrap();
+        let keep = KeepList::load(&manifest(r#"{"keep": ["core/*.json"]}"#), &dir).unwrap();
+        assert!(!keep.is_empty());
+
+        assert_eq!(keep.carry_over(&installed, &new).unwrap(), 2);
+        assert_eq!(
+            fs::read_to_string(new.join("scripts").join("wallpaper.ps1")).unwrap(),
+            "user script"
+        );
+        assert!(!new.join("scripts").join("notes.tmp").exists());
+        assert_eq!(
+            fs::read_to_string(new.join("core").join("theme.json")).unwrap(),
+            "shipped theme"
+        );
+        assert_eq!(
+            fs::read_to_string(new.join("core").join("theme.json.user")).unwrap(),
+            "user theme"
+        );
+        assert_eq!(
+            fs::read_to_string(new.join("core").join("AutoDarkModeSvc.exe")).unwrap(),
+            "new svc"
+        );
+        assert!(installed.join("core").join("theme.json").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn user_files_win_if_configured() {
+        let (dir, installed, new) = setup("user");
+        let keep = KeepList::load(&manifest(r#"{"keep": ["theme.json"], "keepConflict": "user"}"#), &dir).unwrap();
+        assert_eq!(keep.carry_over(&installed, &new).unwrap(), 1);
+        assert_eq!(fs::read_to_string(new.join("core").join("theme.json")).unwrap(), "user theme");
+        assert_eq!(
+            fs::read_to_string(new.join("core").join("theme.json.new")).unwrap(),
+            "shipped theme"
+        );
+
+        assert!(KeepList::load(&PayloadManifest::default(), &dir).unwrap().is_empty());
+        // the manifest comes with the release, a broken entry there is not skipped
+        assert!(KeepList::load(&manifest(r#"{"keep": ["[broken"]}"#), &dir).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
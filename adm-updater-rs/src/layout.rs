@@ -0,0 +1,280 @@
+use std::{
+    fmt, fs,
+    path::{Path, PathBuf},
+};
+
+use log::{debug, info, warn};
+
+use crate::{
+    extensions::{APP_DIR, SERVICE_EXE},
+    whitelist::Whitelist,
+    OpError,
+};
+
+/// Name of the directory a flat installation is assembled in before it becomes the app directory
+const STAGING_DIR: &str = "adm-app.migrating";
+
+/// How the files of an installation are arranged in the working directory
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InstallLayout {
+    /// Everything below `adm-app`, the service in `adm-app/core`, which is what io_v3 patches
+    AppDir,
+    /// Legacy v2 installation with the service and all other files directly in the working directory
+    Flat,
+    /// Neither layout was found, the updater must not touch anything
+    Unknown,
+}
+
+impl fmt::Display for InstallLayout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InstallLayout::AppDir => write!(f, "adm-app"),
+            InstallLayout::Flat => write!(f, "flat (legacy)"),
+            InstallLayout::Unknown => write!(f, "unknown"),
+        }
+    }
+}
+
+/// Detects the layout of the installation in the given working directory
+///
+/// A staging directory without an app directory is left behind by an interrupted migration,
+/// it is reported as a flat installation so the next migration can put its entries back first
+pub fn detect_layout(working_dir: &Path) -> InstallLayout {
+    if working_dir.join(APP_DIR).join("core").join(SERVICE_EXE).is_file() {
+        if working_dir.join(SERVICE_EXE).exists() {
+            warn!("found leftover service executable of a flat installation next to {}", APP_DIR);
+        }
+        InstallLayout::AppDir
+    } else if working_dir.join(SERVICE_EXE).is_file() && !working_dir.join(APP_DIR).exists() {
+        InstallLayout::Flat
+    } else if working_dir.join(STAGING_DIR).is_dir() && !working_dir.join(APP_DIR).exists() {
+        warn!("found staging directory of an interrupted migration");
+        InstallLayout::Flat
+    } else {
+        InstallLayout::Unknown
+    }
+}
+
+/// A completed migration, which can be undone if the update that follows fails
+#[derive(Debug)]
+pub struct Migration {
+    working_dir: PathBuf,
+    app_dir: PathBuf,
+    moved: Vec<PathBuf>,
+}
+
+impl Migration {
+    /// Moves the migrated entries back into the working directory, restoring the flat installation
+    pub fn undo(&self) -> Result<(), OpError> {
+        info!("restoring flat installation layout");
+        move_back(&self.app_dir, &self.working_dir, &self.moved)?;
+        if let Err(e) = fs::remove_dir(&self.app_dir) {
+            warn!(
+                "could not remove {} after restoring flat layout: {}",
+                self.app_dir.display(),
+                e
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Moves all whitelisted entries of a flat installation into the app directory
+///
+/// The entries are moved into a staging directory first, which is renamed once everything is in place.
+/// If anything fails, the entries that were already moved are put back and the working directory is left as it was.
+/// Entries below the excluded paths, usually the updater and the update data, are never touched.
+pub fn migrate_flat_layout(working_dir: &Path, excluded: &[PathBuf], whitelist: &Whitelist) -> Result<Migration, OpError> {
+    let app_dir = working_dir.join(APP_DIR);
+    let staging_dir = working_dir.join(STAGING_DIR);
+    if app_dir.exists() {
+        return Err(OpError::new(
+            &format!("{} already exists, refusing to migrate flat installation", app_dir.display()),
+            false,
+        ));
+    }
+    recover_interrupted_migration(&staging_dir, working_dir)?;
+
+    let entries = fs::read_dir(working_dir).map_err(|e| {
+        OpError::new(
+            &format!("could not read working directory {}: {}", working_dir.display(), e),
+            false,
+        )
+    })?;
+    let mut names = Vec::new();
+    for entry in entries.filter_map(|e| e.ok()) {
+        let path = entry.path();
+        if path == staging_dir || excluded.iter().any(|ex| path.starts_with(ex)) {
+            continue;
+        }
+        let name = PathBuf::from(entry.file_name());
+        if whitelist.is_match(&name, path.is_dir()) {
+            names.push(name);
+        } else {
+            debug!("leaving non-whitelisted entry {} in place", path.display());
+        }
+    }
+    // same safety check as the legacy updater, never move anything if this is not an auto dark mode directory
+    if !names.iter().any(|n| n.as_os_str().eq_ignore_ascii_case(SERVICE_EXE)) {
+        return Err(OpError::new(
+            &format!(
+                "{} is not whitelisted in {}, aborting migration",
+                SERVICE_EXE,
+                working_dir.display()
+            ),
+            false,
+        ));
+    }
+
+    fs::create_dir(&staging_dir)
+        .map_err(|e| OpError::new(&format!("could not create staging directory for migration: {}", e), false))?;
+    let mut moved = Vec::new();
+    for name in names {
+        if let Err(e) = fs::rename(working_dir.join(&name), staging_dir.join(&name)) {
+            let op = OpError::new(
+                &format!("could not move {} into staging directory: {}", name.display(), e),
+                false,
+            );
+            return Err(abort_migration(op, &staging_dir, working_dir, &moved));
+        }
+        moved.push(name);
+    }
+    if let Err(e) = fs::rename(&staging_dir, &app_dir) {
+        let op = OpError::new(&format!("could not rename staging directory to {}: {}", APP_DIR, e), false);
+        return Err(abort_migration(op, &staging_dir, working_dir, &moved));
+    }
+    info!("migrated {} entries into {}", moved.len(), app_dir.display());
+    Ok(Migration {
+        working_dir: working_dir.to_path_buf(),
+        app_dir,
+        moved,
+    })
+}
+
+fn abort_migration(op: OpError, staging_dir: &Path, working_dir: &Path, moved: &[PathBuf]) -> OpError {
+    warn!("{}, rolling back migration", op);
+    if let Err(e) = move_back(staging_dir, working_dir, moved) {
+        return OpError::new(&format!("{}, rollback failed: {}", op, e), true);
+    }
+    if let Err(e) = fs::remove_dir(staging_dir) {
+        warn!("could not remove staging directory after rollback: {}", e);
+    }
+    op
+}
+
+fn move_back(source: &Path, target: &Path, names: &[PathBuf]) -> Result<(), OpError> {
+    let mut failed = Vec::new();
+    for name in names.iter().rev() {
+        if let Err(e) = fs::rename(source.join(name), target.join(name)) {
+            warn!("could not move {} back: {}", name.display(), e);
+            failed.push(name.display().to_string());
+        }
+    }
+    if !failed.is_empty() {
+        return Err(OpError::new(&format!("could not restore {}", failed.join(", ")), true));
+    }
+    Ok(())
+}
+
+/// Puts back entries a previously interrupted migration left in the staging directory
+fn recover_interrupted_migration(staging_dir: &Path, working_dir: &Path) -> Result<(), OpError> {
+    if !staging_dir.exists() {
+        return Ok(());
+    }
+    warn!("found staging directory of an interrupted migration, restoring its entries");
+    let entries =
+        fs::read_dir(staging_dir).map_err(|e| OpError::new(&format!("could not read staging directory: {}", e), true))?;
+    let names: Vec<PathBuf> = entries
+        .filter_map(|e| e.ok())
+        .map(|e| PathBuf::from(e.file_name()))
+        .filter(|name| !working_dir.join(name).exists())
+        .collect();
+    move_back(staging_dir, working_dir, &names)?;
+    fs::remove_dir(staging_dir).map_err(|e| {
+        OpError::new(
+            &format!("staging directory {} still contains files: {}", staging_dir.display(), e),
+            true,
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::test_dir;
+
+    fn flat_install(dir: &Path) {
+        fs::write(dir.join(SERVICE_EXE), "svc").unwrap();
+        fs::write(dir.join("AutoDarkModeApp.exe"), "app").unwrap();
+        fs::write(dir.join("unins000.exe"), "setup").unwrap();
+        fs::create_dir_all(dir.join("runtimes").join("win-x64")).unwrap();
+        fs::write(dir.join("runtimes").join("win-x64").join("native.dll"), "dll").unwrap();
+        fs::create_dir_all(dir.join("adm-updater")).unwrap();
+        fs::write(dir.join("adm-updater").join("AutoDarkModeUpdater.exe"), "updater").unwrap();
+    }
+
+    fn whitelist() -> Whitelist {
+        Whitelist::parse("AutoDarkMode\nruntimes/\n!unins000.*").unwrap()
+    }
+
+    #[test]
+    fn detect_layouts() {
+        let dir = test_dir("layout-detect");
+        assert_eq!(detect_layout(&dir), InstallLayout::Unknown);
+        flat_install(&dir);
+        assert_eq!(detect_layout(&dir), InstallLayout::Flat);
+        fs::create_dir_all(dir.join(APP_DIR).join("core")).unwrap();
+        assert_eq!(detect_layout(&dir), InstallLayout::Unknown);
+        fs::write(dir.join(APP_DIR).join("core").join(SERVICE_EXE), "svc").unwrap();
+        assert_eq!(detect_layout(&dir), InstallLayout::AppDir);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn migrate_and_undo() {
+        let dir = test_dir("layout-migrate");
+        flat_install(&dir);
+        let migration = migrate_flat_layout(&dir, &[dir.join("adm-updater")], &whitelist()).unwrap();
+        let app_dir = dir.join(APP_DIR);
+        assert!(app_dir.join(SERVICE_EXE).is_file());
+        assert!(app_dir.join("runtimes").join("win-x64").join("native.dll").is_file());
+        assert!(dir.join("unins000.exe").is_file());
+        assert!(dir.join("adm-updater").join("AutoDarkModeUpdater.exe").is_file());
+        assert!(!dir.join(SERVICE_EXE).exists());
+        assert!(!dir.join(STAGING_DIR).exists());
+
+        migration.undo().unwrap();
+        assert!(!app_dir.exists());
+        assert_eq!(detect_layout(&dir), InstallLayout::Flat);
+        assert!(dir.join("runtimes").join("win-x64").join("native.dll").is_file());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn refuses_directories_without_service() {
+        let dir = test_dir("layout-refuse");
+        fs::write(dir.join("AutoDarkModeApp.exe"), "app").unwrap();
+        assert!(migrate_flat_layout(&dir, &[], &whitelist()).is_err());
+        assert!(dir.join("AutoDarkModeApp.exe").is_file());
+        assert!(!dir.join(STAGING_DIR).exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn recovers_interrupted_migration() {
+        let dir = test_dir("layout-recover");
+        flat_install(&dir);
+        fs::create_dir(dir.join(STAGING_DIR)).unwrap();
+        fs::rename(
+            dir.join("AutoDarkModeApp.exe"),
+            dir.join(STAGING_DIR).join("AutoDarkModeApp.exe"),
+        )
+        .unwrap();
+        fs::rename(dir.join(SERVICE_EXE), dir.join(STAGING_DIR).join(SERVICE_EXE)).unwrap();
+        assert_eq!(detect_layout(&dir), InstallLayout::Flat);
+        migrate_flat_layout(&dir, &[dir.join("adm-updater")], &whitelist()).unwrap();
+        assert!(dir.join(APP_DIR).join("AutoDarkModeApp.exe").is_file());
+        assert!(!dir.join(STAGING_DIR).exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
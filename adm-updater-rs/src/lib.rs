@@ -0,0 +1,28 @@
+//! Platform independent parts of the updater
+//!
+//! Everything in here builds without the Windows dependencies, so its tests also run on other hosts with `cargo test --lib`
+
+use std::{error::Error, fmt};
+
+pub mod pe_version;
+
+#[derive(Debug, Clone)]
+pub struct OpError {
+    pub message: String,
+    pub severe: bool,
+}
+
+impl Error for OpError {}
+
+impl fmt::Display for OpError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl OpError {
+    pub fn new(msg: &str, severe: bool) -> OpError {
+        let message = msg.to_string();
+        OpError { message, severe }
+    }
+}
@@ -1,31 +1,14 @@
-pub fn display_license() {
-    let rand = "rand - Copyright 2018 Developers of the Rand project Copyright (c) 2014 The Rust Project Developers - MIT License - https://github.com/rust-random/rand/blob/master/LICENSE-MIT\n";
-    let fern = "fern - Copyright (c) 2014-2017 David Ross - MIT License - https://github.com/daboross/fern/blob/master/LICENSE\n";
-    let log = "log - Copyright (c) 2014 The Rust Project Developers - MIT License - https://github.com/rust-lang/log/blob/master/LICENSE-MIT\n";
-    let chrono = "chrono - Copyright (c) 2014--2017, Kang Seonghoon and contributors. - MIT License - https://github.com/chronotope/chrono/blob/main/LICENSE.txt\n";
-    let named_pipe = "named_pipe - Copyright (c) 2015 Ikorsky Anatoly - MIT License - https://github.com/blackbeam/named_pipe/blob/master/LICENSE-MIT\n";
-    let whoami = "whoami - Copyright (c) libcala - MIT License - https://github.com/libcala/whoami/blob/main/LICENSE_MIT.txt\n";
-    let sysinfo = "sysinfo - Copyright (c) 2015 Guillaume Gomez - MIT License - https://github.com/GuillaumeGomez/sysinfo/blob/master/LICENSE\n";
-    let winres = "winres - Copyright 2016 Max Resch - MIT License - https://github.com/mxre/winres/blob/master/LICENSE\n";
-    let walkdir = "walkdir - Copyright BurntSushi - Unlicense - https://github.com/BurntSushi/walkdir/blob/master/UNLICENSE\n";
-    let winreg = "winreg - Copyright (c) 2015 Igor Shaula - MTI License - https://github.com/gentoo90/winreg-rs/blob/master/LICENSE\n";
-    let windows_permissions = "windows-permissions - Copyright (c) 2021 Daniel Dulaney - MIT License - https://crates.io/crates/windows-permissions\n";
-    let platform_dirs = "platform-dirs - Copyright (c) 2019 Caleb Bassi - MIT License - https://github.com/cjbassi/platform-dirs-rs/blob/master/LICENSE\n";
-    let lazy_static = "lazy_static - Copyright 2016 lazy-static.rs Developers - MIT License - https://choosealicense.com/licenses/mit\n";
-
+/// Third party notice generated by build.rs from the dependencies built for the target
+const LICENSES_TEXT: &str = include_str!(concat!(env!("OUT_DIR"), "/licenses.txt"));
+const LICENSES_HTML: &str = include_str!(concat!(env!("OUT_DIR"), "/licenses.html"));
 
+pub fn display_license(html: bool) {
+    if html {
+        print!("{}", LICENSES_HTML);
+        return;
+    }
     println!("auto dark mode rust updater, Copyright (c) 2021, Spiritreader, Auto Dark Mode - MIT License\n");
-    println!("{}", rand);
-    println!("{}", fern);
-    println!("{}", log);
-    println!("{}", chrono);
-    println!("{}", named_pipe);
-    println!("{}", whoami);
-    println!("{}", sysinfo);
-    println!("{}", winres);
-    println!("{}", walkdir);
-    println!("{}", winreg);
-    println!("{}", windows_permissions);
-    println!("{}", platform_dirs);
-    println!("{}", lazy_static);
+    for line in LICENSES_TEXT.lines() {
+        println!("{}\n", line);
+    }
 }
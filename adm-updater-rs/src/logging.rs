@@ -0,0 +1,209 @@
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+use log::LevelFilter;
+use platform_dirs::AppDirs;
+
+const LOG_FILE: &str = "updater.log";
+const PATH_ENV: &str = "ADM_UPDATER_LOG_PATH";
+const LEVEL_ENV: &str = "ADM_UPDATER_LOG_LEVEL";
+const MAX_SIZE_ENV: &str = "ADM_UPDATER_LOG_MAX_KB";
+const RETENTION_ENV: &str = "ADM_UPDATER_LOG_RETENTION";
+const DEFAULT_MAX_SIZE_KB: u64 = 1024;
+const DEFAULT_RETENTION: usize = 3;
+
+lazy_static! {
+    /// Identifies the lines written by this run, so consecutive update attempts can be told apart in the log
+    static ref SESSION_ID: String = format!("{:08x}", rand::random::<u32>());
+}
+
+pub fn session_id() -> &'static str {
+    &SESSION_ID
+}
+
+/// Where and how much the updater logs
+///
+/// Command line arguments take precedence over environment variables, which take precedence over the defaults
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogConfig {
+    pub path: PathBuf,
+    pub level: LevelFilter,
+    /// The log file is rotated on startup once it is larger than this
+    pub max_size_kb: u64,
+    /// How many rotated log files are kept next to the current one
+    pub retention: usize,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        let path =
+            AppDirs::new(Some("AutoDarkMode"), false).map_or(PathBuf::from(LOG_FILE), |dirs| dirs.config_dir.join(LOG_FILE));
+        let level = if cfg!(debug_assertions) {
+            LevelFilter::Debug
+        } else {
+            LevelFilter::Info
+        };
+        LogConfig {
+            path,
+            level,
+            max_size_kb: DEFAULT_MAX_SIZE_KB,
+            retention: DEFAULT_RETENTION,
+        }
+    }
+}
+
+impl LogConfig {
+    /// Reads the configuration from the process arguments and environment
+    pub fn from_env() -> LogConfig {
+        let args: Vec<String> = env::args().collect();
+        LogConfig::resolve(&args, |key| env::var(key).ok())
+    }
+
+    /// Applies `--log-path`, `--log-level`, `--log-max-kb` and `--log-retention` or their environment variables to the defaults
+    ///
+    /// Invalid values are reported on stderr and ignored, the logger is not up yet
+    pub fn resolve(args: &[String], var: impl Fn(&str) -> Option<String>) -> LogConfig {
+        let mut config = LogConfig::default();
+        let value = |flag: &str, key: &str| arg_value(args, flag).or_else(|| var(key));
+        if let Some(path) = value("--log-path", PATH_ENV) {
+            config.path = PathBuf::from(path);
+        }
+        if let Some(level) = value("--log-level", LEVEL_ENV) {
+            match level.parse() {
+                Ok(level) => config.level = level,
+                Err(_) => eprintln!("invalid log level {}, using {}", level, config.level),
+            }
+        }
+        if let Some(size) = value("--log-max-kb", MAX_SIZE_ENV) {
+            match size.parse() {
+                Ok(size) => config.max_size_kb = size,
+                Err(_) => eprintln!("invalid log size {}, using {} kb", size, config.max_size_kb),
+            }
+        }
+        if let Some(retention) = value("--log-retention", RETENTION_ENV) {
+            match retention.parse() {
+                Ok(retention) => config.retention = retention,
+                Err(_) => eprintln!("invalid log retention {}, keeping {} files", retention, config.retention),
+            }
+        }
+        config
+    }
+}
+
+fn arg_value(args: &[String], flag: &str) -> Option<String> {
+    args.iter().position(|a| a == flag).and_then(|i| args.get(i + 1)).cloned()
+}
+
+/// Logger entry point expected by the module tests
+#[cfg(test)]
+pub fn setup_logger() -> Result<(), fern::InitError> {
+    setup_logger_with_config(&LogConfig::from_env())
+}
+
+/// Sets up logging to stdout and the rotated log file
+pub fn setup_logger_with_config(config: &LogConfig) -> Result<(), fern::InitError> {
+    if let Some(parent) = config.path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        fs::create_dir_all(parent)?;
+    }
+    if let Err(e) = rotate(&config.path, config.max_size_kb * 1024, config.retention) {
+        eprintln!("failed to rotate {}: {}", config.path.display(), e);
+    }
+    fern::Dispatch::new()
+        .format(|out, message, record| {
+            out.finish(format_args!(
+                "{} [{}] [{}] [{}] {}",
+                chrono::Local::now().format("%Y-%m-%d %H:%M:%S"),
+                session_id(),
+                record.level(),
+                record.target(),
+                message
+            ))
+        })
+        .level(config.level)
+        .chain(std::io::stdout())
+        .chain(fern::log_file(&config.path)?)
+        .apply()?;
+    Ok(())
+}
+
+/// Renames the log file to `<name>.1` if it exceeds the size limit, shifting older files up and deleting the oldest
+fn rotate(path: &Path, max_size: u64, retention: usize) -> std::io::Result<()> {
+    let size = match fs::metadata(path) {
+        Ok(metadata) => metadata.len(),
+        Err(_) => return Ok(()),
+    };
+    if size <= max_size {
+        return Ok(());
+    }
+    if retention == 0 {
+        return fs::remove_file(path);
+    }
+    let rotated = |n: usize| {
+        let mut name = path.as_os_str().to_owned();
+        name.push(format!(".{}", n));
+        PathBuf::from(name)
+    };
+    let oldest = rotated(retention);
+    if oldest.exists() {
+        fs::remove_file(&oldest)?;
+    }
+    for n in (1..retention).rev() {
+        let from = rotated(n);
+        if from.exists() {
+            fs::rename(&from, rotated(n + 1))?;
+        }
+    }
+    fs::rename(path, rotated(1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::test_dir;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn arguments_override_environment() {
+        let env = |key: &str| match key {
+            PATH_ENV => Some("env.log".to_string()),
+            LEVEL_ENV => Some("warn".to_string()),
+            RETENTION_ENV => Some("many".to_string()),
+            _ => None,
+        };
+        let config = LogConfig::resolve(&args("updater.exe --log-level trace --log-max-kb 16"), env);
+        assert_eq!(config.path, PathBuf::from("env.log"));
+        assert_eq!(config.level, LevelFilter::Trace);
+        assert_eq!(config.max_size_kb, 16);
+        assert_eq!(config.retention, DEFAULT_RETENTION);
+        assert_eq!(
+            LogConfig::resolve(&args("updater.exe --log-path"), |_| None),
+            LogConfig::default()
+        );
+    }
+
+    #[test]
+    fn rotates_and_prunes_old_logs() {
+        let dir = test_dir("logging-rotate");
+        let log = dir.join(LOG_FILE);
+        let rotated = |n: usize| dir.join(format!("{}.{}", LOG_FILE, n));
+
+        fs::write(&log, "small").unwrap();
+        rotate(&log, 10, 2).unwrap();
+        assert!(log.exists());
+
+        for run in ["first run", "second run", "third run"] {
+            fs::write(&log, run).unwrap();
+            rotate(&log, 5, 2).unwrap();
+            assert!(!log.exists());
+        }
+        assert_eq!(fs::read_to_string(rotated(1)).unwrap(), "third run");
+        assert_eq!(fs::read_to_string(rotated(2)).unwrap(), "second run");
+        assert!(!rotated(3).exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -3,17 +3,35 @@
 #[macro_use]
 extern crate lazy_static;
 
-use crate::extensions::{get_adm_app_dir, get_service_path, get_update_data_dir};
-use crate::io_v3::{clean_update_files, move_to_temp, patch, rollback};
+use crate::deferral::{DeferralPolicy, DesktopProbe, SystemClock};
+use crate::extensions::{get_adm_app_dir, get_assembly_dir, get_service_path, get_update_data_dir};
+use crate::file_locks::{release_adm_locks, RestartManager};
+use crate::install_kind::{detect_install_kind, InstallKind};
+use crate::instance_lock::{InstanceLock, EXIT_CODE_LOCKED};
+use crate::io_v3::{clean_update_files, move_to_temp, patch, rollback, RetryPolicy};
+use crate::layout::{detect_layout, migrate_flat_layout, InstallLayout, Migration};
+#[cfg(test)]
+use crate::logging::setup_logger;
+use crate::logging::{setup_logger_with_config, LogConfig};
+use crate::manifest::{check_payload, PayloadManifest};
+use crate::other_sessions::{restart_other_sessions, stop_other_sessions};
+use crate::preflight::{run_preflight, PreflightPaths};
+use crate::report::{report_path, Phase, UpdateReport};
+use crate::version::Version;
+use adm_updater_rs::{pe_version, OpError};
+use comms::events::{subscribe, AdmEvent};
 use comms::send_message_and_get_reply;
 use extensions::get_working_dir;
 use log::{debug, warn};
 use log::{error, info};
+use std::env;
 use std::error::Error;
 use std::ffi::OsStr;
+use std::fs;
+use std::path::{Path, PathBuf};
 use std::process::Command;
 use std::rc::Rc;
-use std::{env, fmt};
+use std::time::{Duration, Instant};
 use sysinfo::System;
 use sysinfo::{ProcessRefreshKind, ProcessesToUpdate, Users};
 use windows::core::PCWSTR;
@@ -24,35 +42,33 @@ use windows::Win32::UI::WindowsAndMessaging::SHOW_WINDOW_CMD;
 use windows_strings::w;
 
 mod comms;
+mod copy_move;
+mod deferral;
+mod delta;
 mod extensions;
+mod extract;
+mod file_locks;
+mod install_kind;
+mod instance_lock;
 mod io_v2;
 mod io_v3;
+mod keep_list;
+mod layout;
 mod license;
+mod logging;
+mod manifest;
+mod other_sessions;
+mod preflight;
 mod regedit;
+mod report;
+mod self_update;
+#[cfg(test)]
+mod test_support;
+mod version;
+mod whitelist;
 
 const VERSION: &'static str = env!("CARGO_PKG_VERSION");
 
-#[derive(Debug, Clone)]
-pub struct OpError {
-    pub message: String,
-    pub severe: bool,
-}
-
-impl Error for OpError {}
-
-impl fmt::Display for OpError {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", self.message)
-    }
-}
-
-impl OpError {
-    pub fn new(msg: &str, severe: bool) -> OpError {
-        let message = msg.to_string();
-        OpError { message, severe }
-    }
-}
-
 #[allow(dead_code)]
 trait LogExt {
     fn log(self) -> Self;
@@ -72,103 +88,448 @@ where
 
 fn main() -> Result<(), Box<dyn Error>> {
     let result = unsafe { AttachConsole(ATTACH_PARENT_PROCESS) };
+    let args: Vec<String> = env::args().collect();
+    // before the logger, which writes to stdout and rotates the log file
+    if args.contains(&"--info".to_string()) || args.contains(&"--licenses".to_string()) {
+        license::display_license(args.contains(&"--html".to_string()));
+        return Ok(());
+    }
     if let Err(e) = result {
         warn!("error attaching to parent console: {}", e);
     }
-    if !setup_logger().is_ok() {
+    let log_config = LogConfig::from_env();
+    if !setup_logger_with_config(&log_config).is_ok() {
         print!("failed to setup logger");
     }
 
     let mut restart_app = false;
     let mut restart_shell = false;
-    let args: Vec<String> = env::args().collect();
-    if args.len() >= 2 {
-        if args.contains(&"--notify".to_string()) {
-            if args.len() >= 3 {
-                if args[2] == "True" {
-                    restart_shell = true;
-                }
+    let allow_downgrade = args.contains(&"--allow-downgrade".to_string());
+    let force = args.contains(&"--force".to_string());
+    if args.len() >= 2 && args.contains(&"--notify".to_string()) {
+        if args.len() >= 3 {
+            if args[2] == "True" {
+                restart_shell = true;
             }
-            if args.len() >= 4 {
-                if args[3] == "True" {
-                    restart_app = true;
-                }
+        }
+        if args.len() >= 4 {
+            if args[3] == "True" {
+                restart_app = true;
             }
         }
-        if args.contains(&"--info".to_string()) {
-            license::display_license();
-            return Ok(());
+    }
+
+    let arg_value = |flag: &str| args.iter().position(|a| a == flag).and_then(|i| args.get(i + 1)).cloned();
+    let assembly_dir = arg_value(self_update::ASSEMBLY_DIR_ARG).map(PathBuf::from);
+    if let Some(dir) = &assembly_dir {
+        extensions::set_assembly_dir_override(dir.clone());
+    }
+    let options = RunOptions {
+        restart_shell,
+        restart_app,
+        allow_downgrade,
+        force,
+        archive: arg_value("--archive").map(PathBuf::from),
+        sha256: arg_value("--sha256"),
+        full_archive: arg_value("--full-archive").map(PathBuf::from),
+        relaunched: assembly_dir.is_some(),
+        retry: RetryPolicy::resolve(&args, |key| env::var(key).ok()),
+        deferral: DeferralPolicy::resolve(&args, |key| env::var(key).ok()),
+        args: args.clone(),
+    };
+    let lock_timeout = arg_value("--lock-timeout")
+        .and_then(|secs| secs.parse().ok())
+        .map(Duration::from_secs)
+        .unwrap_or(instance_lock::DEFAULT_TIMEOUT);
+    let mut report = UpdateReport::new(report_path(&log_config.path));
+    // held until main returns, a relaunched copy waits here for the original updater to exit
+    let started = Instant::now();
+    let lock = match InstanceLock::acquire(&get_working_dir(), lock_timeout) {
+        Ok(lock) => lock,
+        Err(op) => {
+            error!("{}", op);
+            report.record(Phase::Lock, started, &Err::<(), _>(op));
+            report.finish(EXIT_CODE_LOCKED);
+            std::process::exit(EXIT_CODE_LOCKED);
         }
+    };
+    let result = run(&options, &lock, &mut report);
+    if options.relaunched {
+        self_update::remove_relaunch_copy(&get_assembly_dir());
     }
+    restart_other_sessions(&report.stopped_sessions);
+    report.finish(if result.is_ok() { 0 } else { 1 });
+    result
+}
+
+struct RunOptions {
+    restart_shell: bool,
+    restart_app: bool,
+    allow_downgrade: bool,
+    force: bool,
+    /// The downloaded update archive, `adm-update-data/update.zip` if not given
+    archive: Option<PathBuf>,
+    /// Expected hash of the archive, read from `<archive>.sha256` if not given
+    sha256: Option<String>,
+    /// Full package used if a delta package fails to apply, `adm-update-data/update.full.zip` if not given
+    full_archive: Option<PathBuf>,
+    /// Running as the copy started by the original updater to replace the updater directory
+    relaunched: bool,
+    /// Retries for moving the installation and the payload, see `io_v3::RetryPolicy::resolve`
+    retry: RetryPolicy,
+    /// When the update may interrupt the user, see `deferral::DeferralPolicy::resolve`
+    deferral: DeferralPolicy,
+    args: Vec<String>,
+}
+
+fn run(options: &RunOptions, lock: &InstanceLock, report: &mut UpdateReport) -> Result<(), Box<dyn Error>> {
+    let RunOptions {
+        restart_shell,
+        restart_app,
+        allow_downgrade,
+        force,
+        ..
+    } = *options;
     info!("auto dark mode updater {}", VERSION);
     info!("cwd: {}", get_working_dir().display());
     info!("restart app: {}, restart shell: {}", restart_app, restart_shell);
+    if options.relaunched {
+        info!("running as relaunched copy for {}", get_assembly_dir().display());
+    } else {
+        self_update::clean_previous_self_update(&get_assembly_dir());
+    }
 
     let username = whoami::username();
-    let _curver = io_v2::get_file_version(get_service_path())
-        .and_then(|ver| {
-            info!("currently installed version: {}", ver);
-            Ok(ver)
-        })
-        .or_else(|e| {
-            warn!("could not read installed version: {}", e);
-            Err(e)
-        });
+    let layout = detect_layout(&get_working_dir());
+    info!("installation layout: {}", layout);
+    report.layout = Some(layout.to_string());
+    let installed_service = match layout {
+        InstallLayout::AppDir => get_service_path(),
+        InstallLayout::Flat => get_working_dir().join(extensions::SERVICE_EXE),
+        InstallLayout::Unknown => {
+            let op = OpError::new(
+                "no auto dark mode installation found in working directory, skipping update",
+                true,
+            );
+            error!("{}", op);
+            return Err(Box::new(op));
+        }
+    };
+    let installed_version = io_v2::get_file_version(installed_service)
+        .inspect(|ver| info!("currently installed version: {}", ver))
+        .inspect_err(|e| warn!("could not read installed version: {}", e))
+        .ok();
+    report.old_version = installed_version.as_ref().map(|v| v.to_string());
+
+    let install_kind = detect_install_kind(&username, &get_assembly_dir(), &get_working_dir());
+    info!("installation kind: {}", install_kind);
+    report.install_kind = Some(install_kind.to_string());
 
     let update_data_dir = get_update_data_dir();
-    let temp_dir = &update_data_dir.join("tmp");
+    let temp_dir = &install_kind.backup_dir(&get_working_dir(), &update_data_dir);
+
+    let archive = options
+        .archive
+        .clone()
+        .unwrap_or_else(|| update_data_dir.join(extract::ARCHIVE_FILE));
+    // the original updater already extracted the payload before relaunching
+    if options.relaunched {
+        debug!("using payload unpacked by the original updater");
+    } else if archive.exists() || options.archive.is_some() {
+        info!("extracting update archive {}", archive.display());
+        let started = Instant::now();
+        let extracted = match &options.sha256 {
+            Some(hash) => Ok(hash.clone()),
+            None => extract::read_hash_file(&archive),
+        }
+        .and_then(|hash| extract::extract_update(&archive, &hash, &update_data_dir.join("unpacked")));
+        report.record(Phase::Extract, started, &extracted);
+        if let Err(op) = extracted {
+            error!("{}", op);
+            try_relaunch(report, restart_shell, restart_app, &username, false);
+            return Err(Box::new(op));
+        }
+    } else {
+        debug!("no update archive at {}, using unpacked update files", archive.display());
+        report.skip(Phase::Extract, "no update archive");
+    }
 
-    shutdown_running_instances(&username).map_err(|op| {
-        error!("update process failed, restarting auto dark mode: {}", op);
-        try_relaunch(restart_shell, restart_app, &username, false);
-        op
-    })?;
+    let unpacked_dir = update_data_dir.join("unpacked");
+    if !options.relaunched && delta::is_delta_package(&unpacked_dir) {
+        let started = Instant::now();
+        let applied = match layout {
+            InstallLayout::AppDir => delta::apply_delta(&unpacked_dir, &get_adm_app_dir(), installed_version.as_ref()),
+            _ => Err(OpError::new(
+                "delta packages can only be applied to adm-app installations",
+                true,
+            )),
+        };
+        report.record(Phase::Delta, started, &applied);
+        if let Err(op) = applied {
+            warn!("{}, falling back to full package", op);
+            let full_archive = options
+                .full_archive
+                .clone()
+                .unwrap_or_else(|| update_data_dir.join(extract::FULL_ARCHIVE_FILE));
+            let started = Instant::now();
+            let extracted = delta::extract_full_package(&full_archive, &unpacked_dir);
+            report.record(Phase::Extract, started, &extracted);
+            if let Err(op) = extracted {
+                error!("full package unavailable, skipping update: {}", op);
+                try_relaunch(report, restart_shell, restart_app, &username, false);
+                return Err(Box::new(op));
+            }
+        }
+    }
+
+    // the new updater checks the payload, so it can accept payloads the current updater does not know yet
+    let new_updater = self_update::pending_updater(&unpacked_dir);
+    if let Some(new_updater) = new_updater.as_ref().filter(|_| !options.relaunched) {
+        let started = Instant::now();
+        let relaunched = self_update::relaunch_from_payload(&get_assembly_dir(), new_updater, &options.args);
+        report.record(Phase::SelfUpdate, started, &relaunched);
+        match relaunched {
+            Ok(()) => {
+                info!("updater payload found, the update continues in the relaunched updater");
+                return Ok(());
+            }
+            Err(op) => warn!("{}, continuing with the current updater", op),
+        }
+    }
+
+    let started = Instant::now();
+    let checked = check_update_payload(&update_data_dir, installed_version.as_ref(), allow_downgrade, force);
+    report.record(Phase::PayloadCheck, started, &checked);
+    if let Err(op) = checked {
+        error!("{}", op);
+        try_relaunch(report, restart_shell, restart_app, &username, false);
+        return Err(Box::new(op));
+    }
+
+    // the updater directory is only replaced once the new updater accepted the payload
+    if let Some(new_updater) = new_updater.as_ref().filter(|_| options.relaunched) {
+        let started = Instant::now();
+        let swapped = self_update::swap_updater_dir(&get_assembly_dir(), new_updater, &options.retry);
+        report.record(Phase::SelfUpdate, started, &swapped);
+        if let Err(op) = swapped {
+            warn!("{}, continuing with the current updater", op);
+        }
+    }
+
+    // with deferral enabled the service leaves the app and shell running, nothing has interrupted the user yet
+    if options.deferral.enabled {
+        let started = Instant::now();
+        let reason = options.deferral.wait_until_ready(&SystemClock, &DesktopProbe::default());
+        info!("starting update, {}", reason);
+        report.record(Phase::Deferral, started, &Ok::<_, OpError>(()));
+    }
+
+    let started = Instant::now();
+    let paths = PreflightPaths {
+        working_dir: &get_working_dir(),
+        update_data_dir: &update_data_dir,
+        backup_dir: temp_dir,
+    };
+    let preflight = run_preflight(&paths, lock);
+    report.record(Phase::Preflight, started, &preflight);
+    let keep = match preflight {
+        Ok(keep) => keep,
+        Err(op) => {
+            error!("pre-flight check failed, no update has been performed: {}", op);
+            try_relaunch(report, restart_shell, restart_app, &username, false);
+            return Err(Box::new(op));
+        }
+    };
 
+    // other users' instances first, if one of them cannot be stopped the current user's keeps running as well
+    let started = Instant::now();
+    let shutdown = match stop_other_sessions(&username) {
+        Ok(users) => {
+            report.stopped_sessions = users;
+            shutdown_running_instances(&username)
+        }
+        Err(op) => Err(Box::new(op) as Box<dyn Error>),
+    };
+    report.record(Phase::Shutdown, started, &shutdown);
+    let (restart_shell, restart_app) = match shutdown {
+        // still running if the service deferred stopping them to the updater
+        Ok((shell_running, app_running)) if options.deferral.enabled => {
+            (restart_shell || shell_running, restart_app || app_running)
+        }
+        Ok(_) => (restart_shell, restart_app),
+        Err(op) => {
+            error!("update process failed, restarting auto dark mode: {}", op);
+            try_relaunch(report, restart_shell, restart_app, &username, false);
+            return Err(op);
+        }
+    };
+
+    let migration = match layout {
+        InstallLayout::Flat => {
+            info!("migrating flat installation to {} layout", extensions::APP_DIR);
+            let excluded = [get_assembly_dir(), update_data_dir.clone()];
+            let started = Instant::now();
+            let migration =
+                io_v2::whitelist().and_then(|whitelist| migrate_flat_layout(&get_working_dir(), &excluded, whitelist));
+            report.record(Phase::Migration, started, &migration);
+            match migration {
+                Ok(migration) => Some(migration),
+                Err(op) => {
+                    // the migration already moved everything it touched back into place
+                    error!("migration failed, no update has been performed: {}", op);
+                    try_relaunch(report, restart_shell, restart_app, &username, false);
+                    return Err(Box::new(op));
+                }
+            }
+        }
+        _ => None,
+    };
+
+    // copied while the installed tree is still in place, it stays untouched as the backup for a rollback
+    let started = Instant::now();
+    let kept = if keep.is_empty() {
+        Ok(0)
+    } else {
+        keep.carry_over(&get_adm_app_dir(), &unpacked_dir.join(extensions::APP_DIR))
+    };
+    report.record(Phase::KeepFiles, started, &kept);
+    if let Err(op) = kept {
+        error!("could not carry kept files over, no update has been performed: {}", op);
+        undo_migration(migration.as_ref());
+        try_relaunch(report, restart_shell, restart_app, &username, false);
+        return Err(Box::new(op));
+    }
+
+    if install_kind == InstallKind::DevBuild && temp_dir.exists() {
+        info!("removing backup of previous dev build");
+        if let Err(e) = fs::remove_dir_all(temp_dir) {
+            warn!("could not remove previous backup {}: {}", temp_dir.display(), e);
+        }
+    }
     info!("moving current installation to temp directory");
-    move_to_temp(&temp_dir).map_err(|op| {
+    let started = Instant::now();
+    let release = |dir: &Path| release_adm_locks(&RestartManager, dir, &get_working_dir());
+    let moved = move_to_temp(&temp_dir, &options.retry, &release);
+    report.record(Phase::MoveToTemp, started, &moved);
+    if let Err(op) = moved {
         error!("{}", op);
-        try_relaunch(restart_shell, restart_app, &username, false);
-        op
-    })?;
+        undo_migration(migration.as_ref());
+        try_relaunch(report, restart_shell, restart_app, &username, false);
+        return Err(Box::new(op));
+    }
 
     info!("patching auto dark mode");
-    patch(&update_data_dir, &get_adm_app_dir()).map_err(|op| {
+    let started = Instant::now();
+    let patched = patch(&update_data_dir, &get_adm_app_dir(), &options.retry);
+    report.record(Phase::Patch, started, &patched);
+    if let Err(op) = patched {
         error!("patching failed, attempting rollback: {}", op);
-        if let Err(e) = rollback(&temp_dir) {
+        let rolled_back = rollback(&temp_dir, &options.retry);
+        report.rollback(&rolled_back);
+        if let Err(e) = rolled_back {
             error!("rollback failed, this is non-recoverable, please reinstall auto dark mode: {e}");
+            report.finish(-1);
             std::process::exit(-1);
         } else {
             info!("rollback successful, no update has been performed, restarting auto dark mode");
-            try_relaunch(restart_shell, restart_app, &username, false);
+            undo_migration(migration.as_ref());
+            try_relaunch(report, restart_shell, restart_app, &username, false);
         }
-        op
-    })?;
+        return Err(Box::new(op));
+    }
 
     info!("removing temporary update files");
-    clean_update_files(&update_data_dir);
+    clean_update_files(&update_data_dir, temp_dir);
 
     let mut patch_success_msg = "patch_complete".to_string();
     if let Ok(current_version) = io_v2::get_file_version(get_service_path()) {
         patch_success_msg.push_str(&format!(", installed version: {}", current_version).to_string());
-        info!("updating setup version string");
-        if let Err(e) = regedit::update_inno_installer_string(&username, &current_version.to_string()) {
-            if e.severe {
-                warn!("{}", e);
-            } else {
-                info!("{}", e);
+        report.new_version = Some(current_version.to_string());
+        if let Some(scope) = install_kind.registry_scope() {
+            info!("updating setup version string");
+            let started = Instant::now();
+            let updated = regedit::update_inno_installer_string(&scope, &current_version);
+            match &updated {
+                Err(e) if !e.severe => {
+                    info!("{}", e);
+                    report.skip(Phase::Registry, &e.message);
+                }
+                Err(e) => {
+                    warn!("{}", e);
+                    report.record(Phase::Registry, started, &updated);
+                }
+                Ok(()) => report.record(Phase::Registry, started, &updated),
             }
-        };
+        } else {
+            info!("{} installation, skipping installer version string update", install_kind);
+            report.skip(Phase::Registry, &format!("{} installation", install_kind));
+        }
     } else {
         warn!("could not read patched file version, skipping installer versin string update");
+        report.skip(Phase::Registry, "could not read patched file version");
     };
     info!("{}", patch_success_msg);
 
-    try_relaunch(restart_shell, restart_app, &username, true);
+    try_relaunch(report, restart_shell, restart_app, &username, true);
     Ok(())
 }
 
-fn shutdown_running_instances(channel: &str) -> Result<(), Box<dyn Error>> {
+/// Restores the flat layout after a failed update, so the previous installation keeps working
+fn undo_migration(migration: Option<&Migration>) {
+    if let Some(migration) = migration {
+        if let Err(e) = migration.undo() {
+            error!("could not restore flat installation, please reinstall auto dark mode: {}", e);
+        }
+    }
+}
+
+/// Compares the unpacked payload with the installed version and the running updater before anything is touched
+fn check_update_payload(
+    update_data_dir: &Path,
+    installed_version: Option<&Version>,
+    allow_downgrade: bool,
+    force: bool,
+) -> Result<(), OpError> {
+    let unpacked_dir = update_data_dir.join("unpacked");
+    let manifest = PayloadManifest::load(&unpacked_dir)?;
+    let payload_service = unpacked_dir
+        .join(extensions::APP_DIR)
+        .join("core")
+        .join(extensions::SERVICE_EXE);
+    let payload_version = match io_v2::get_file_version(payload_service) {
+        Ok(version) => Some(version),
+        Err(e) => {
+            warn!("could not read payload version: {}", e);
+            manifest.declared_version()?
+        }
+    };
+    if let Some(version) = &payload_version {
+        info!("payload version: {}", version);
+    }
+    let updater_version: Version = VERSION.parse()?;
+    check_payload(
+        &manifest,
+        installed_version,
+        payload_version.as_ref(),
+        &updater_version,
+        allow_downgrade,
+        force,
+    )
+}
+
+/// Stops the service, app and shell of the current user
+///
+/// Returns whether the shell and the app were running, the service leaves them running when the update is deferred
+fn shutdown_running_instances(channel: &str) -> Result<(bool, bool), Box<dyn Error>> {
     info!("stopping service gracefully");
+    // subscribe before requesting the exit, otherwise the shutdown event could be missed
+    let mut subscription = match subscribe(3000, channel) {
+        Ok(s) => Some(s),
+        Err(e) => {
+            debug!("event subscription unavailable, falling back to polling: {}", e);
+            None
+        }
+    };
     let mut api_shutdown_confirmed = false;
     if let Err(e) = send_message_and_get_reply("--exit", 3000, channel) {
         if e.is_timeout {
@@ -177,6 +538,15 @@ fn shutdown_running_instances(channel: &str) -> Result<(), Box<dyn Error>> {
             warn!("could not cleanly stop service: {}", e);
         }
     }
+    if !api_shutdown_confirmed {
+        if let Some(subscription) = subscription.as_mut() {
+            info!("waiting for service to announce shutdown");
+            match subscription.wait_for(5000, |e| *e == AdmEvent::ServiceShutdown) {
+                Ok(_) => api_shutdown_confirmed = true,
+                Err(e) => warn!("service did not announce shutdown: {}", e),
+            }
+        }
+    }
     if !api_shutdown_confirmed {
         info!("waiting for service to stop");
         for _ in 0..5 {
@@ -190,23 +560,25 @@ fn shutdown_running_instances(channel: &str) -> Result<(), Box<dyn Error>> {
 
     let retries = 3;
     shutdown_with_retries("AutoDarkModeSvc", "service", retries)?;
-    shutdown_with_retries("AutoDarkModeApp", "app", retries)?;
-    shutdown_with_retries("AutoDarkModeShell", "shell", retries)?;
+    let app_running = shutdown_with_retries("AutoDarkModeApp", "app", retries)?;
+    let shell_running = shutdown_with_retries("AutoDarkModeShell", "shell", retries)?;
 
     info!("adm has exited successfully");
-    Ok(())
+    Ok((shell_running, app_running))
 }
 
 /// Attempts to shut down the given process name for the current user
 ///
-/// Returns true if the process was found and a signal was sent, false otherwise
-fn shutdown_with_retries(process_name: &str, process_description: &str, retries: u8) -> Result<(), OpError> {
+/// Returns whether the process was running, or an error if it is still running after all retries
+fn shutdown_with_retries(process_name: &str, process_description: &str, retries: u8) -> Result<bool, OpError> {
     let mut success = false;
+    let mut was_running = false;
     for i in 0..retries {
         if !shutdown_process(process_name, process_description) {
             success = true;
             break;
         } else {
+            was_running = true;
             debug!(
                 "waiting for {} to stop, attempt {} out of {}",
                 process_description,
@@ -220,7 +592,7 @@ fn shutdown_with_retries(process_name: &str, process_description: &str, retries:
         let msg = format!("could not stop {}, skipping update", process_description);
         return Err(OpError::new(msg.as_str(), false));
     }
-    Ok(())
+    Ok(was_running)
 }
 
 /// Attempts to shut down the given process name for the current user
@@ -264,13 +636,13 @@ fn shutdown_process(process_name: &str, process_description: &str) -> bool {
     false
 }
 
-fn try_relaunch(restart_shell: bool, restart_app: bool, channel: &str, patch_success: bool) {
-    match relaunch(restart_shell, restart_app, &channel, patch_success) {
-        Ok(_) => {}
-        Err(e) => {
-            warn!("{}", e);
-        }
+fn try_relaunch(report: &mut UpdateReport, restart_shell: bool, restart_app: bool, channel: &str, patch_success: bool) {
+    let started = Instant::now();
+    let result = relaunch(restart_shell, restart_app, &channel, patch_success);
+    if let Err(e) = &result {
+        warn!("{}", e);
     }
+    report.record(Phase::Relaunch, started, &result);
 }
 
 fn relaunch(restart_shell: bool, restart_app: bool, channel: &str, patch_success: bool) -> Result<(), Box<dyn Error>> {
@@ -342,60 +714,6 @@ fn relaunch(restart_shell: bool, restart_app: bool, channel: &str, patch_success
     Ok(())
 }
 
-#[cfg(debug_assertions)]
-fn setup_logger() -> Result<(), fern::InitError> {
-    use platform_dirs::AppDirs;
-    let log_path = AppDirs::new(Some("AutoDarkMode"), false).map_or("updater.log".into(), |dirs| {
-        dirs.config_dir
-            .join("updater.log")
-            .to_str()
-            .unwrap_or("updater.log")
-            .to_string()
-    });
-    fern::Dispatch::new()
-        .format(|out, message, record| {
-            out.finish(format_args!(
-                "{} [{}] [{}] {}",
-                chrono::Local::now().format("%Y-%m-%d %H:%M:%S"),
-                record.level(),
-                record.target(),
-                message
-            ))
-        })
-        .level(log::LevelFilter::Debug)
-        .chain(std::io::stdout())
-        .chain(fern::log_file(log_path)?)
-        .apply()?;
-    Ok(())
-}
-
-#[cfg(not(debug_assertions))]
-fn setup_logger() -> Result<(), fern::InitError> {
-    use platform_dirs::AppDirs;
-    let log_path = AppDirs::new(Some("AutoDarkMode"), false).map_or("updater.log".into(), |dirs| {
-        dirs.config_dir
-            .join("updater.log")
-            .to_str()
-            .unwrap_or("updater.log")
-            .to_string()
-    });
-    fern::Dispatch::new()
-        .format(|out, message, record| {
-            out.finish(format_args!(
-                "{} [{}] [{}] {}",
-                chrono::Local::now().format("%Y-%m-%d %H:%M:%S"),
-                record.level(),
-                record.target(),
-                message
-            ))
-        })
-        .level(log::LevelFilter::Info)
-        .chain(std::io::stdout())
-        .chain(fern::log_file(log_path)?)
-        .apply()?;
-    Ok(())
-}
-
 #[cfg(test)]
 mod tests {
     use std::error::Error;
@@ -419,7 +737,13 @@ mod tests {
     fn try_relaunch_adm() -> Result<(), Box<dyn Error>> {
         setup_logger()?;
         let username = whoami::username();
-        try_relaunch(true, true, &username, true);
+        try_relaunch(
+            &mut UpdateReport::new(std::env::temp_dir().join("adm-report.json")),
+            true,
+            true,
+            &username,
+            true,
+        );
         Ok(())
     }
 }
@@ -0,0 +1,158 @@
+use std::{fs, path::Path};
+
+use log::{debug, warn};
+use serde::Deserialize;
+
+use crate::{
+    keep_list::KeepConflict,
+    version::{Version, VersionRange},
+    OpError,
+};
+
+pub const MANIFEST_FILE: &str = "manifest.json";
+
+/// Metadata shipped next to the unpacked update payload
+///
+/// Every field is optional, payloads built before the manifest existed simply do not ship one
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PayloadManifest {
+    pub version: Option<String>,
+    pub min_updater_version: Option<String>,
+    pub max_updater_version: Option<String>,
+    /// Patterns of files inside `adm-app` that are carried over from the installed version, see `KeepList`
+    #[serde(default)]
+    pub keep: Vec<String>,
+    pub keep_conflict: Option<KeepConflict>,
+}
+
+impl PayloadManifest {
+    /// Reads `manifest.json` from the unpacked directory, returning an empty manifest if the payload has none
+    pub fn load(unpacked_dir: &Path) -> Result<PayloadManifest, OpError> {
+        let path = unpacked_dir.join(MANIFEST_FILE);
+        if !path.exists() {
+            debug!("payload ships no manifest");
+            return Ok(PayloadManifest::default());
+        }
+        let content =
+            fs::read_to_string(&path).map_err(|e| OpError::new(&format!("could not read payload manifest: {}", e), true))?;
+        serde_json::from_str(&content).map_err(|e| OpError::new(&format!("invalid payload manifest: {}", e), true))
+    }
+
+    /// The version the payload declares, used if the version of the payload executables cannot be read
+    pub fn declared_version(&self) -> Result<Option<Version>, OpError> {
+        match &self.version {
+            Some(v) => Ok(Some(v.parse()?)),
+            None => Ok(None),
+        }
+    }
+
+    /// The updater versions this payload may be installed with, missing bounds are unrestricted
+    pub fn updater_range(&self) -> Result<VersionRange, OpError> {
+        let min = match &self.min_updater_version {
+            Some(v) => v.parse()?,
+            None => Version::new(0, 0, 0, 0),
+        };
+        let max = match &self.max_updater_version {
+            Some(v) => v.parse()?,
+            None => Version::new(u32::MAX, u32::MAX, u32::MAX, u32::MAX),
+        };
+        Ok(VersionRange::new(min, max))
+    }
+}
+
+/// Checks the payload against the installed version and the running updater
+///
+/// Downgrades and reinstalls of the same version are refused unless allowed, `force` skips every check
+pub fn check_payload(
+    manifest: &PayloadManifest,
+    installed: Option<&Version>,
+    payload: Option<&Version>,
+    updater: &Version,
+    allow_downgrade: bool,
+    force: bool,
+) -> Result<(), OpError> {
+    let problem = match validate(manifest, installed, payload, updater, allow_downgrade) {
+        Ok(()) => return Ok(()),
+        Err(e) => e,
+    };
+    if force {
+        warn!("{}, continuing because the update was forced", problem);
+        return Ok(());
+    }
+    Err(OpError::new(&format!("{}, skipping update", problem), true))
+}
+
+fn validate(
+    manifest: &PayloadManifest,
+    installed: Option<&Version>,
+    payload: Option<&Version>,
+    updater: &Version,
+    allow_downgrade: bool,
+) -> Result<(), String> {
+    let range = manifest.updater_range().map_err(|e| e.message)?;
+    if !range.contains(updater) {
+        return Err(format!("payload requires an updater in range {}, running {}", range, updater));
+    }
+    let payload = payload.ok_or_else(|| "could not determine payload version".to_string())?;
+    let installed = match installed {
+        Some(v) => v,
+        None => return Ok(()),
+    };
+    if payload == installed && !allow_downgrade {
+        return Err(format!("version {} is already installed", installed));
+    }
+    if payload < installed && !allow_downgrade {
+        return Err(format!(
+            "payload version {} is older than installed version {}",
+            payload, installed
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::test_dir;
+
+    fn v(s: &str) -> Version {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn refuses_downgrades_and_reinstalls() {
+        let manifest = PayloadManifest::default();
+        let updater = v("4.0.3");
+        let installed = v("11.0.0.23");
+        assert!(check_payload(&manifest, Some(&installed), Some(&v("11.0.0.24")), &updater, false, false).is_ok());
+        assert!(check_payload(&manifest, Some(&installed), Some(&v("11.0.0.23")), &updater, false, false).is_err());
+        assert!(check_payload(&manifest, Some(&installed), Some(&v("10.4.1.0")), &updater, false, false).is_err());
+        assert!(check_payload(&manifest, Some(&installed), Some(&v("10.4.1.0")), &updater, true, false).is_ok());
+        assert!(check_payload(&manifest, Some(&installed), Some(&v("10.4.1.0")), &updater, false, true).is_ok());
+        assert!(check_payload(&manifest, None, Some(&v("10.4.1.0")), &updater, false, false).is_ok());
+        assert!(check_payload(&manifest, Some(&installed), None, &updater, false, false).is_err());
+    }
+
+    #[test]
+    fn enforces_updater_range() {
+        let manifest: PayloadManifest =
+            serde_json::from_str(r#"{"version": "11.0.1.0", "minUpdaterVersion": "4.1", "maxUpdaterVersion": "4.99"}"#).unwrap();
+        let payload = v("11.0.1.0");
+        assert!(check_payload(&manifest, None, Some(&payload), &v("4.0.3"), true, false).is_err());
+        assert!(check_payload(&manifest, None, Some(&payload), &v("4.0.3"), false, true).is_ok());
+        assert!(check_payload(&manifest, None, Some(&payload), &v("4.1.0"), false, false).is_ok());
+        assert_eq!(manifest.declared_version().unwrap(), Some(payload));
+    }
+
+    #[test]
+    fn load_missing_and_invalid_manifests() {
+        let dir = test_dir("manifest-load");
+        assert!(PayloadManifest::load(&dir).unwrap().version.is_none());
+        fs::write(dir.join(MANIFEST_FILE), "{ not json").unwrap();
+        assert!(PayloadManifest::load(&dir).is_err());
+        fs::write(dir.join(MANIFEST_FILE), r#"{"minUpdaterVersion": "4.x"}"#).unwrap();
+        assert!(PayloadManifest::load(&dir).unwrap().updater_range().is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,230 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    process::Command,
+    thread,
+    time::Duration,
+};
+
+use log::{debug, info, warn};
+use sysinfo::{Pid, ProcessRefreshKind, ProcessesToUpdate, System, UpdateKind, Users};
+
+use crate::{
+    comms::{send_message_with_transport, Transport},
+    extensions::{get_working_dir, is_below},
+    OpError,
+};
+
+const ADM_PROCESSES: [&str; 3] = ["AutoDarkModeSvc", "AutoDarkModeApp", "AutoDarkModeShell"];
+/// How long another user's service gets to exit after being asked to, before it is killed
+const EXIT_WAIT: Duration = Duration::from_secs(10);
+/// How long another user's processes get to exit after being killed
+const KILL_WAIT: Duration = Duration::from_secs(3);
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// A running auto dark mode process and the user it belongs to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AdmProcess {
+    pub pid: u32,
+    pub name: String,
+    pub user: Option<String>,
+    /// Full path of the executable, if it could be read
+    pub exe: Option<PathBuf>,
+}
+
+/// Groups the auto dark mode processes of all users except the current one by lowercase user name
+///
+/// Only processes started from the installation in `install_dir` count, other installations are not updated.
+/// Windows user names are case insensitive, and so are the task names derived from them.
+/// Processes of unknown users are skipped, like `shutdown_process` does for the current user.
+pub fn other_users(processes: &[AdmProcess], current_user: &str, install_dir: &Path) -> BTreeMap<String, Vec<u32>> {
+    let mut users: BTreeMap<String, Vec<u32>> = BTreeMap::new();
+    for process in processes {
+        let is_adm = ADM_PROCESSES
+            .iter()
+            .any(|name| process.name.trim_end_matches(".exe").eq_ignore_ascii_case(name))
+            && process.exe.as_deref().is_some_and(|exe| is_below(exe, install_dir));
+        match &process.user {
+            Some(user) if is_adm && !user.eq_ignore_ascii_case(current_user) => {
+                users.entry(user.to_lowercase()).or_default().push(process.pid)
+            }
+            _ => {}
+        }
+    }
+    users
+}
+
+fn running_processes() -> Vec<AdmProcess> {
+    let mut s = System::new();
+    s.refresh_processes_specifics(
+        ProcessesToUpdate::All,
+        true,
+        ProcessRefreshKind::nothing()
+            .with_user(UpdateKind::OnlyIfNotSet)
+            .with_exe(UpdateKind::OnlyIfNotSet),
+    );
+    let users = Users::new_with_refreshed_list();
+    s.processes()
+        .values()
+        .map(|p| AdmProcess {
+            pid: p.pid().as_u32(),
+            name: p.name().to_string_lossy().to_string(),
+            user: p
+                .user_id()
+                .and_then(|id| users.get_user_by_id(id))
+                .map(|user| user.name().to_string()),
+            exe: p.exe().map(Path::to_path_buf),
+        })
+        .collect()
+}
+
+/// Stops auto dark mode for every other signed in user, so their processes do not keep files in `adm-app` open
+///
+/// Each service is asked to exit through its own pipe, which administrators may send `--exit` to, so it can finish
+/// writing its files. Whatever is still running after the timeout is killed, which also requires administrative rights.
+/// If any user's instance cannot be stopped, the ones stopped so far are restarted and the update is aborted before any
+/// file is touched. Returns the users whose instances were stopped.
+pub fn stop_other_sessions(current_user: &str) -> Result<Vec<String>, OpError> {
+    let users = other_users(&running_processes(), current_user, &get_working_dir());
+    if users.is_empty() {
+        debug!("auto dark mode is not running for other users");
+        return Ok(Vec::new());
+    }
+    let mut stopped = Vec::new();
+    for (user, pids) in users {
+        info!("stopping auto dark mode for user {}", user);
+        if let Err(op) = stop_user(&user, &pids) {
+            restart_other_sessions(&stopped);
+            return Err(op);
+        }
+        stopped.push(user);
+    }
+    Ok(stopped)
+}
+
+fn stop_user(user: &str, pids: &[u32]) -> Result<(), OpError> {
+    let mut s = System::new();
+    let pids: Vec<Pid> = pids.iter().map(|pid| Pid::from_u32(*pid)).collect();
+    s.refresh_processes_specifics(ProcessesToUpdate::Some(&pids), true, ProcessRefreshKind::nothing());
+    let service: Vec<u32> = s
+        .processes()
+        .values()
+        .filter(|p| {
+            p.name()
+                .to_string_lossy()
+                .trim_end_matches(".exe")
+                .eq_ignore_ascii_case(ADM_PROCESSES[0])
+        })
+        .map(|p| p.pid().as_u32())
+        .collect();
+    if !service.is_empty() {
+        // the service closes the pipe while exiting, so a timeout means it received the message
+        if let Err(e) = send_message_with_transport("--exit", 3000, user, Transport::Pipe) {
+            if !e.is_timeout {
+                warn!("could not ask the service of user {} to exit: {}", user, e);
+            }
+        }
+        if !wait_for_exit(&service, EXIT_WAIT) {
+            warn!("service of user {} did not exit in time, killing it", user);
+        }
+    }
+    s.refresh_processes_specifics(ProcessesToUpdate::Some(&pids), true, ProcessRefreshKind::nothing());
+    for process in s.processes().values() {
+        if !process.kill() {
+            debug!("could not send kill signal to {}", process.pid());
+        }
+    }
+    let pids: Vec<u32> = pids.iter().map(|pid| pid.as_u32()).collect();
+    if wait_for_exit(&pids, KILL_WAIT) {
+        return Ok(());
+    }
+    Err(OpError::new(
+        &format!(
+            "auto dark mode is running for user {} and could not be stopped, run the update as administrator or \
+             sign that user out, skipping update",
+            user
+        ),
+        false,
+    ))
+}
+
+/// Returns true once none of the processes is running anymore
+fn wait_for_exit(pids: &[u32], timeout: Duration) -> bool {
+    let pids: Vec<Pid> = pids.iter().map(|pid| Pid::from_u32(*pid)).collect();
+    let mut waited = Duration::ZERO;
+    loop {
+        let mut s = System::new();
+        s.refresh_processes_specifics(ProcessesToUpdate::Some(&pids), true, ProcessRefreshKind::nothing());
+        if s.processes().is_empty() {
+            return true;
+        }
+        if waited >= timeout {
+            return false;
+        }
+        thread::sleep(POLL_INTERVAL);
+        waited += POLL_INTERVAL;
+    }
+}
+
+/// Restarts auto dark mode for the given users through their logon task
+///
+/// Running another user's task needs the right to do so, otherwise auto dark mode starts on their next logon
+pub fn restart_other_sessions(users: &[String]) {
+    for user in users {
+        let task = format!("\\ADM_{}\\ADM Logon", user);
+        match Command::new("schtasks").args(["/Run", "/TN", &task]).output() {
+            Ok(output) if output.status.success() => info!("restarted auto dark mode for user {}", user),
+            Ok(output) => warn!(
+                "could not restart auto dark mode for user {}, it starts again on their next logon: {}",
+                user,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ),
+            Err(e) => warn!(
+                "could not restart auto dark mode for user {}, it starts again on their next logon: {}",
+                user, e
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn process(pid: u32, name: &str, user: Option<&str>) -> AdmProcess {
+        AdmProcess {
+            pid,
+            name: name.to_string(),
+            user: user.map(str::to_string),
+            exe: Some(Path::new("C:/Program Files/AutoDarkMode/adm-app").join(name)),
+        }
+    }
+
+    #[test]
+    fn groups_other_users_instances() {
+        let processes = [
+            process(1, "AutoDarkModeSvc.exe", Some("alice")),
+            process(2, "AutoDarkModeSvc.exe", Some("Bob")),
+            process(3, "AutoDarkModeShell.exe", Some("bob")),
+            process(4, "AutoDarkModeApp.exe", Some("carol")),
+            process(5, "AutoDarkModeUpdater.exe", Some("carol")),
+            process(6, "explorer.exe", Some("dave")),
+            process(7, "AutoDarkModeSvc.exe", None),
+        ];
+        let install_dir = Path::new("C:/Program Files/AutoDarkMode");
+        let users = other_users(&processes, "Alice", install_dir);
+        assert_eq!(users.len(), 2);
+        assert_eq!(users["bob"], vec![2, 3]);
+        assert_eq!(users["carol"], vec![4]);
+        assert!(other_users(&processes[..1], "alice", install_dir).is_empty());
+
+        // another installation of the same user is not part of this update
+        let mut elsewhere = process(8, "AutoDarkModeSvc.exe", Some("erin"));
+        elsewhere.exe = Some(PathBuf::from("D:/Portable/adm-app/AutoDarkModeSvc.exe"));
+        let unreadable = AdmProcess {
+            exe: None,
+            ..process(9, "AutoDarkModeSvc.exe", Some("frank"))
+        };
+        assert!(other_users(&[elsewhere, unreadable], "alice", install_dir).is_empty());
+    }
+}
@@ -0,0 +1,467 @@
+use std::{collections::HashMap, fs, path::Path};
+
+use crate::OpError;
+
+const RT_VERSION: u32 = 16;
+const RESOURCE_DIRECTORY_INDEX: usize = 2;
+const FIXED_FILE_INFO_SIGNATURE: u32 = 0xFEEF04BD;
+const FIXED_FILE_INFO_LEN: usize = 52;
+
+/// The VS_FIXEDFILEINFO block of a version resource
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct FixedFileInfo {
+    pub file_version_ms: u32,
+    pub file_version_ls: u32,
+    pub product_version_ms: u32,
+    pub product_version_ls: u32,
+}
+
+impl FixedFileInfo {
+    /// Returns the binary file version as major, minor, build and revision
+    pub fn file_version(&self) -> (u16, u16, u16, u16) {
+        split_version(self.file_version_ms, self.file_version_ls)
+    }
+
+    /// Returns the binary product version as major, minor, build and revision
+    pub fn product_version(&self) -> (u16, u16, u16, u16) {
+        split_version(self.product_version_ms, self.product_version_ls)
+    }
+}
+
+/// Version information embedded in a PE file, read without any help from the OS
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct PeVersionInfo {
+    pub fixed: FixedFileInfo,
+    /// Entries of the first StringFileInfo table, keyed by their name
+    pub strings: HashMap<String, String>,
+}
+
+impl PeVersionInfo {
+    pub fn product_version(&self) -> Option<&str> {
+        self.strings.get("ProductVersion").map(|s| s.as_str())
+    }
+
+    pub fn file_description(&self) -> Option<&str> {
+        self.strings.get("FileDescription").map(|s| s.as_str())
+    }
+
+    pub fn company_name(&self) -> Option<&str> {
+        self.strings.get("CompanyName").map(|s| s.as_str())
+    }
+}
+
+/// Reads the version resource of the PE file at the given path
+pub fn read_version_info(path: &Path) -> Result<PeVersionInfo, OpError> {
+    let data = fs::read(path).map_err(|e| OpError::new(&format!("could not read pe file {}: {}", path.display(), e), false))?;
+    parse_version_info(&data)
+}
+
+/// Extracts the version resource from the raw bytes of a PE file
+pub fn parse_version_info(data: &[u8]) -> Result<PeVersionInfo, OpError> {
+    let image = PeImage::parse(data)?;
+    let version_block = image.version_resource()?;
+    parse_version_block(version_block)
+}
+
+fn split_version(ms: u32, ls: u32) -> (u16, u16, u16, u16) {
+    ((ms >> 16) as u16, ms as u16, (ls >> 16) as u16, ls as u16)
+}
+
+struct Section {
+    virtual_address: u32,
+    virtual_size: u32,
+    raw_size: u32,
+    raw_offset: u32,
+}
+
+struct PeImage<'a> {
+    data: &'a [u8],
+    sections: Vec<Section>,
+    resource_rva: u32,
+}
+
+impl<'a> PeImage<'a> {
+    fn parse(data: &'a [u8]) -> Result<PeImage<'a>, OpError> {
+        if data.get(0..2) != Some(b"MZ") {
+            return Err(pe_error("missing dos header"));
+        }
+        let pe_offset = read_u32(data, 0x3C)? as usize;
+        if data.get(pe_offset..pe_offset + 4) != Some(b"PE\0\0") {
+            return Err(pe_error("missing pe signature"));
+        }
+        let coff = pe_offset + 4;
+        let section_count = read_u16(data, coff + 2)? as usize;
+        let optional_header_size = read_u16(data, coff + 16)? as usize;
+        let optional = coff + 20;
+        let (rva_count_offset, directories) = match read_u16(data, optional)? {
+            0x10B => (optional + 92, optional + 96),
+            0x20B => (optional + 108, optional + 112),
+            magic => return Err(pe_error(&format!("unknown optional header magic {:#x}", magic))),
+        };
+        if read_u32(data, rva_count_offset)? as usize <= RESOURCE_DIRECTORY_INDEX {
+            return Err(pe_error("no resource directory"));
+        }
+        let resource_rva = read_u32(data, directories + RESOURCE_DIRECTORY_INDEX * 8)?;
+        if resource_rva == 0 {
+            return Err(pe_error("no resources"));
+        }
+
+        let section_table = optional + optional_header_size;
+        let sections = (0..section_count)
+            .map(|i| {
+                let entry = section_table + i * 40;
+                Ok(Section {
+                    virtual_size: read_u32(data, entry + 8)?,
+                    virtual_address: read_u32(data, entry + 12)?,
+                    raw_size: read_u32(data, entry + 16)?,
+                    raw_offset: read_u32(data, entry + 20)?,
+                })
+            })
+            .collect::<Result<Vec<Section>, OpError>>()?;
+        Ok(PeImage {
+            data,
+            sections,
+            resource_rva,
+        })
+    }
+
+    fn rva_to_offset(&self, rva: u32) -> Result<usize, OpError> {
+        self.sections
+            .iter()
+            .find(|s| rva >= s.virtual_address && rva - s.virtual_address < s.virtual_size.max(s.raw_size))
+            .ok_or_else(|| pe_error(&format!("rva {:#x} is not mapped by any section", rva)))
+            .and_then(|s| {
+                (rva - s.virtual_address)
+                    .checked_add(s.raw_offset)
+                    .map(|offset| offset as usize)
+                    .ok_or_else(|| pe_error(&format!("rva {:#x} maps outside of the file", rva)))
+            })
+    }
+
+    /// Walks the type, name and language levels of the resource tree down to the first version resource
+    fn version_resource(&self) -> Result<&'a [u8], OpError> {
+        let base = self.rva_to_offset(self.resource_rva)?;
+        let type_entry = self
+            .find_entry(base, base, Some(RT_VERSION))?
+            .ok_or_else(|| pe_error("no version resource"))?;
+        let name_dir = subdirectory(base, type_entry)?;
+        let name_entry = self
+            .find_entry(base, name_dir, None)?
+            .ok_or_else(|| pe_error("empty version resource directory"))?;
+        let language_dir = subdirectory(base, name_entry)?;
+        let language_entry = self
+            .find_entry(base, language_dir, None)?
+            .ok_or_else(|| pe_error("empty version language directory"))?;
+        if language_entry & 0x8000_0000 != 0 {
+            return Err(pe_error("unexpected resource directory nesting"));
+        }
+        let data_entry = base + language_entry as usize;
+        let data_rva = read_u32(self.data, data_entry)?;
+        let data_size = read_u32(self.data, data_entry + 4)? as usize;
+        let offset = self.rva_to_offset(data_rva)?;
+        offset
+            .checked_add(data_size)
+            .and_then(|end| self.data.get(offset..end))
+            .ok_or_else(|| pe_error("version resource exceeds file"))
+    }
+
+    /// Returns the data offset of the entry with the given id, or of the first entry if no id is given
+    fn find_entry(&self, base: usize, directory: usize, id: Option<u32>) -> Result<Option<u32>, OpError> {
+        let named = read_u16(self.data, directory + 12)? as usize;
+        let ids = read_u16(self.data, directory + 14)? as usize;
+        for i in 0..named + ids {
+            let entry = directory + 16 + i * 8;
+            let name = read_u32(self.data, entry)?;
+            let offset = read_u32(self.data, entry + 4)?;
+            if base + (offset & 0x7FFF_FFFF) as usize >= self.data.len() {
+                return Err(pe_error("resource entry points outside of file"));
+            }
+            match id {
+                Some(id) if name & 0x8000_0000 == 0 && name == id => return Ok(Some(offset)),
+                Some(_) => continue,
+                None => return Ok(Some(offset)),
+            }
+        }
+        Ok(None)
+    }
+}
+
+fn subdirectory(base: usize, entry: u32) -> Result<usize, OpError> {
+    if entry & 0x8000_0000 == 0 {
+        return Err(pe_error("expected resource subdirectory"));
+    }
+    Ok(base + (entry & 0x7FFF_FFFF) as usize)
+}
+
+/// A node of the VS_VERSIONINFO tree
+struct Block<'a> {
+    key: String,
+    is_text: bool,
+    value: &'a [u8],
+    children: &'a [u8],
+    length: usize,
+}
+
+fn parse_block(data: &[u8]) -> Result<Block<'_>, OpError> {
+    let length = read_u16(data, 0)? as usize;
+    let value_length = read_u16(data, 2)? as usize;
+    let is_text = read_u16(data, 4)? == 1;
+    if length < 6 || length > data.len() {
+        return Err(pe_error("invalid version block length"));
+    }
+    let data = &data[..length];
+    let (key, key_end) = read_utf16_z(data, 6)?;
+    let value_start = align4(key_end);
+    let value_len = if is_text { value_length * 2 } else { value_length };
+    let value_end = (value_start + value_len).min(length);
+    let value = data.get(value_start..value_end).unwrap_or_default();
+    let children = data.get(align4(value_end).min(length)..).unwrap_or_default();
+    Ok(Block {
+        key,
+        is_text,
+        value,
+        children,
+        length,
+    })
+}
+
+fn child_blocks(data: &[u8]) -> Result<Vec<Block<'_>>, OpError> {
+    let mut blocks = Vec::new();
+    let mut pos = 0;
+    while pos + 6 <= data.len() {
+        let block = parse_block(&data[pos..])?;
+        pos += align4(block.length);
+        blocks.push(block);
+    }
+    Ok(blocks)
+}
+
+fn parse_version_block(data: &[u8]) -> Result<PeVersionInfo, OpError> {
+    let root = parse_block(data)?;
+    if root.key != "VS_VERSION_INFO" {
+        return Err(pe_error(&format!("unexpected version block {}", root.key)));
+    }
+    let fixed = parse_fixed_file_info(root.value)?;
+    let mut strings = HashMap::new();
+    for child in child_blocks(root.children)?.iter().filter(|b| b.key == "StringFileInfo") {
+        if let Some(table) = child_blocks(child.children)?.first() {
+            for entry in child_blocks(table.children)? {
+                let value = if entry.is_text {
+                    read_utf16_z(entry.value, 0).map(|(s, _)| s).unwrap_or_default()
+                } else {
+                    String::new()
+                };
+                strings.insert(entry.key, value);
+            }
+            break;
+        }
+    }
+    Ok(PeVersionInfo { fixed, strings })
+}
+
+fn parse_fixed_file_info(value: &[u8]) -> Result<FixedFileInfo, OpError> {
+    if value.len() < FIXED_FILE_INFO_LEN || read_u32(value, 0)? != FIXED_FILE_INFO_SIGNATURE {
+        return Err(pe_error("missing fixed file info"));
+    }
+    Ok(FixedFileInfo {
+        file_version_ms: read_u32(value, 8)?,
+        file_version_ls: read_u32(value, 12)?,
+        product_version_ms: read_u32(value, 16)?,
+        product_version_ls: read_u32(value, 20)?,
+    })
+}
+
+/// Reads a null terminated UTF-16 string, returning it with the offset right after the terminator
+fn read_utf16_z(data: &[u8], offset: usize) -> Result<(String, usize), OpError> {
+    let mut units = Vec::new();
+    let mut pos = offset;
+    while pos + 2 <= data.len() {
+        let unit = u16::from_le_bytes([data[pos], data[pos + 1]]);
+        pos += 2;
+        if unit == 0 {
+            return Ok((String::from_utf16_lossy(&units), pos));
+        }
+        units.push(unit);
+    }
+    // strings that run up to the end of their block are valid, only the terminator is missing
+    Ok((String::from_utf16_lossy(&units), pos))
+}
+
+fn align4(offset: usize) -> usize {
+    (offset + 3) & !3
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Result<u16, OpError> {
+    data.get(offset..offset + 2)
+        .map(|b| u16::from_le_bytes([b[0], b[1]]))
+        .ok_or_else(|| pe_error("unexpected end of file"))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Result<u32, OpError> {
+    data.get(offset..offset + 4)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .ok_or_else(|| pe_error("unexpected end of file"))
+}
+
+fn pe_error(message: &str) -> OpError {
+    OpError::new(&format!("could not parse pe version resource: {}", message), false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utf16_z(s: &str) -> Vec<u8> {
+        s.encode_utf16()
+            .chain(std::iter::once(0))
+            .flat_map(|u| u.to_le_bytes())
+            .collect()
+    }
+
+    fn pad4(buf: &mut Vec<u8>) {
+        while !buf.len().is_multiple_of(4) {
+            buf.push(0);
+        }
+    }
+
+    /// Serializes a version block the way the resource compiler lays it out
+    fn block(key: &str, text: bool, value: &[u8], children: &[Vec<u8>]) -> Vec<u8> {
+        let mut buf = vec![0u8; 6];
+        buf.extend(utf16_z(key));
+        pad4(&mut buf);
+        buf.extend_from_slice(value);
+        for child in children {
+            pad4(&mut buf);
+            buf.extend(child);
+        }
+        let value_length = if text { value.len() / 2 } else { value.len() };
+        let length = buf.len() as u16;
+        buf[0..2].copy_from_slice(&length.to_le_bytes());
+        buf[2..4].copy_from_slice(&(value_length as u16).to_le_bytes());
+        buf[4..6].copy_from_slice(&(text as u16).to_le_bytes());
+        buf
+    }
+
+    fn version_resource(version: (u16, u16, u16, u16), strings: &[(&str, &str)]) -> Vec<u8> {
+        let mut fixed = Vec::new();
+        let ms = ((version.0 as u32) << 16) | version.1 as u32;
+        let ls = ((version.2 as u32) << 16) | version.3 as u32;
+        for field in [FIXED_FILE_INFO_SIGNATURE, 0x10000, ms, ls, ms, ls, 0x3F, 0, 4, 1, 0, 0, 0] {
+            fixed.extend(field.to_le_bytes());
+        }
+        let entries: Vec<Vec<u8>> = strings
+            .iter()
+            .map(|(key, value)| block(key, true, &utf16_z(value), &[]))
+            .collect();
+        let table = block("040904b0", true, &[], &entries);
+        let string_file_info = block("StringFileInfo", true, &[], &[table]);
+        let translation = block("Translation", false, &[0x09, 0x04, 0xB0, 0x04], &[]);
+        let var_file_info = block("VarFileInfo", true, &[], &[translation]);
+        block("VS_VERSION_INFO", false, &fixed, &[var_file_info, string_file_info])
+    }
+
+    /// Builds a PE image with a single .rsrc section holding the given version resource
+    fn build_pe(pe32_plus: bool, version: &[u8]) -> Vec<u8> {
+        const SECTION_RVA: u32 = 0x2000;
+        const SECTION_OFFSET: usize = 0x400;
+        let optional_size: usize = if pe32_plus { 240 } else { 224 };
+
+        let mut rsrc = Vec::new();
+        let directory = |rsrc: &mut Vec<u8>, id: u32, target: u32| {
+            rsrc.extend([0u8; 12]);
+            rsrc.extend(0u16.to_le_bytes());
+            rsrc.extend(1u16.to_le_bytes());
+            rsrc.extend(id.to_le_bytes());
+            rsrc.extend(target.to_le_bytes());
+        };
+        directory(&mut rsrc, RT_VERSION, 0x8000_0000 | 24);
+        directory(&mut rsrc, 1, 0x8000_0000 | 48);
+        directory(&mut rsrc, 0x409, 72);
+        rsrc.extend((SECTION_RVA + 88).to_le_bytes());
+        rsrc.extend((version.len() as u32).to_le_bytes());
+        rsrc.extend([0u8; 8]);
+        rsrc.extend_from_slice(version);
+
+        let mut pe = vec![0u8; SECTION_OFFSET];
+        pe[0..2].copy_from_slice(b"MZ");
+        pe[0x3C..0x40].copy_from_slice(&0x80u32.to_le_bytes());
+        pe[0x80..0x84].copy_from_slice(b"PE\0\0");
+        let coff = 0x84;
+        pe[coff..coff + 2].copy_from_slice(&(if pe32_plus { 0x8664u16 } else { 0x14Cu16 }).to_le_bytes());
+        pe[coff + 2..coff + 4].copy_from_slice(&1u16.to_le_bytes());
+        pe[coff + 16..coff + 18].copy_from_slice(&(optional_size as u16).to_le_bytes());
+        let optional = coff + 20;
+        let (magic, rva_count, directories) = if pe32_plus {
+            (0x20Bu16, optional + 108, optional + 112)
+        } else {
+            (0x10Bu16, optional + 92, optional + 96)
+        };
+        pe[optional..optional + 2].copy_from_slice(&magic.to_le_bytes());
+        pe[rva_count..rva_count + 4].copy_from_slice(&16u32.to_le_bytes());
+        let resource_dir = directories + RESOURCE_DIRECTORY_INDEX * 8;
+        pe[resource_dir..resource_dir + 4].copy_from_slice(&SECTION_RVA.to_le_bytes());
+        pe[resource_dir + 4..resource_dir + 8].copy_from_slice(&(rsrc.len() as u32).to_le_bytes());
+        let section = optional + optional_size;
+        pe[section..section + 5].copy_from_slice(b".rsrc");
+        pe[section + 8..section + 12].copy_from_slice(&(rsrc.len() as u32).to_le_bytes());
+        pe[section + 12..section + 16].copy_from_slice(&SECTION_RVA.to_le_bytes());
+        pe[section + 16..section + 20].copy_from_slice(&(rsrc.len() as u32).to_le_bytes());
+        pe[section + 20..section + 24].copy_from_slice(&(SECTION_OFFSET as u32).to_le_bytes());
+        pe.extend(rsrc);
+        pe
+    }
+
+    #[test]
+    fn parses_pe32_plus_version_resource() {
+        let resource = version_resource(
+            (11, 0, 0, 23),
+            &[
+                ("CompanyName", "Auto Dark Mode"),
+                ("FileDescription", "Auto Dark Mode Service"),
+                ("ProductVersion", "11.0.0.23-beta"),
+            ],
+        );
+        let info = parse_version_info(&build_pe(true, &resource)).unwrap();
+        assert_eq!(info.fixed.file_version(), (11, 0, 0, 23));
+        assert_eq!(info.fixed.product_version(), (11, 0, 0, 23));
+        assert_eq!(info.company_name(), Some("Auto Dark Mode"));
+        assert_eq!(info.file_description(), Some("Auto Dark Mode Service"));
+        assert_eq!(info.product_version(), Some("11.0.0.23-beta"));
+    }
+
+    #[test]
+    fn parses_pe32_version_resource() {
+        let resource = version_resource((4, 0, 3, 0), &[("FileDescription", "Auto Dark Mode Updater")]);
+        let info = parse_version_info(&build_pe(false, &resource)).unwrap();
+        assert_eq!(info.fixed.file_version(), (4, 0, 3, 0));
+        assert_eq!(info.file_description(), Some("Auto Dark Mode Updater"));
+        assert_eq!(info.company_name(), None);
+    }
+
+    #[test]
+    fn rejects_files_without_version_resource() {
+        assert!(parse_version_info(b"not a pe file").is_err());
+        let mut pe = build_pe(true, &version_resource((1, 0, 0, 0), &[]));
+        // point the type entry at a different resource type
+        pe[0x400 + 16..0x400 + 20].copy_from_slice(&3u32.to_le_bytes());
+        assert!(parse_version_info(&pe).is_err());
+        let truncated = build_pe(true, &version_resource((1, 0, 0, 0), &[]));
+        assert!(parse_version_info(&truncated[..truncated.len() - 40]).is_err());
+    }
+
+    #[test]
+    fn rejects_section_offsets_beyond_address_space() {
+        let image = PeImage {
+            data: &[],
+            sections: vec![Section {
+                virtual_address: 0x1000,
+                virtual_size: 0x100,
+                raw_size: 0x100,
+                raw_offset: u32::MAX - 4,
+            }],
+            resource_rva: 0x1000,
+        };
+        assert!(image.rva_to_offset(0x1000).is_ok());
+        assert!(image.rva_to_offset(0x1010).is_err());
+    }
+}
@@ -0,0 +1,172 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use log::{debug, info};
+use walkdir::WalkDir;
+use windows::core::HSTRING;
+use windows::Win32::Storage::FileSystem::{GetDiskFreeSpaceExW, GetVolumePathNameW};
+
+use crate::{extensions, instance_lock::InstanceLock, keep_list::KeepList, manifest::PayloadManifest, OpError};
+
+/// Free space kept on top of the payload size, the service writes its config and logs to the same volume
+const SPACE_RESERVE: u64 = 50 * 1024 * 1024;
+
+/// The directories the update moves files between
+pub struct PreflightPaths<'a> {
+    pub working_dir: &'a Path,
+    pub update_data_dir: &'a Path,
+    /// Where `move_to_temp` puts the current installation
+    pub backup_dir: &'a Path,
+}
+
+/// Checks everything `move_to_temp` and `patch` rely on while auto dark mode is still running
+///
+/// Nothing is stopped or moved yet, so a failed check aborts the update without any user visible interruption.
+/// Returns the keep list, which is loaded here so a broken one is found before anything is stopped.
+pub fn run_preflight(paths: &PreflightPaths, lock: &InstanceLock) -> Result<KeepList, OpError> {
+    lock.verify()?;
+    let unpacked_dir = paths.update_data_dir.join("unpacked");
+    let payload_size = check_payload_present(&unpacked_dir.join(extensions::APP_DIR))?;
+    debug!("payload size: {} bytes", payload_size);
+    let keep = PayloadManifest::load(&unpacked_dir).and_then(|manifest| KeepList::load(&manifest, paths.working_dir))?;
+
+    for dir in [paths.working_dir, paths.update_data_dir, existing_ancestor(paths.backup_dir)] {
+        check_write_access(dir)?;
+    }
+    let install_volume = volume_of(paths.working_dir)?;
+    check_disk_space(payload_size + SPACE_RESERVE, free_space(paths.working_dir)?)?;
+    for dir in [paths.update_data_dir, paths.backup_dir] {
+        let dir = existing_ancestor(dir);
+        let volume = volume_of(dir)?;
+        if !volume.as_os_str().eq_ignore_ascii_case(install_volume.as_os_str()) {
+            // io_v3 falls back to copying, which needs room for a full copy on the other volume as well
+            info!(
+                "{} is on volume {}, the installation on {}, files will be copied instead of moved",
+                dir.display(),
+                volume.display(),
+                install_volume.display()
+            );
+            check_disk_space(payload_size + SPACE_RESERVE, free_space(dir)?)?;
+        }
+    }
+    info!("pre-flight checks passed");
+    Ok(keep)
+}
+
+/// Returns the size of the unpacked payload, failing if it is missing or has no service executable
+fn check_payload_present(payload_dir: &Path) -> Result<u64, OpError> {
+    let service = payload_dir.join("core").join(extensions::SERVICE_EXE);
+    if !service.is_file() {
+        return Err(OpError::new(
+            &format!("update payload is incomplete, {} is missing", service.display()),
+            false,
+        ));
+    }
+    let mut size = 0;
+    for entry in WalkDir::new(payload_dir) {
+        let entry = entry.map_err(|e| OpError::new(&format!("could not read update payload: {}", e), false))?;
+        if entry.file_type().is_file() {
+            size += entry
+                .metadata()
+                .map_err(|e| OpError::new(&format!("could not read update payload: {}", e), false))?
+                .len();
+        }
+    }
+    Ok(size)
+}
+
+fn check_disk_space(required: u64, available: u64) -> Result<(), OpError> {
+    if available < required {
+        return Err(OpError::new(
+            &format!(
+                "not enough disk space, {} MB required, {} MB available",
+                required / 1024 / 1024,
+                available / 1024 / 1024
+            ),
+            false,
+        ));
+    }
+    Ok(())
+}
+
+/// Creates and removes a probe file, which catches read only directories and missing permissions alike
+fn check_write_access(dir: &Path) -> Result<(), OpError> {
+    let probe = dir.join(format!(".adm-preflight-{}", std::process::id()));
+    fs::write(&probe, b"")
+        .and_then(|_| fs::remove_file(&probe))
+        .map_err(|e| OpError::new(&format!("no write access to {}: {}", dir.display(), e), false))
+}
+
+/// The backup directory usually does not exist yet, its closest existing parent is checked instead
+fn existing_ancestor(path: &Path) -> &Path {
+    path.ancestors().find(|p| p.exists()).unwrap_or(path)
+}
+
+/// Returns the root of the volume the path is on, honouring mounted folders
+fn volume_of(path: &Path) -> Result<PathBuf, OpError> {
+    let path_h = HSTRING::from(path.as_os_str());
+    let mut buffer = [0u16; 261];
+    unsafe { GetVolumePathNameW(&path_h, &mut buffer) }
+        .map_err(|e| OpError::new(&format!("could not determine volume of {}: {}", path.display(), e), false))?;
+    let len = buffer.iter().position(|c| *c == 0).unwrap_or(buffer.len());
+    Ok(PathBuf::from(String::from_utf16_lossy(&buffer[..len])))
+}
+
+fn free_space(path: &Path) -> Result<u64, OpError> {
+    let path_h = HSTRING::from(path.as_os_str());
+    let mut available = 0u64;
+    unsafe { GetDiskFreeSpaceExW(&path_h, Some(&mut available as *mut u64), None, None) }
+        .map_err(|e| OpError::new(&format!("could not determine free space on {}: {}", path.display(), e), false))?;
+    Ok(available)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::test_dir;
+
+    #[test]
+    fn checks_payload_and_space() {
+        let dir = test_dir("preflight-payload");
+        let payload = dir.join("unpacked").join(extensions::APP_DIR);
+        fs::create_dir_all(payload.join("core")).unwrap();
+        assert!(check_payload_present(&payload).is_err());
+        fs::write(payload.join("core").join(extensions::SERVICE_EXE), [0u8; 1000]).unwrap();
+        fs::write(payload.join("ui.dll"), [0u8; 24]).unwrap();
+        assert_eq!(check_payload_present(&payload).unwrap(), 1024);
+
+        assert!(check_disk_space(1024, 1024).is_ok());
+        assert!(check_disk_space(1025, 1024).is_err());
+        assert!(check_write_access(&dir).is_ok());
+        assert!(check_write_access(&dir.join("missing")).is_err());
+        assert_eq!(existing_ancestor(&dir.join("tmp").join("nested")), dir.as_path());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn passes_for_a_prepared_installation() {
+        let dir = test_dir("preflight-run");
+        let update_data_dir = dir.join("adm-update-data");
+        let payload = update_data_dir.join("unpacked").join(extensions::APP_DIR).join("core");
+        fs::create_dir_all(&payload).unwrap();
+        fs::write(payload.join(extensions::SERVICE_EXE), "svc").unwrap();
+        let lock = InstanceLock::acquire(&dir, std::time::Duration::ZERO).unwrap();
+        let paths = PreflightPaths {
+            working_dir: &dir,
+            update_data_dir: &update_data_dir,
+            backup_dir: &update_data_dir.join("tmp"),
+        };
+        assert!(run_preflight(&paths, &lock).unwrap().is_empty());
+
+        fs::write(
+            update_data_dir.join("unpacked").join(crate::manifest::MANIFEST_FILE),
+            r#"{"keep": ["[broken"]}"#,
+        )
+        .unwrap();
+        assert!(run_preflight(&paths, &lock).is_err());
+        drop(lock);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,50 +1,423 @@
-use std::{ffi::OsStr, path::PathBuf};
+use std::{ffi::OsStr, fmt, path::Path};
 
+use log::{debug, info, warn};
+use walkdir::WalkDir;
 use windows_permissions::wrappers::LookupAccountName;
 use winreg::{
-    enums::{HKEY_USERS, KEY_SET_VALUE},
+    enums::{HKEY_LOCAL_MACHINE, HKEY_USERS, KEY_READ, KEY_SET_VALUE},
     RegKey,
 };
 
-use crate::OpError;
+use crate::{extensions::get_adm_app_dir, version::Version, OpError};
 
-/// sets the innosetup version string that shows up in windows settings to the patched vesion
-/// ### Returns
-/// An op error with severity true if the updating failed, severity false if the key was not found.
+const UNINSTALL_KEY: &str = "SOFTWARE\\Microsoft\\Windows\\CurrentVersion\\Uninstall\\{470BC918-3740-4A97-9797-8570A7961130}_is1";
+const UNINSTALL_KEY_WOW64: &str =
+    "SOFTWARE\\WOW6432Node\\Microsoft\\Windows\\CurrentVersion\\Uninstall\\{470BC918-3740-4A97-9797-8570A7961130}_is1";
+/// Prefix of the values inno setup keeps for itself in the uninstall entry
+const INNO_VALUE_PREFIX: &str = "Inno Setup: ";
+const INNO_APP_VERSION: &str = "Inno Setup: App Version";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RegistryRoot {
+    Users,
+    LocalMachine,
+}
+
+/// The few registry operations needed to maintain the uninstall entry
+pub trait RegistryBackend {
+    fn key_exists(&self, root: RegistryRoot, path: &str) -> bool;
+    /// Whether values of the key can be set, keys below HKLM are read only for unelevated processes
+    fn is_writable(&self, root: RegistryRoot, path: &str) -> bool;
+    /// Returns the names of all values of the key, or nothing if the key does not exist
+    fn value_names(&self, root: RegistryRoot, path: &str) -> Vec<String>;
+    /// Returns None if the key or the value does not exist
+    fn get_string(&self, root: RegistryRoot, path: &str, name: &str) -> Option<String>;
+    fn set_string(&mut self, root: RegistryRoot, path: &str, name: &str, value: &str) -> Result<(), OpError>;
+    fn set_dword(&mut self, root: RegistryRoot, path: &str, name: &str, value: u32) -> Result<(), OpError>;
+}
+
+/// The Windows registry, accessed through winreg
+pub struct WinRegistry;
+
+impl WinRegistry {
+    fn open(root: RegistryRoot, path: &str, flags: u32) -> std::io::Result<RegKey> {
+        let hive = match root {
+            RegistryRoot::Users => RegKey::predef(HKEY_USERS),
+            RegistryRoot::LocalMachine => RegKey::predef(HKEY_LOCAL_MACHINE),
+        };
+        hive.open_subkey_with_flags(path, flags)
+    }
+}
+
+impl RegistryBackend for WinRegistry {
+    fn key_exists(&self, root: RegistryRoot, path: &str) -> bool {
+        WinRegistry::open(root, path, KEY_READ).is_ok()
+    }
+
+    fn is_writable(&self, root: RegistryRoot, path: &str) -> bool {
+        WinRegistry::open(root, path, KEY_SET_VALUE).is_ok()
+    }
+
+    fn value_names(&self, root: RegistryRoot, path: &str) -> Vec<String> {
+        match WinRegistry::open(root, path, KEY_READ) {
+            Ok(key) => key.enum_values().filter_map(|v| v.ok()).map(|(name, _)| name).collect(),
+            Err(_) => Vec::new(),
+        }
+    }
+
+    fn get_string(&self, root: RegistryRoot, path: &str, name: &str) -> Option<String> {
+        WinRegistry::open(root, path, KEY_READ).ok()?.get_value(name).ok()
+    }
+
+    fn set_string(&mut self, root: RegistryRoot, path: &str, name: &str, value: &str) -> Result<(), OpError> {
+        WinRegistry::open(root, path, KEY_SET_VALUE)
+            .and_then(|key| key.set_value(name, &value.to_string()))
+            .map_err(|e| OpError::new(&format!("could not set {}: {}", name, e), true))
+    }
+
+    fn set_dword(&mut self, root: RegistryRoot, path: &str, name: &str, value: u32) -> Result<(), OpError> {
+        WinRegistry::open(root, path, KEY_SET_VALUE)
+            .and_then(|key| key.set_value(name, &value))
+            .map_err(|e| OpError::new(&format!("could not set {}: {}", name, e), true))
+    }
+}
+
+/// Where the installer registered auto dark mode
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InstallScope {
+    PerUser(String),
+    PerMachine,
+    /// Per machine, registered by a 32-bit installer on a 64-bit system
+    PerMachineWow64,
+}
+
+impl InstallScope {
+    fn key(&self) -> (RegistryRoot, String) {
+        match self {
+            InstallScope::PerUser(sid) => (RegistryRoot::Users, format!("{}\\{}", sid, UNINSTALL_KEY)),
+            InstallScope::PerMachine => (RegistryRoot::LocalMachine, UNINSTALL_KEY.to_string()),
+            InstallScope::PerMachineWow64 => (RegistryRoot::LocalMachine, UNINSTALL_KEY_WOW64.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for InstallScope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InstallScope::PerUser(sid) => write!(f, "per user ({})", sid),
+            InstallScope::PerMachine => write!(f, "per machine"),
+            InstallScope::PerMachineWow64 => write!(f, "per machine (wow64)"),
+        }
+    }
+}
+
+/// Values written to the uninstall entry after a patch
+pub struct UninstallInfo<'a> {
+    pub version: &'a Version,
+    pub estimated_size_kb: u32,
+    /// Formatted as YYYYMMDD, like the installer writes it
+    pub install_date: String,
+}
+
+/// Finds the uninstall entry belonging to the installation in the working directory
 ///
-/// It is then assumed that adm is a portable installation, as such no warning or error should be emitted.
-pub fn update_inno_installer_string(username: &str, version_string: &str) -> Result<(), OpError> {
-    let (sid, _, _) = LookupAccountName(Option::<&OsStr>::None, username)
-        .map_err(|e| OpError::new(&format!("could not get user sid: {}", e), true))?;
-    let sid_string = sid.to_string();
-    let hku = RegKey::predef(HKEY_USERS);
-    let path = PathBuf::from(format!(
-        "{}\\SOFTWARE\\Microsoft\\Windows\\CurrentVersion\\Uninstall\\{{470BC918-3740-4A97-9797-8570A7961130}}_is1",
-        sid_string
-    ));
-    let installer_key = hku.open_subkey_with_flags(path, KEY_SET_VALUE).map_err(|e| {
-        OpError::new(
-            &format!("inno installer key not detected, assuming portable adm installation: {}", e),
+/// The per-user entry is preferred, it is only checked if the sid of the user is known.
+/// Entries that record a different install path are skipped, so a second installation of auto dark mode is never touched.
+pub fn detect_install_scope<R: RegistryBackend>(registry: &R, sid: Option<&str>, working_dir: &Path) -> Option<InstallScope> {
+    let mut candidates = Vec::new();
+    if let Some(sid) = sid {
+        candidates.push(InstallScope::PerUser(sid.to_string()));
+    }
+    candidates.push(InstallScope::PerMachine);
+    candidates.push(InstallScope::PerMachineWow64);
+    candidates.into_iter().find(|scope| {
+        let (root, path) = scope.key();
+        if !registry.key_exists(root, &path) {
+            return false;
+        }
+        match registry.get_string(root, &path, "Inno Setup: App Path") {
+            Some(app_path) if !same_path(Path::new(&app_path), working_dir) => {
+                debug!("skipping {} uninstall entry for other installation at {}", scope, app_path);
+                false
+            }
+            _ => true,
+        }
+    })
+}
+
+/// Updates the version, name, size and date of the uninstall entry in the given scope
+///
+/// Besides `Inno Setup: App Version`, every other `Inno Setup: ` value that holds the previous version is updated as well.
+/// ### Returns
+/// An op error with severity false if the entry cannot be written, which happens for per machine entries without elevation.
+pub fn update_uninstall_entry<R: RegistryBackend>(
+    registry: &mut R,
+    scope: &InstallScope,
+    info: &UninstallInfo,
+) -> Result<(), OpError> {
+    let (root, path) = scope.key();
+    if !registry.is_writable(root, &path) {
+        return Err(OpError::new(
+            &format!("no write access to the {} uninstall entry, skipping it", scope),
             false,
-        )
-    })?;
-    installer_key
-        .set_value("DisplayVersion", &version_string.to_string())
-        .map_err(|e| OpError::new(&format!("could not update installer version string: {}", e), true))?;
+        ));
+    }
+    let new_version = info.version.to_string();
+    let old_version = registry.get_string(root, &path, "DisplayVersion");
+    registry.set_string(root, &path, "DisplayVersion", &new_version)?;
+    registry.set_string(root, &path, INNO_APP_VERSION, &new_version)?;
+    if let Some(old_version) = old_version.as_deref().filter(|v| !v.is_empty()) {
+        let inno_values = registry
+            .value_names(root, &path)
+            .into_iter()
+            .filter(|name| name.starts_with(INNO_VALUE_PREFIX) && name != INNO_APP_VERSION);
+        for name in inno_values.collect::<Vec<String>>() {
+            if registry.get_string(root, &path, &name).as_deref() == Some(old_version) {
+                registry.set_string(root, &path, &name, &new_version)?;
+            }
+        }
+    }
 
+    if let (Some(old_version), Some(display_name)) = (old_version, registry.get_string(root, &path, "DisplayName")) {
+        if !old_version.is_empty() && display_name.contains(&old_version) {
+            registry.set_string(root, &path, "DisplayName", &display_name.replace(&old_version, &new_version))?;
+        }
+    }
+    registry.set_dword(root, &path, "MajorVersion", info.version.major)?;
+    registry.set_dword(root, &path, "MinorVersion", info.version.minor)?;
+    registry.set_dword(root, &path, "VersionMajor", info.version.major)?;
+    registry.set_dword(root, &path, "VersionMinor", info.version.minor)?;
+    registry.set_dword(root, &path, "EstimatedSize", info.estimated_size_kb)?;
+    registry.set_string(root, &path, "InstallDate", &info.install_date)?;
     Ok(())
 }
 
+/// Returns the string sid of the given user account
+pub fn lookup_user_sid(username: &str) -> Result<String, OpError> {
+    let (sid, _, _) = LookupAccountName(Option::<&OsStr>::None, username)
+        .map_err(|e| OpError::new(&format!("could not get user sid: {}", e), true))?;
+    Ok(sid.to_string())
+}
+
+/// Updates the innosetup uninstall entry that shows up in windows settings to the patched version
+/// ### Returns
+/// An op error with severity true if the updating failed, severity false if the key was not found or is not writable.
+pub fn update_inno_installer_string(scope: &InstallScope, version: &Version) -> Result<(), OpError> {
+    let mut registry = WinRegistry;
+    let (root, path) = scope.key();
+    if !registry.key_exists(root, &path) {
+        return Err(OpError::new(&format!("{} uninstall entry no longer exists", scope), false));
+    }
+    info!("updating {} uninstall entry", scope);
+    let info = UninstallInfo {
+        version,
+        estimated_size_kb: dir_size_kb(&get_adm_app_dir()),
+        install_date: chrono::Local::now().format("%Y%m%d").to_string(),
+    };
+    update_uninstall_entry(&mut registry, scope, &info)
+}
+
+/// Sums up the size of all files below the given directory in KiB, rounded up
+pub fn dir_size_kb(path: &Path) -> u32 {
+    let bytes: u64 = WalkDir::new(path)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file())
+        .filter_map(|e| match e.metadata() {
+            Ok(m) => Some(m.len()),
+            Err(err) => {
+                warn!("could not read size of {}: {}", e.path().display(), err);
+                None
+            }
+        })
+        .sum();
+    u32::try_from(bytes.div_ceil(1024)).unwrap_or(u32::MAX)
+}
+
+/// Compares paths the way windows does, ignoring case and trailing separators
+fn same_path(a: &Path, b: &Path) -> bool {
+    let normalize = |p: &Path| p.to_string_lossy().replace('/', "\\").trim_end_matches('\\').to_lowercase();
+    normalize(a) == normalize(b)
+}
+
 #[cfg(test)]
 mod tests {
-    use super::update_inno_installer_string;
+    use std::collections::HashMap;
+
+    use super::*;
     use crate::setup_logger;
-    use log::debug;
+
+    #[derive(Debug, Clone, PartialEq)]
+    enum Value {
+        String(String),
+        Dword(u32),
+    }
+
+    /// Keys and values kept in memory, keys must be created before values can be set, like in the real registry
+    #[derive(Default)]
+    struct MemoryRegistry {
+        keys: HashMap<(RegistryRoot, String), HashMap<String, Value>>,
+        read_only: bool,
+    }
+
+    impl MemoryRegistry {
+        fn with_entry(mut self, scope: &InstallScope, values: &[(&str, &str)]) -> Self {
+            let values = values
+                .iter()
+                .map(|(k, v)| (k.to_string(), Value::String(v.to_string())))
+                .collect();
+            self.keys.insert(scope.key(), values);
+            self
+        }
+
+        fn value(&self, scope: &InstallScope, name: &str) -> Option<&Value> {
+            self.keys.get(&scope.key())?.get(name)
+        }
+
+        fn set(&mut self, root: RegistryRoot, path: &str, name: &str, value: Value) -> Result<(), OpError> {
+            let key = self
+                .keys
+                .get_mut(&(root, path.to_string()))
+                .ok_or_else(|| OpError::new("key not found", true))?;
+            key.insert(name.to_string(), value);
+            Ok(())
+        }
+    }
+
+    impl RegistryBackend for MemoryRegistry {
+        fn key_exists(&self, root: RegistryRoot, path: &str) -> bool {
+            self.keys.contains_key(&(root, path.to_string()))
+        }
+
+        fn is_writable(&self, root: RegistryRoot, path: &str) -> bool {
+            !self.read_only && self.key_exists(root, path)
+        }
+
+        fn value_names(&self, root: RegistryRoot, path: &str) -> Vec<String> {
+            self.keys
+                .get(&(root, path.to_string()))
+                .map(|values| values.keys().cloned().collect())
+                .unwrap_or_default()
+        }
+
+        fn get_string(&self, root: RegistryRoot, path: &str, name: &str) -> Option<String> {
+            match self.keys.get(&(root, path.to_string()))?.get(name)? {
+                Value::String(s) => Some(s.clone()),
+                Value::Dword(_) => None,
+            }
+        }
+
+        fn set_string(&mut self, root: RegistryRoot, path: &str, name: &str, value: &str) -> Result<(), OpError> {
+            self.set(root, path, name, Value::String(value.to_string()))
+        }
+
+        fn set_dword(&mut self, root: RegistryRoot, path: &str, name: &str, value: u32) -> Result<(), OpError> {
+            self.set(root, path, name, Value::Dword(value))
+        }
+    }
+
+    const SID: &str = "S-1-5-21-1000";
+
+    #[test]
+    fn detects_scope() {
+        let working_dir = Path::new(r"C:\Users\sam\AppData\Local\Programs\AutoDarkMode");
+        let per_user = InstallScope::PerUser(SID.to_string());
+        let registry = MemoryRegistry::default();
+        assert_eq!(detect_install_scope(&registry, Some(SID), working_dir), None);
+
+        let registry = MemoryRegistry::default().with_entry(&InstallScope::PerMachineWow64, &[]);
+        assert_eq!(
+            detect_install_scope(&registry, Some(SID), working_dir),
+            Some(InstallScope::PerMachineWow64)
+        );
+        assert_eq!(
+            detect_install_scope(&registry, None, working_dir),
+            Some(InstallScope::PerMachineWow64)
+        );
+
+        let registry = MemoryRegistry::default()
+            .with_entry(
+                &InstallScope::PerMachine,
+                &[("Inno Setup: App Path", r"C:\Program Files\AutoDarkMode")],
+            )
+            .with_entry(
+                &per_user,
+                &[("Inno Setup: App Path", r"c:\users\sam\appdata\local\programs\autodarkmode\")],
+            );
+        assert_eq!(detect_install_scope(&registry, Some(SID), working_dir), Some(per_user));
+
+        let registry = MemoryRegistry::default().with_entry(
+            &InstallScope::PerMachine,
+            &[("Inno Setup: App Path", r"C:\Program Files\AutoDarkMode")],
+        );
+        assert_eq!(detect_install_scope(&registry, Some(SID), working_dir), None);
+    }
+
+    #[test]
+    fn updates_uninstall_entry() {
+        let scope = InstallScope::PerMachine;
+        let mut registry = MemoryRegistry::default().with_entry(
+            &scope,
+            &[
+                ("DisplayName", "Auto Dark Mode version 11.0.0.23"),
+                ("DisplayVersion", "11.0.0.23"),
+                ("InstallDate", "20250101"),
+                ("Inno Setup: Setup Version", "6.4.3"),
+                ("Inno Setup: Display Version", "11.0.0.23"),
+            ],
+        );
+        let version: Version = "11.1.0.4".parse().unwrap();
+        let info = UninstallInfo {
+            version: &version,
+            estimated_size_kb: 123456,
+            install_date: "20261019".to_string(),
+        };
+        update_uninstall_entry(&mut registry, &scope, &info).unwrap();
+        let string = |s: &str| Some(Value::String(s.to_string()));
+        assert_eq!(registry.value(&scope, "DisplayVersion").cloned(), string("11.1.0.4"));
+        assert_eq!(
+            registry.value(&scope, "DisplayName").cloned(),
+            string("Auto Dark Mode version 11.1.0.4")
+        );
+        assert_eq!(registry.value(&scope, "InstallDate").cloned(), string("20261019"));
+        assert_eq!(registry.value(&scope, "EstimatedSize").cloned(), Some(Value::Dword(123456)));
+        assert_eq!(registry.value(&scope, "MajorVersion").cloned(), Some(Value::Dword(11)));
+        assert_eq!(registry.value(&scope, "VersionMinor").cloned(), Some(Value::Dword(1)));
+        assert_eq!(registry.value(&scope, "Inno Setup: App Version").cloned(), string("11.1.0.4"));
+        assert_eq!(
+            registry.value(&scope, "Inno Setup: Display Version").cloned(),
+            string("11.1.0.4")
+        );
+        assert_eq!(registry.value(&scope, "Inno Setup: Setup Version").cloned(), string("6.4.3"));
+
+        let mut missing = MemoryRegistry::default();
+        assert!(update_uninstall_entry(&mut missing, &scope, &info).is_err());
+    }
+
+    #[test]
+    fn skips_entries_without_write_access() {
+        let scope = InstallScope::PerMachine;
+        let mut registry = MemoryRegistry::default().with_entry(&scope, &[("DisplayVersion", "11.0.0.23")]);
+        registry.read_only = true;
+        let version: Version = "11.1.0.4".parse().unwrap();
+        let info = UninstallInfo {
+            version: &version,
+            estimated_size_kb: 1,
+            install_date: "20261019".to_string(),
+        };
+        let err = update_uninstall_entry(&mut registry, &scope, &info).unwrap_err();
+        assert!(!err.severe);
+        assert_eq!(
+            registry.value(&scope, "DisplayVersion").cloned(),
+            Some(Value::String("11.0.0.23".to_string()))
+        );
+    }
 
     #[test]
     fn change_version_test() {
         setup_logger().unwrap();
-        match update_inno_installer_string("sam", "10.0.1.10") {
+        let scope = InstallScope::PerUser(lookup_user_sid("sam").unwrap());
+        match update_inno_installer_string(&scope, &"10.0.1.10".parse().unwrap()) {
             Ok(_) => debug!("test passed"),
             Err(e) => debug!("failed to test update inno installer: {}", e),
         }
@@ -0,0 +1,227 @@
+use std::{
+    fmt::Display,
+    fs,
+    path::{Path, PathBuf},
+    time::Instant,
+};
+
+use log::{info, warn};
+use serde::Serialize;
+
+use crate::{logging, OpError, VERSION};
+
+const REPORT_FILE: &str = "updater-report.json";
+/// Exit code rust uses for a process that ends with a panic
+const PANIC_EXIT_CODE: i32 = 101;
+
+/// The steps of an update run, in the order they are executed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Phase {
+    Lock,
+    Extract,
+    Delta,
+    PayloadCheck,
+    Deferral,
+    SelfUpdate,
+    Preflight,
+    Shutdown,
+    Migration,
+    KeepFiles,
+    MoveToTemp,
+    Patch,
+    Registry,
+    Relaunch,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    Success,
+    Failed,
+    Skipped,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PhaseReport {
+    pub phase: Phase,
+    pub outcome: Outcome,
+    pub duration_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RollbackReport {
+    pub success: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+/// Machine readable summary of an updater run, written next to the log file when the run ends
+///
+/// The service and support tooling read it to find out what happened, even if the updater could not reach the service
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UpdateReport {
+    pub session_id: String,
+    pub updater_version: String,
+    pub started_at: String,
+    pub finished_at: Option<String>,
+    pub install_kind: Option<String>,
+    pub layout: Option<String>,
+    pub old_version: Option<String>,
+    pub new_version: Option<String>,
+    pub phases: Vec<PhaseReport>,
+    pub rollback: Option<RollbackReport>,
+    /// Other users whose auto dark mode instances were stopped for the update
+    pub stopped_sessions: Vec<String>,
+    pub exit_code: Option<i32>,
+    #[serde(skip)]
+    path: PathBuf,
+}
+
+impl UpdateReport {
+    pub fn new(path: PathBuf) -> UpdateReport {
+        UpdateReport {
+            session_id: logging::session_id().to_string(),
+            updater_version: VERSION.to_string(),
+            started_at: chrono::Local::now().to_rfc3339(),
+            finished_at: None,
+            install_kind: None,
+            layout: None,
+            old_version: None,
+            new_version: None,
+            phases: Vec::new(),
+            rollback: None,
+            stopped_sessions: Vec::new(),
+            exit_code: None,
+            path,
+        }
+    }
+
+    /// Records the outcome of a phase that was started at the given instant
+    pub fn record<T, E: Display>(&mut self, phase: Phase, started: Instant, result: &Result<T, E>) {
+        let (outcome, message) = match result {
+            Ok(_) => (Outcome::Success, None),
+            Err(e) => (Outcome::Failed, Some(e.to_string())),
+        };
+        self.phases.push(PhaseReport {
+            phase,
+            outcome,
+            duration_ms: started.elapsed().as_millis() as u64,
+            message,
+        });
+    }
+
+    pub fn skip(&mut self, phase: Phase, reason: &str) {
+        self.phases.push(PhaseReport {
+            phase,
+            outcome: Outcome::Skipped,
+            duration_ms: 0,
+            message: Some(reason.to_string()),
+        });
+    }
+
+    pub fn rollback<E: Display>(&mut self, result: &Result<(), E>) {
+        self.rollback = Some(RollbackReport {
+            success: result.is_ok(),
+            message: result.as_ref().err().map(|e| e.to_string()),
+        });
+    }
+
+    /// Stamps the end of the run and writes the report, failing to write it never fails the update
+    pub fn finish(&mut self, exit_code: i32) {
+        self.finished_at = Some(chrono::Local::now().to_rfc3339());
+        self.exit_code = Some(exit_code);
+        match self.write() {
+            Ok(()) => info!("update report written to {}", self.path.display()),
+            Err(e) => warn!("{}", e),
+        }
+    }
+
+    /// Writes to a temporary file first, so readers never see a partially written report
+    fn write(&self) -> Result<(), OpError> {
+        let path = &self.path;
+        let json = serde_json::to_string_pretty(self)
+            .map_err(|e| OpError::new(&format!("could not serialize update report: {}", e), false))?;
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        let tmp = PathBuf::from(tmp);
+        fs::write(&tmp, json)
+            .and_then(|_| fs::rename(&tmp, path))
+            .map_err(|e| OpError::new(&format!("could not write update report {}: {}", path.display(), e), false))
+    }
+}
+
+/// A run that ends without calling finish, through a panic or an early return, still leaves a report behind
+impl Drop for UpdateReport {
+    fn drop(&mut self) {
+        if self.exit_code.is_none() {
+            let panicked = std::thread::panicking();
+            if panicked {
+                warn!("updater panicked, writing update report");
+            }
+            self.finish(if panicked { PANIC_EXIT_CODE } else { 1 });
+        }
+    }
+}
+
+/// The report lives in the same directory as the log file
+pub fn report_path(log_path: &Path) -> PathBuf {
+    log_path.with_file_name(REPORT_FILE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::test_dir;
+
+    #[test]
+    fn writes_phases_rollback_and_exit_code() {
+        let dir = test_dir("report-phases");
+        let path = report_path(&dir.join("updater.log"));
+
+        let mut report = UpdateReport::new(path.clone());
+        report.old_version = Some("11.0.0.23".to_string());
+        report.record(Phase::Shutdown, Instant::now(), &Ok::<(), OpError>(()));
+        report.record(Phase::Patch, Instant::now(), &Err::<(), _>(OpError::new("disk full", true)));
+        report.rollback(&Ok::<(), OpError>(()));
+        report.skip(Phase::Registry, "portable installation");
+        report.finish(1);
+
+        let json: serde_json::Value = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(json["exitCode"], 1);
+        assert_eq!(json["oldVersion"], "11.0.0.23");
+        assert!(json["newVersion"].is_null());
+        assert_eq!(json["phases"][0]["phase"], "shutdown");
+        assert_eq!(json["phases"][1]["outcome"], "failed");
+        assert_eq!(json["phases"][1]["message"], "disk full");
+        assert_eq!(json["phases"][2]["phase"], "registry");
+        assert_eq!(json["phases"][2]["outcome"], "skipped");
+        assert_eq!(json["rollback"]["success"], true);
+        assert!(json["finishedAt"].is_string());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn writes_report_when_unwinding() {
+        let dir = test_dir("report-panic");
+        let path = report_path(&dir.join("updater.log"));
+
+        let report_path = path.clone();
+        let result = std::panic::catch_unwind(move || {
+            let mut report = UpdateReport::new(report_path);
+            report.record(Phase::Shutdown, Instant::now(), &Ok::<(), OpError>(()));
+            panic!("unexpected state");
+        });
+        assert!(result.is_err());
+
+        let json: serde_json::Value = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(json["exitCode"], PANIC_EXIT_CODE);
+        assert_eq!(json["phases"][0]["phase"], "shutdown");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
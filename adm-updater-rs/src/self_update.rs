@@ -0,0 +1,217 @@
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use log::{debug, info, warn};
+
+use crate::{
+    io_v3::{move_dir, RetryPolicy},
+    OpError,
+};
+
+/// Name of the updater directory, both in the installation and in the update payload
+pub const UPDATER_DIR: &str = "adm-updater";
+/// The new updater from the payload is copied here and started to replace the updater directory
+const RELAUNCH_DIR: &str = "adm-updater.relaunch";
+/// The previous updater directory is kept here until the new one is in place
+const PREVIOUS_DIR: &str = "adm-updater.old";
+/// Passed to the relaunched copy, which cannot derive the installation from its own location
+pub const ASSEMBLY_DIR_ARG: &str = "--assembly-dir";
+
+/// Returns the new updater directory shipped with the payload, if there is one
+pub fn pending_updater(unpacked_dir: &Path) -> Option<PathBuf> {
+    let dir = unpacked_dir.join(UPDATER_DIR);
+    dir.is_dir().then_some(dir)
+}
+
+/// Copies the new updater executable from the payload next to the updater directory and starts it with the same
+/// arguments
+///
+/// The copy is told where the real updater directory is, so it resolves the same working directory and can swap
+/// the directory the current process runs from once the current process has exited. The rest of the update then
+/// runs with the new updater.
+pub fn relaunch_from_payload(assembly_dir: &Path, new_updater: &Path, args: &[String]) -> Result<(), OpError> {
+    let relaunch_dir = sibling(assembly_dir, RELAUNCH_DIR)?;
+    let current_exe = env::current_exe().map_err(|e| OpError::new(&format!("could not locate running updater: {}", e), true))?;
+    let exe_name = current_exe
+        .file_name()
+        .ok_or_else(|| OpError::new("running updater has no file name", true))?;
+    let new_exe = new_updater.join(exe_name);
+    if !new_exe.is_file() {
+        return Err(OpError::new(
+            &format!("updater payload does not contain {}", new_exe.display()),
+            true,
+        ));
+    }
+
+    if relaunch_dir.exists() {
+        fs::remove_dir_all(&relaunch_dir)
+            .map_err(|e| OpError::new(&format!("could not remove previous updater copy: {}", e), true))?;
+    }
+    fs::create_dir_all(&relaunch_dir)
+        .map_err(|e| OpError::new(&format!("could not create directory for updater copy: {}", e), true))?;
+    let copy = relaunch_dir.join(exe_name);
+    fs::copy(&new_exe, &copy).map_err(|e| OpError::new(&format!("could not copy new updater: {}", e), true))?;
+
+    info!("relaunching updater from {}", copy.display());
+    Command::new(&copy)
+        .args(args.iter().skip(1))
+        .arg(ASSEMBLY_DIR_ARG)
+        .arg(assembly_dir)
+        .current_dir(&relaunch_dir)
+        .spawn()
+        .map_err(|e| OpError::new(&format!("could not start updater copy: {}", e), true))?;
+    Ok(())
+}
+
+/// Replaces the updater directory with the new one from the payload
+///
+/// The previous directory is moved first, retrying with `policy` until the original process has exited and released
+/// its executable. If the new directory cannot be moved into place, the previous one is restored.
+pub fn swap_updater_dir(assembly_dir: &Path, new_dir: &Path, policy: &RetryPolicy) -> Result<(), OpError> {
+    let previous_dir = sibling(assembly_dir, PREVIOUS_DIR)?;
+    if previous_dir.exists() {
+        fs::remove_dir_all(&previous_dir)
+            .map_err(|e| OpError::new(&format!("could not remove stale previous updater: {}", e), true))?;
+    }
+    move_dir(assembly_dir, &previous_dir, policy)
+        .map_err(|e| OpError::new(&format!("could not move current updater out of the way: {}", e), true))?;
+    if let Err(e) = fs::rename(new_dir, assembly_dir) {
+        let op = OpError::new(&format!("could not move new updater into place: {}", e), true);
+        if let Err(e) = fs::rename(&previous_dir, assembly_dir) {
+            return Err(OpError::new(
+                &format!(
+                    "{}, restoring previous updater failed, the updater needs to be reinstalled: {}",
+                    op, e
+                ),
+                true,
+            ));
+        }
+        return Err(op);
+    }
+    info!("updater replaced");
+    if let Err(e) = fs::remove_dir_all(&previous_dir) {
+        warn!("could not remove previous updater, it will be removed on the next run: {}", e);
+    }
+    Ok(())
+}
+
+/// Removes what an earlier self update left behind, must not be called from the relaunched copy
+pub fn clean_previous_self_update(assembly_dir: &Path) {
+    for name in [RELAUNCH_DIR, PREVIOUS_DIR] {
+        let dir = match sibling(assembly_dir, name) {
+            Ok(dir) => dir,
+            Err(_) => return,
+        };
+        if dir.exists() {
+            debug!("removing leftover {}", dir.display());
+            if let Err(e) = fs::remove_dir_all(&dir) {
+                warn!("could not remove leftover {}: {}", dir.display(), e);
+            }
+        }
+    }
+}
+
+/// Removes the relaunched copy, called at the end of the relaunched run
+///
+/// Windows keeps the running executable locked, so a detached shell removes the copy once this process has exited.
+/// If that fails, the next updater run removes it.
+pub fn remove_relaunch_copy(assembly_dir: &Path) {
+    let dir = match sibling(assembly_dir, RELAUNCH_DIR) {
+        Ok(dir) => dir,
+        Err(_) => return,
+    };
+    debug!("removing updater copy {} after exit", dir.display());
+    if let Err(e) = remove_after_exit(&dir) {
+        warn!("could not remove updater copy, it will be removed on the next run: {}", e);
+    }
+}
+
+#[cfg(windows)]
+fn remove_after_exit(dir: &Path) -> std::io::Result<()> {
+    use std::os::windows::process::CommandExt;
+    const CREATE_NO_WINDOW: u32 = 0x0800_0000;
+    // ping waits for about two seconds, cmd has no sleep that works without a console
+    Command::new("cmd.exe")
+        .raw_arg(format!("/C ping -n 3 127.0.0.1 >nul & rmdir /S /Q \"{}\"", dir.display()))
+        .current_dir(dir.parent().unwrap_or(dir))
+        .creation_flags(CREATE_NO_WINDOW)
+        .spawn()
+        .map(|_| ())
+}
+
+#[cfg(not(windows))]
+fn remove_after_exit(dir: &Path) -> std::io::Result<()> {
+    fs::remove_dir_all(dir)
+}
+
+fn sibling(assembly_dir: &Path, name: &str) -> Result<PathBuf, OpError> {
+    assembly_dir
+        .parent()
+        .map(|parent| parent.join(name))
+        .ok_or_else(|| OpError::new("adm updater must not be in root dir", true))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::test_dir;
+
+    #[test]
+    fn swaps_updater_directory() {
+        let dir = test_dir("self-update-swap");
+        let assembly_dir = dir.join(UPDATER_DIR);
+        let unpacked = dir.join("adm-update-data").join("unpacked");
+        fs::create_dir_all(&assembly_dir).unwrap();
+        fs::write(assembly_dir.join("AutoDarkModeUpdater.exe"), "old").unwrap();
+        assert!(pending_updater(&unpacked).is_none());
+        fs::create_dir_all(unpacked.join(UPDATER_DIR)).unwrap();
+        fs::write(unpacked.join(UPDATER_DIR).join("AutoDarkModeUpdater.exe"), "new").unwrap();
+
+        let new_dir = pending_updater(&unpacked).unwrap();
+        swap_updater_dir(&assembly_dir, &new_dir, &RetryPolicy::default()).unwrap();
+        assert_eq!(
+            fs::read_to_string(assembly_dir.join("AutoDarkModeUpdater.exe")).unwrap(),
+            "new"
+        );
+        assert!(!new_dir.exists());
+        assert!(!dir.join(PREVIOUS_DIR).exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn restores_previous_updater_on_failure() {
+        let dir = test_dir("self-update-restore");
+        let assembly_dir = dir.join(UPDATER_DIR);
+        fs::create_dir_all(&assembly_dir).unwrap();
+        fs::write(assembly_dir.join("AutoDarkModeUpdater.exe"), "old").unwrap();
+        assert!(swap_updater_dir(&assembly_dir, &dir.join("missing"), &RetryPolicy::default()).is_err());
+        assert_eq!(
+            fs::read_to_string(assembly_dir.join("AutoDarkModeUpdater.exe")).unwrap(),
+            "old"
+        );
+
+        fs::create_dir_all(dir.join(RELAUNCH_DIR)).unwrap();
+        fs::create_dir_all(dir.join(PREVIOUS_DIR)).unwrap();
+        clean_previous_self_update(&assembly_dir);
+        assert!(!dir.join(RELAUNCH_DIR).exists());
+        assert!(!dir.join(PREVIOUS_DIR).exists());
+        assert!(assembly_dir.exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn relaunches_the_updater_from_the_payload() {
+        let dir = test_dir("self-update-relaunch");
+        let assembly_dir = dir.join(UPDATER_DIR);
+        let new_updater = dir.join("unpacked").join(UPDATER_DIR);
+        fs::create_dir_all(&assembly_dir).unwrap();
+        fs::create_dir_all(&new_updater).unwrap();
+        // without the executable there is nothing to start, the current updater continues
+        assert!(relaunch_from_payload(&assembly_dir, &new_updater, &[]).is_err());
+        assert!(!dir.join(RELAUNCH_DIR).exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,11 @@
+//! Helpers shared by the unit tests of several modules
+
+use std::{fs, path::PathBuf};
+
+/// Returns an empty directory below the temp directory, unique to the given name and the test process
+pub fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("adm-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
@@ -0,0 +1,232 @@
+use std::{cmp::Ordering, error::Error, fmt, str::FromStr};
+
+use crate::OpError;
+
+/// A four part file version with an optional pre-release tag, e.g. `11.0.0.23` or `11.0.1-beta.2`
+///
+/// Missing components are treated as zero, so `4.0` and `4.0.0.0` are equal.
+/// A pre-release sorts before the release with the same numbers, tags are compared like semver identifiers.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub struct Version {
+    pub major: u32,
+    pub minor: u32,
+    pub build: u32,
+    pub revision: u32,
+    pre_release: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VersionError {
+    pub input: String,
+    pub reason: String,
+}
+
+impl Error for VersionError {}
+
+impl fmt::Display for VersionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid version {:?}: {}", self.input, self.reason)
+    }
+}
+
+impl From<VersionError> for OpError {
+    fn from(e: VersionError) -> Self {
+        OpError::new(&e.to_string(), false)
+    }
+}
+
+impl Version {
+    pub fn new(major: u32, minor: u32, build: u32, revision: u32) -> Version {
+        Version {
+            major,
+            minor,
+            build,
+            revision,
+            pre_release: Vec::new(),
+        }
+    }
+
+    /// Returns the pre-release tag without the leading dash, for example `beta.2`
+    pub fn pre_release(&self) -> Option<String> {
+        if self.pre_release.is_empty() {
+            None
+        } else {
+            Some(self.pre_release.join("."))
+        }
+    }
+
+    fn numbers(&self) -> [u32; 4] {
+        [self.major, self.minor, self.build, self.revision]
+    }
+}
+
+impl FromStr for Version {
+    type Err = VersionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = |reason: &str| VersionError {
+            input: s.to_string(),
+            reason: reason.to_string(),
+        };
+        let (numbers, pre_release) = match s.split_once('-') {
+            Some((numbers, tag)) => (numbers, Some(tag)),
+            None => (s, None),
+        };
+        let parts: Vec<&str> = numbers.split('.').collect();
+        if parts.len() < 2 || parts.len() > 4 {
+            return Err(error("expected two to four numeric components"));
+        }
+        let mut components = [0u32; 4];
+        for (component, part) in components.iter_mut().zip(&parts) {
+            if part.is_empty() || !part.bytes().all(|b| b.is_ascii_digit()) {
+                return Err(error(&format!("component {:?} is not a number", part)));
+            }
+            *component = part
+                .parse()
+                .map_err(|_| error(&format!("component {} is out of range", part)))?;
+        }
+        let pre_release = match pre_release {
+            Some(tag) => {
+                let identifiers: Vec<String> = tag.split('.').map(|i| i.to_string()).collect();
+                if identifiers
+                    .iter()
+                    .any(|i| i.is_empty() || !i.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-'))
+                {
+                    return Err(error(&format!("invalid pre-release tag {:?}", tag)));
+                }
+                identifiers
+            }
+            None => Vec::new(),
+        };
+        Ok(Version {
+            major: components[0],
+            minor: components[1],
+            build: components[2],
+            revision: components[3],
+            pre_release,
+        })
+    }
+}
+
+impl Ord for Version {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.numbers()
+            .cmp(&other.numbers())
+            .then_with(|| match (self.pre_release.is_empty(), other.pre_release.is_empty()) {
+                (true, true) => Ordering::Equal,
+                (true, false) => Ordering::Greater,
+                (false, true) => Ordering::Less,
+                (false, false) => compare_pre_release(&self.pre_release, &other.pre_release),
+            })
+    }
+}
+
+impl PartialOrd for Version {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}.{}", self.major, self.minor, self.build, self.revision)?;
+        if let Some(tag) = self.pre_release() {
+            write!(f, "-{}", tag)?;
+        }
+        Ok(())
+    }
+}
+
+/// Numeric identifiers compare numerically and sort before alphanumeric ones, a shorter tag sorts first on a tie
+fn compare_pre_release(a: &[String], b: &[String]) -> Ordering {
+    for (x, y) in a.iter().zip(b) {
+        let ordering = match (x.parse::<u64>(), y.parse::<u64>()) {
+            (Ok(x), Ok(y)) => x.cmp(&y),
+            (Ok(_), Err(_)) => Ordering::Less,
+            (Err(_), Ok(_)) => Ordering::Greater,
+            (Err(_), Err(_)) => x.cmp(y),
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+    a.len().cmp(&b.len())
+}
+
+/// An inclusive range of versions, mirroring the minUpdaterVersion/maxUpdaterVersion check of the service
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VersionRange {
+    pub min: Version,
+    pub max: Version,
+}
+
+impl VersionRange {
+    pub fn new(min: Version, max: Version) -> VersionRange {
+        VersionRange { min, max }
+    }
+
+    pub fn contains(&self, version: &Version) -> bool {
+        *version >= self.min && *version <= self.max
+    }
+}
+
+impl fmt::Display for VersionRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} - {}", self.min, self.max)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn v(s: &str) -> Version {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parse_versions() {
+        assert_eq!(v("11.0.0.23"), Version::new(11, 0, 0, 23));
+        assert_eq!(v("4.0"), Version::new(4, 0, 0, 0));
+        assert_eq!(v("11.0.1-beta.2").pre_release(), Some("beta.2".to_string()));
+        assert_eq!(v("11.0.1-rc1").to_string(), "11.0.1.0-rc1");
+        for invalid in [
+            "",
+            "11",
+            "1.2.3.4.5",
+            "1..2",
+            "-1.0",
+            "1.a",
+            "1.0-",
+            "1.0-beta..1",
+            "99999999999.0",
+            " 1.0",
+        ] {
+            assert!(invalid.parse::<Version>().is_err(), "{:?} should not parse", invalid);
+        }
+    }
+
+    #[test]
+    fn order_versions() {
+        assert!(v("11.0.0.23") > v("11.0.0.9"));
+        assert!(v("4.10") > v("4.9.9.9"));
+        assert!(v("11.0.1-beta") < v("11.0.1"));
+        assert!(v("11.0.1-beta") < v("11.0.1-rc"));
+        assert!(v("11.0.1-beta.2") < v("11.0.1-beta.10"));
+        assert!(v("11.0.1-beta") < v("11.0.1-beta.1"));
+        assert!(v("11.0.1-rc.1") > v("11.0.0.99"));
+        assert_eq!(v("4.0").cmp(&v("4.0.0.0")), Ordering::Equal);
+    }
+
+    #[test]
+    fn version_ranges() {
+        let range = VersionRange::new(v("4.0"), v("4.99"));
+        assert!(range.contains(&v("4.0.3")));
+        assert!(range.contains(&v("4.99")));
+        assert!(!range.contains(&v("4.99.1")));
+        assert!(!range.contains(&v("3.9")));
+        assert!(!range.contains(&v("4.0-beta")));
+        assert!(!range.contains(&v("5.0")));
+        assert!(range.contains(&v(crate::VERSION)));
+    }
+}
@@ -0,0 +1,200 @@
+use std::{
+    fs,
+    path::{Component, Path},
+};
+
+use globset::{GlobBuilder, GlobMatcher};
+
+use crate::OpError;
+
+/// Rules appended after the whitelist file, so installer files can never be picked up by the file collector
+const DEFAULT_RULES: &str = "\
+!unins000.*
+!AutoDarkMode.VisualElementsManifest.xml
+";
+
+/// A single whitelist line compiled into a glob
+#[derive(Debug, Clone)]
+struct Rule {
+    matcher: GlobMatcher,
+    negated: bool,
+    dir_only: bool,
+    /// Rules without a slash match the file name at any depth, all others the path relative to the root
+    anchored: bool,
+}
+
+/// A compiled whitelist
+///
+/// Every non-empty line that does not start with `#` is a pattern, matched case-insensitively:
+/// - `AutoDarkMode` entries without glob characters keep the legacy behavior and match every file name starting with them
+/// - `*.dll`, `Auto?ark*.exe` or `{a,b}.json` are globs matched against the file name at any depth
+/// - `core/*.dll` or `/adm-app` contain a slash and are matched against the path relative to the root
+/// - `runtimes/` with a trailing slash only matches directories, files below a matched directory are matched as well
+/// - `!pattern` excludes what the pattern matches
+///
+/// The last matching rule wins, paths no rule matches are not whitelisted.
+/// Nothing below an excluded directory can be included again.
+#[derive(Debug, Clone, Default)]
+pub struct Whitelist {
+    rules: Vec<Rule>,
+}
+
+impl Whitelist {
+    /// Reads and compiles the whitelist file at the given path, followed by the default exclusions
+    pub fn load(path: &Path) -> Result<Whitelist, OpError> {
+        let content = fs::read_to_string(path)
+            .map_err(|e| OpError::new(&format!("failed to read whitelist file {}: {}", path.display(), e), true))?;
+        Whitelist::parse(&format!("{}\n{}", content, DEFAULT_RULES))
+    }
+
+    pub fn parse(content: &str) -> Result<Whitelist, OpError> {
+        let mut rules = Vec::new();
+        for (i, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let rule = parse_rule(line)
+                .map_err(|e| OpError::new(&format!("invalid whitelist entry {:?} on line {}: {}", line, i + 1, e), true))?;
+            rules.push(rule);
+        }
+        Ok(Whitelist { rules })
+    }
+
+    /// Checks a single entry without compiling a whitelist, comments and empty lines are valid
+    pub fn check_entry(line: &str) -> Result<(), globset::Error> {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            return Ok(());
+        }
+        parse_rule(line).map(|_| ())
+    }
+
+    /// Checks whether a path relative to the root is whitelisted
+    ///
+    /// Parent directories are evaluated first, a file below an included directory is whitelisted unless a rule
+    /// excludes it, a file below an excluded directory never is
+    pub fn is_match(&self, relative: &Path, is_dir: bool) -> bool {
+        let components: Vec<String> = relative
+            .components()
+            .filter_map(|c| match c {
+                Component::Normal(part) => Some(part.to_string_lossy().to_string()),
+                _ => None,
+            })
+            .collect();
+        let mut matched = false;
+        for depth in 1..=components.len() {
+            let path = components[..depth].join("/");
+            let name = &components[depth - 1];
+            let entry_is_dir = depth < components.len() || is_dir;
+            if let Some(rule) = self.rules.iter().rev().find(|r| r.matches(&path, name, entry_is_dir)) {
+                if rule.negated {
+                    // like with gitignore, nothing below an excluded directory can be included again
+                    return false;
+                }
+                matched = true;
+            }
+        }
+        matched
+    }
+}
+
+impl Rule {
+    fn matches(&self, path: &str, name: &str, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        if self.anchored {
+            self.matcher.is_match(path)
+        } else {
+            self.matcher.is_match(name)
+        }
+    }
+}
+
+fn parse_rule(line: &str) -> Result<Rule, globset::Error> {
+    let (negated, pattern) = match line.strip_prefix('!') {
+        Some(rest) => (true, rest.trim_start()),
+        None => (false, line),
+    };
+    let pattern = pattern.replace('\\', "/");
+    let dir_only = pattern.ends_with('/');
+    let pattern = pattern.trim_end_matches('/');
+    let anchored = pattern.contains('/');
+    let pattern = pattern.trim_start_matches('/');
+    let is_glob = pattern.contains(['*', '?', '[', '{']);
+    let pattern = if !is_glob && !anchored && !dir_only {
+        format!("{}*", globset::escape(pattern))
+    } else {
+        pattern.to_string()
+    };
+    let matcher = GlobBuilder::new(&pattern)
+        .case_insensitive(true)
+        .literal_separator(true)
+        .build()?
+        .compile_matcher();
+    Ok(Rule {
+        matcher,
+        negated,
+        dir_only,
+        anchored,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(whitelist: &Whitelist, path: &str) -> bool {
+        whitelist.is_match(Path::new(path), false)
+    }
+
+    #[test]
+    fn legacy_prefix_entries() {
+        let whitelist = Whitelist::parse("AutoDarkMode\n# comment\n\nmicrosoft.").unwrap();
+        assert!(matches(&whitelist, "AutoDarkModeSvc.exe"));
+        assert!(matches(&whitelist, "autodarkmodeapp.dll"));
+        assert!(matches(&whitelist, "Microsoft.Win32.TaskScheduler.dll"));
+        assert!(!matches(&whitelist, "# comment"));
+        assert!(!matches(&whitelist, "config.yaml"));
+    }
+
+    #[test]
+    fn globs_paths_and_directories() {
+        let whitelist = Whitelist::parse("*.dll\ncore/*.json\nruntimes/\n/ui").unwrap();
+        assert!(matches(&whitelist, "some.dll"));
+        assert!(matches(&whitelist, "nested/deeper/some.DLL"));
+        assert!(matches(&whitelist, "core/AutoDarkModeSvc.deps.json"));
+        assert!(!matches(&whitelist, "core/nested/AutoDarkModeSvc.deps.json"));
+        assert!(!matches(&whitelist, "AutoDarkModeSvc.deps.json"));
+        assert!(whitelist.is_match(Path::new("runtimes"), true));
+        assert!(!matches(&whitelist, "runtimes"));
+        assert!(matches(&whitelist, "runtimes/win-x64/native/WebView2Loader.exe"));
+        assert!(matches(&whitelist, "ui"));
+        assert!(!matches(&whitelist, "core/ui"));
+    }
+
+    #[test]
+    fn negations_last_match_wins() {
+        let whitelist = Whitelist::parse(&format!(
+            "*\n!*.log\nupdater.log\nruntimes/\n!runtimes/arm64/\n{}",
+            DEFAULT_RULES
+        ))
+        .unwrap();
+        assert!(matches(&whitelist, "AutoDarkModeSvc.exe"));
+        assert!(!matches(&whitelist, "service.log"));
+        assert!(matches(&whitelist, "updater.log"));
+        assert!(matches(&whitelist, "runtimes/x64/a.dll"));
+        assert!(!matches(&whitelist, "runtimes/arm64/a.dll"));
+        assert!(!matches(&whitelist, "unins000.exe"));
+        assert!(!matches(&whitelist, "unins000.dat"));
+        assert!(!matches(&whitelist, "AutoDarkMode.VisualElementsManifest.xml"));
+    }
+
+    #[test]
+    fn invalid_or_missing_whitelist_is_an_error() {
+        let error = Whitelist::parse("*.dll\ncore/[a-").unwrap_err();
+        assert!(error.message.contains("line 2"));
+        assert!(Whitelist::load(Path::new("this/whitelist/does/not/exist.txt")).is_err());
+    }
+}
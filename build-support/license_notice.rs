@@ -0,0 +1,369 @@
+//! Generates the third party license notice from the resolved dependency graph.
+//!
+//! Shared by the build scripts of the updater and the downloader. `cargo tree` lists the packages that are compiled
+//! into the binary for the target being built, only normal dependencies are followed, so build tools, dev dependencies
+//! and crates for other platforms are not listed. Their license fields come from `cargo metadata` and their license
+//! files are read from the unpacked sources cargo compiles them from.
+//! The notice is written to `OUT_DIR` as `licenses.txt` and `licenses.html` and embedded with `include_str!`, the html
+//! notice is also copied next to the binary for the installer.
+//! A package without sources or license, or with a license outside the policy, fails the build.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    env, fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use serde_json::Value;
+
+/// License expressions the project accepts, read from `about.toml` if the crate has one
+const POLICY_FILE: &str = "about.toml";
+const LICENSE_FILE_PREFIXES: [&str; 4] = ["license", "licence", "copying", "unlicense"];
+
+#[derive(Debug, Clone, Default)]
+pub struct PackageInfo {
+    pub name: String,
+    pub version: String,
+    pub license: Option<String>,
+    pub authors: Vec<String>,
+    pub repository: Option<String>,
+    pub copyright: Option<String>,
+    pub license_texts: Vec<(String, String)>,
+}
+
+/// Writes the notice for the crate being built, called from build.rs
+pub fn generate() {
+    let manifest_dir = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap());
+    let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+    let root = env::var("CARGO_PKG_NAME").unwrap();
+    println!("cargo:rerun-if-changed=Cargo.lock");
+    println!("cargo:rerun-if-changed=Cargo.toml");
+    println!("cargo:rerun-if-changed={}", POLICY_FILE);
+
+    let mut errors = Vec::new();
+    let mut infos = Vec::new();
+    for package in shipped_packages(&manifest_dir, &env::var("TARGET").unwrap()) {
+        match read_package_info(&package) {
+            Ok(info) => infos.push(info),
+            Err(e) => errors.push(e),
+        }
+    }
+    if infos.is_empty() && errors.is_empty() {
+        errors.push(format!("no dependencies of {} found, the notice would be empty", root));
+    }
+
+    let policy = manifest_dir.join(POLICY_FILE);
+    let accepted = match fs::read_to_string(&policy) {
+        Ok(p) => parse_string_array(&p, "accepted"),
+        Err(e) => panic!("could not read license policy {}: {}", policy.display(), e),
+    };
+    for info in &infos {
+        let license = info.license.as_deref().unwrap_or_default();
+        if !is_accepted(license, &accepted) {
+            errors.push(format!(
+                "{} {} uses license {} which is not accepted in {}",
+                info.name, info.version, license, POLICY_FILE
+            ));
+        }
+    }
+    if !errors.is_empty() {
+        panic!("could not generate the license notice:\n{}", errors.join("\n"));
+    }
+
+    let html = render_html(&root, &infos);
+    fs::write(out_dir.join("licenses.txt"), render_text(&infos)).unwrap();
+    fs::write(out_dir.join("licenses.html"), &html).unwrap();
+    // OUT_DIR is target/[<triple>/]<profile>/build/<package>-<hash>/out
+    if let Some(profile_dir) = out_dir.ancestors().nth(3) {
+        fs::write(profile_dir.join("licenses.html"), &html).unwrap();
+    }
+}
+
+/// Runs cargo with the given arguments for the crate being built and returns its output
+fn cargo(manifest_dir: &Path, args: &[&str]) -> Vec<u8> {
+    let cargo = env::var("CARGO").unwrap_or_else(|_| "cargo".to_string());
+    let output = Command::new(cargo)
+        .args(args)
+        .args(["--locked", "--manifest-path"])
+        .arg(manifest_dir.join("Cargo.toml"))
+        .output()
+        .unwrap_or_else(|e| panic!("could not run cargo {}: {}", args[0], e));
+    if !output.status.success() {
+        panic!("cargo {} failed: {}", args[0], String::from_utf8_lossy(&output.stderr).trim());
+    }
+    output.stdout
+}
+
+/// Looks up the registry packages compiled into the binary for the target, sorted by name and version
+///
+/// `cargo tree` applies the feature resolver, so optional dependencies that are not enabled are left out, and
+/// `cargo metadata` provides their manifest path and license fields.
+fn shipped_packages(manifest_dir: &Path, target: &str) -> Vec<Value> {
+    let tree = cargo(
+        manifest_dir,
+        &[
+            "tree", "--edges", "normal", "--target", target, "--prefix", "none", "--format", "{p}",
+        ],
+    );
+    let shipped = parse_tree(&String::from_utf8_lossy(&tree));
+    let metadata = cargo(
+        manifest_dir,
+        &["metadata", "--format-version", "1", "--filter-platform", target],
+    );
+    let metadata: Value = serde_json::from_slice(&metadata).unwrap_or_else(|e| panic!("invalid cargo metadata output: {}", e));
+
+    let mut packages: Vec<Value> = metadata["packages"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter(|p| !p["source"].is_null())
+        .filter(|p| {
+            let name = p["name"].as_str().unwrap_or_default().to_string();
+            let version = p["version"].as_str().unwrap_or_default().to_string();
+            shipped.contains(&(name, version))
+        })
+        .cloned()
+        .collect();
+    packages.sort_by(|a, b| (a["name"].as_str(), a["version"].as_str()).cmp(&(b["name"].as_str(), b["version"].as_str())));
+    packages
+}
+
+/// Reads the name and version of every package listed by `cargo tree --prefix none --format {p}`
+///
+/// Lines look like `serde v1.0.219`, followed by ` (proc-macro)`, ` (*)` or the path of local packages
+pub fn parse_tree(output: &str) -> BTreeSet<(String, String)> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let mut parts = line.split_whitespace();
+            let name = parts.next()?;
+            let version = parts.next()?.strip_prefix('v')?;
+            Some((name.to_string(), version.to_string()))
+        })
+        .collect()
+}
+
+/// Reads license metadata and license files from the unpacked sources of a package
+pub fn read_package_info(package: &Value) -> Result<PackageInfo, String> {
+    let name = package["name"].as_str().unwrap_or_default().to_string();
+    let version = package["version"].as_str().unwrap_or_default().to_string();
+    let dir = match package["manifest_path"].as_str().map(Path::new).and_then(Path::parent) {
+        Some(dir) if dir.is_dir() => dir,
+        _ => return Err(format!("sources of {} {} not found", name, version)),
+    };
+    let mut license_files: Vec<PathBuf> = match fs::read_dir(dir) {
+        Ok(entries) => entries
+            .filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .filter(|p| p.is_file())
+            .filter(|p| {
+                let name = p.file_name().unwrap_or_default().to_string_lossy().to_lowercase();
+                LICENSE_FILE_PREFIXES.iter().any(|prefix| name.starts_with(prefix))
+            })
+            .collect(),
+        Err(e) => return Err(format!("could not read sources of {} {}: {}", name, version, e)),
+    };
+    if let Some(file) = package["license_file"].as_str() {
+        let path = dir.join(file);
+        if path.is_file() && !license_files.contains(&path) {
+            license_files.push(path);
+        }
+    }
+    license_files.sort();
+
+    let license_texts: Vec<(String, String)> = license_files
+        .iter()
+        .filter_map(|p| {
+            let text = fs::read_to_string(p).ok()?;
+            Some((p.file_name()?.to_string_lossy().to_string(), text))
+        })
+        .collect();
+    let license = package["license"].as_str().map(str::to_string);
+    if license.is_none() {
+        return Err(format!("{} {} does not declare a license", name, version));
+    }
+    let copyright = license_texts
+        .iter()
+        .flat_map(|(_, text)| text.lines())
+        .map(str::trim)
+        .find(|l| l.to_lowercase().starts_with("copyright") && l.chars().any(|c| c.is_ascii_digit()))
+        .map(str::to_string);
+
+    Ok(PackageInfo {
+        name,
+        version,
+        license,
+        authors: package["authors"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|a| a.as_str().map(str::to_string))
+            .collect(),
+        repository: package["repository"].as_str().map(str::to_string),
+        copyright,
+        license_texts,
+    })
+}
+
+/// Reads an array of strings, which may span several lines
+fn parse_string_array(section: &str, key: &str) -> Vec<String> {
+    let mut collected = String::new();
+    let mut collecting = false;
+    for line in section.lines() {
+        if !collecting {
+            if let Some((_, v)) = line.split_once('=').filter(|(k, _)| k.trim() == key) {
+                collected.push_str(v.trim().trim_start_matches('['));
+                collecting = true;
+            }
+        } else {
+            collected.push_str(line.trim());
+        }
+        if collecting && collected.contains(']') {
+            break;
+        }
+    }
+    split_string_list(collected.split(']').next().unwrap_or_default())
+}
+
+fn split_string_list(list: &str) -> Vec<String> {
+    list.split(',').filter_map(|item| unquote(item.trim())).collect()
+}
+
+fn unquote(value: &str) -> Option<String> {
+    let value = value.trim();
+    let inner = value
+        .strip_prefix('"')
+        .and_then(|v| v.strip_suffix('"'))
+        .or_else(|| value.strip_prefix('\'').and_then(|v| v.strip_suffix('\'')))?;
+    Some(inner.replace("\\\"", "\"").replace("\\\\", "\\"))
+}
+
+/// Checks an SPDX expression against the accepted licenses
+///
+/// `AND` requires every operand to be accepted, `OR` and the legacy `/` separator require one
+pub fn is_accepted(expression: &str, accepted: &[String]) -> bool {
+    let spaced = expression.replace('(', " ( ").replace(')', " ) ").replace('/', " OR ");
+    let tokens: Vec<&str> = spaced.split_whitespace().collect();
+    let mut pos = 0;
+    let result = eval_or(&tokens, &mut pos, accepted);
+    result && pos == tokens.len()
+}
+
+fn eval_or(tokens: &[&str], pos: &mut usize, accepted: &[String]) -> bool {
+    let mut result = eval_and(tokens, pos, accepted);
+    while tokens.get(*pos) == Some(&"OR") {
+        *pos += 1;
+        result |= eval_and(tokens, pos, accepted);
+    }
+    result
+}
+
+fn eval_and(tokens: &[&str], pos: &mut usize, accepted: &[String]) -> bool {
+    let mut result = eval_term(tokens, pos, accepted);
+    while tokens.get(*pos) == Some(&"AND") {
+        *pos += 1;
+        result &= eval_term(tokens, pos, accepted);
+    }
+    result
+}
+
+fn eval_term(tokens: &[&str], pos: &mut usize, accepted: &[String]) -> bool {
+    let token = match tokens.get(*pos) {
+        Some(t) => *t,
+        None => return false,
+    };
+    *pos += 1;
+    if token == "(" {
+        let result = eval_or(tokens, pos, accepted);
+        if tokens.get(*pos) == Some(&")") {
+            *pos += 1;
+        }
+        return result;
+    }
+    // exceptions like "Apache-2.0 WITH LLVM-exception" only widen the license
+    if tokens.get(*pos) == Some(&"WITH") {
+        *pos += 2;
+    }
+    accepted.iter().any(|a| a == token)
+}
+
+pub fn render_text(infos: &[PackageInfo]) -> String {
+    let mut text = String::new();
+    for info in infos {
+        let holder = info
+            .copyright
+            .clone()
+            .or_else(|| (!info.authors.is_empty()).then(|| format!("Copyright (c) {}", info.authors.join(", "))));
+        text.push_str(&format!("{} {}", info.name, info.version));
+        if let Some(holder) = holder {
+            text.push_str(&format!(" - {}", holder));
+        }
+        text.push_str(&format!(" - {}", info.license.as_deref().unwrap_or("unknown license")));
+        if let Some(repository) = &info.repository {
+            text.push_str(&format!(" - {}", repository));
+        }
+        text.push('\n');
+    }
+    text
+}
+
+pub fn render_html(root: &str, infos: &[PackageInfo]) -> String {
+    let mut by_license: BTreeMap<&str, Vec<&PackageInfo>> = BTreeMap::new();
+    for info in infos {
+        by_license
+            .entry(info.license.as_deref().unwrap_or("unknown"))
+            .or_default()
+            .push(info);
+    }
+
+    let mut html = String::new();
+    html.push_str("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n");
+    html.push_str(&format!("<title>{} third party licenses</title>\n", escape(root)));
+    html.push_str(
+        "<style>\n\
+         @media (prefers-color-scheme: dark) { body { background: #333; color: white; } a { color: skyblue; } }\n\
+         .container { font-family: sans-serif; max-width: 800px; margin: 0 auto; }\n\
+         pre { white-space: pre-wrap; background: rgba(127, 127, 127, 0.15); padding: 10px; }\n\
+         </style>\n</head>\n<body>\n<main class=\"container\">\n",
+    );
+    html.push_str(&format!(
+        "<h1>Third party licenses</h1>\n<p>{} uses the following {} crates.</p>\n",
+        escape(root),
+        infos.len()
+    ));
+
+    html.push_str("<h2>Overview</h2>\n<ul>\n");
+    for (license, packages) in &by_license {
+        html.push_str(&format!("<li>{} ({})</li>\n", escape(license), packages.len()));
+    }
+    html.push_str("</ul>\n");
+
+    for (license, packages) in &by_license {
+        html.push_str(&format!("<h2>{}</h2>\n", escape(license)));
+        for info in packages {
+            let title = format!("{} {}", escape(&info.name), escape(&info.version));
+            let title = match &info.repository {
+                Some(repo) => format!("<a href=\"{}\">{}</a>", escape(repo), title),
+                None => title,
+            };
+            html.push_str(&format!("<details>\n<summary>{}</summary>\n", title));
+            if info.license_texts.is_empty() {
+                html.push_str("<p>No license file is included in the published crate.</p>\n");
+            }
+            for (file, text) in &info.license_texts {
+                html.push_str(&format!("<h4>{}</h4>\n<pre>{}</pre>\n", escape(file), escape(text)));
+            }
+            html.push_str("</details>\n");
+        }
+    }
+    html.push_str("</main>\n</body>\n</html>\n");
+    html
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
@@ -18,7 +18,7 @@ if not exist bin\Publish\x86\adm-updater mkdir bin\Publish\x86\adm-updater
 if not exist bin\Publish\ARM64\adm-updater mkdir bin\Publish\ARM64\adm-updater
 
 copy adm-updater-rs\target\release\adm-updater-rs.exe bin\Publish\x86\adm-updater\AutoDarkModeUpdater.exe
-copy adm-updater-rs\license.html bin\Publish\x86\adm-updater\license.html
+copy adm-updater-rs\target\release\licenses.html bin\Publish\x86\adm-updater\license.html
 
 copy adm-updater-rs\target\aarch64-pc-windows-msvc\release\adm-updater-rs.exe bin\Publish\ARM64\adm-updater\AutoDarkModeUpdater.exe
-copy adm-updater-rs\license.html bin\Publish\ARM64\\adm-updater\license.html
\ No newline at end of file
+copy adm-updater-rs\target\aarch64-pc-windows-msvc\release\licenses.html bin\Publish\ARM64\adm-updater\license.html
\ No newline at end of file